@@ -0,0 +1,144 @@
+//! Measures how long it takes to fan updates out to many `Get` streams on the
+//! same destination.
+//!
+//! As a baseline, the same streams are also served by as many destinations
+//! with the same endpoints, one stream each, so that every stream's updates
+//! are diffed and encoded separately, as they were before streams shared
+//! them.
+//!
+//! Run with `cargo bench --bench fanout`.
+
+use linkerd2_mock_dst::{Dst, EndpointMeta, EndpointsGenerator, Error, MockController};
+use linkerd2_proxy_api::destination::{destination_client::DestinationClient, GetDestination};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// The number of endpoints the destination has.
+///
+/// The baseline holds a copy of the endpoints for each stream, so this is
+/// kept small enough for it to be run with thousands of streams.
+const ENDPOINTS: usize = 1_000;
+
+/// The numbers of streams to benchmark.
+const SUBSCRIBERS: [usize; 5] = [10, 100, 1_000, 2_500, 5_000];
+
+/// The number of streams opened on each connection to the controller.
+const STREAMS_PER_CONNECTION: usize = 100;
+
+/// The number of times an endpoint is replaced for each number of streams.
+const ROUNDS: usize = 20;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    println!(
+        "{:>11} {:>14} {:>14} {:>22} {:>22}",
+        "subscribers",
+        "initial (ms)",
+        "update (ms)",
+        "baseline initial (ms)",
+        "baseline update (ms)"
+    );
+    for &subscribers in SUBSCRIBERS.iter() {
+        let shared = vec![dst(0)];
+        let (initial, update) = bench(&shared, subscribers).await?;
+        let unshared = (0..subscribers).map(dst).collect::<Vec<_>>();
+        let (baseline_initial, baseline_update) = bench(&unshared, subscribers).await?;
+        println!(
+            "{:>11} {:>14.2} {:>14.2} {:>22.2} {:>22.2}",
+            subscribers,
+            initial.as_secs_f64() * 1000.0,
+            update.as_secs_f64() * 1000.0,
+            baseline_initial.as_secs_f64() * 1000.0,
+            baseline_update.as_secs_f64() * 1000.0,
+        );
+    }
+    Ok(())
+}
+
+fn dst(i: usize) -> Dst {
+    Dst::new(format!("fanout-{}.test.svc.cluster.local", i), 8080)
+}
+
+/// Returns how long it takes for `subscribers` streams, spread evenly across
+/// `dsts`, to open and receive all of their destination's endpoints, and then
+/// the mean time for all of them to receive an update replacing one
+/// endpoint.
+async fn bench(dsts: &[Dst], subscribers: usize) -> Result<(Duration, Duration), Error> {
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+    let cidr = "10.0.0.0/16".parse().expect("network must be valid");
+    let mut endpoints = EndpointsGenerator::new(cidr, 8080, ENDPOINTS).generate();
+    for dst in dsts {
+        controller
+            .dst()
+            .send_endpoints(dst.clone(), endpoints.clone())
+            .await?;
+    }
+
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+    let uri = format!("http://{}", controller.addr());
+    let start = Instant::now();
+    let mut client = None;
+    for i in 0..subscribers {
+        if i % STREAMS_PER_CONNECTION == 0 {
+            client = Some(DestinationClient::connect(uri.clone()).await?);
+        }
+        let mut client = client.clone().expect("a client must be connected");
+        let req = GetDestination {
+            path: dsts[i % dsts.len()].to_string(),
+            ..Default::default()
+        };
+        let mut updates = client.get(req).await?.into_inner();
+        let received_tx = received_tx.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(_)) = updates.message().await {
+                if received_tx.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    // Each stream is sent a single `Add` with all of the endpoints.
+    receive(&mut received_rx, subscribers).await;
+    let initial = start.elapsed();
+
+    let mut total = Duration::default();
+    for round in 0..ROUNDS {
+        let removed = *endpoints
+            .0
+            .keys()
+            .next()
+            .expect("endpoints must not be empty");
+        endpoints.0.remove(&removed);
+        let added = SocketAddr::from(([10, 1, 0, round as u8], 8080));
+        let meta = EndpointMeta::new(added, false, 10_000, BTreeMap::new(), None, None);
+        endpoints.0.insert(added, meta);
+
+        let start = Instant::now();
+        for dst in dsts {
+            controller
+                .dst()
+                .send_endpoints(dst.clone(), endpoints.clone())
+                .await?;
+        }
+        // Each stream is sent an `Add` and a `Remove`.
+        receive(&mut received_rx, subscribers * 2).await;
+        total += start.elapsed();
+    }
+
+    controller.shutdown().await?;
+    Ok((initial, total / ROUNDS as u32))
+}
+
+async fn receive(received: &mut mpsc::UnboundedReceiver<()>, n: usize) {
+    for _ in 0..n {
+        received
+            .recv()
+            .await
+            .expect("streams must not end while benchmarking");
+    }
+}
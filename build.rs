@@ -0,0 +1,48 @@
+use std::{env, path::PathBuf, process::Command};
+
+/// Protos compiled for the services served here and described over
+/// reflection. The destination and identity services are served from the
+/// API crate, so only their descriptors are used.
+const PROTOS: &[&str] = &[
+    "proto/grpc/health/v1/health.proto",
+    "proto/grpc/reflection/v1alpha/reflection.proto",
+    "proto/linkerd/destination.proto",
+    "proto/linkerd/identity.proto",
+    "proto/linkerd/inbound.proto",
+    "proto/linkerd/outbound.proto",
+];
+
+/// Linkerd protos are vendored under `proto/linkerd` and import each other
+/// relative to it, as upstream.
+const INCLUDES: &[&str] = &["proto/linkerd", "proto"];
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Clients are only used by tests.
+    tonic_build::configure()
+        .build_client(true)
+        .compile(PROTOS, INCLUDES)?;
+
+    // The reflection service serves the descriptors of these services, too.
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").ok_or("OUT_DIR is not set")?);
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg(format!(
+            "--descriptor_set_out={}",
+            out_dir.join("descriptors.bin").display()
+        ))
+        .args(INCLUDES.iter().map(|i| format!("--proto_path={}", i)))
+        .arg(format!(
+            "--proto_path={}",
+            prost_build::protoc_include().display()
+        ))
+        .args(PROTOS)
+        .status()?;
+    if !status.success() {
+        return Err(format!("protoc failed: {}", status).into());
+    }
+
+    for include in INCLUDES {
+        println!("cargo:rerun-if-changed={}", include);
+    }
+    Ok(())
+}
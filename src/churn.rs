@@ -0,0 +1,320 @@
+use crate::{
+    generate::{capacity, host_addr},
+    ChurnSpec, Cidr, Dst, DstSender, EndpointMeta, Endpoints, Error, Weight,
+};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
+
+/// How many times to pick a random address for a new endpoint before giving
+/// up on finding one that is not already in use.
+const MAX_ADDR_ATTEMPTS: usize = 16;
+
+/// How a destination's endpoint set changes over time.
+///
+/// Churn is parsed from specs like
+/// `churn(cidr=10.0.0.0/16,port=8080,add=10,remove=10,reweight=50)`, and is
+/// deterministic for a given `seed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Churn {
+    pub(crate) cidr: Cidr,
+    pub(crate) port: u16,
+    /// Endpoints added per second.
+    pub(crate) add: f64,
+    /// Endpoints removed per second.
+    pub(crate) remove: f64,
+    /// Endpoints reweighted per second.
+    pub(crate) reweight: f64,
+    pub(crate) weight: Weight,
+    /// The probability that each added endpoint supports h2 upgrading.
+    pub(crate) h2: f64,
+    pub(crate) seed: u64,
+}
+
+/// Continuously churns the endpoints of destinations.
+#[derive(Debug)]
+pub struct EndpointsChurner {
+    dsts: Vec<Churning>,
+    interval: Duration,
+}
+
+#[derive(Debug)]
+struct Churning {
+    dst: Dst,
+    churn: Churn,
+    rng: StdRng,
+    adds: Owed,
+    removes: Owed,
+    reweights: Owed,
+}
+
+/// Accumulates fractional changes between ticks, so that rates that are not
+/// multiples of the tick rate are honored over time.
+#[derive(Debug, Default)]
+struct Owed(f64);
+
+// === impl Churn ===
+
+impl Churn {
+    /// Adds endpoints with addresses in `cidr` and the given `port`, but
+    /// does not add, remove or reweight any endpoints until configured to.
+    pub fn new(cidr: Cidr, port: u16) -> Self {
+        Self {
+            cidr,
+            port,
+            add: 0.0,
+            remove: 0.0,
+            reweight: 0.0,
+            weight: Weight::Fixed(10_000),
+            h2: 0.0,
+            seed: 0,
+        }
+    }
+
+    pub fn add(self, add: f64) -> Self {
+        Self { add, ..self }
+    }
+
+    pub fn remove(self, remove: f64) -> Self {
+        Self { remove, ..self }
+    }
+
+    pub fn reweight(self, reweight: f64) -> Self {
+        Self { reweight, ..self }
+    }
+
+    /// Sets the weights of added and reweighted endpoints.
+    pub fn weight(self, weight: Weight) -> Self {
+        Self { weight, ..self }
+    }
+
+    /// Sets the probability, between 0 and 1, that each added endpoint
+    /// supports h2 upgrading.
+    pub fn h2(self, h2: f64) -> Self {
+        Self { h2, ..self }
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+}
+
+// === impl EndpointsChurner ===
+
+impl EndpointsChurner {
+    /// Churns each destination in `spec` every `interval`.
+    pub fn new(spec: ChurnSpec, interval: Duration) -> Self {
+        let dsts = spec
+            .dsts
+            .into_iter()
+            .map(|(dst, churn)| Churning {
+                rng: StdRng::seed_from_u64(churn.seed),
+                dst,
+                churn,
+                adds: Owed::default(),
+                removes: Owed::default(),
+                reweights: Owed::default(),
+            })
+            .collect();
+        Self { dsts, interval }
+    }
+
+    /// Churns the endpoints served by `sender` until an update fails to be
+    /// sent.
+    ///
+    /// Each tick starts from the endpoints that are currently served, so
+    /// churn may be combined with other changes to the same destinations.
+    pub async fn run(mut self, sender: &mut DstSender) -> Result<(), Error> {
+        let mut ticks = tokio::time::interval(self.interval);
+        // The first tick completes immediately.
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            for churning in &mut self.dsts {
+                let mut endpoints = sender.endpoints(&churning.dst).await.unwrap_or_default();
+                churning.step(&mut endpoints, self.interval);
+                sender
+                    .send_endpoints(churning.dst.clone(), endpoints)
+                    .await?;
+            }
+        }
+    }
+}
+
+// === impl Churning ===
+
+impl Churning {
+    /// Applies the churn owed for `elapsed`: removing endpoints first, then
+    /// reweighting the remaining ones, and then adding new ones.
+    fn step(&mut self, endpoints: &mut Endpoints, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let removes = self.removes.take(self.churn.remove * secs);
+        let reweights = self.reweights.take(self.churn.reweight * secs);
+        let adds = self.adds.take(self.churn.add * secs);
+        let weights = match self.churn.weight {
+            Weight::Fixed(weight) => Uniform::new_inclusive(weight, weight),
+            Weight::Uniform(min, max) => Uniform::new_inclusive(min, max),
+        };
+
+        // Endpoints are chosen from a sorted list of addresses, since the
+        // order of a `HashMap` is not deterministic.
+        let addrs = sorted_addrs(endpoints);
+        let removes = removes.min(addrs.len());
+        for i in rand::seq::index::sample(&mut self.rng, addrs.len(), removes).into_iter() {
+            endpoints.0.remove(&addrs[i]);
+        }
+
+        let addrs = sorted_addrs(endpoints);
+        let reweights = reweights.min(addrs.len());
+        for i in rand::seq::index::sample(&mut self.rng, addrs.len(), reweights).into_iter() {
+            if let Some(meta) = endpoints.0.get_mut(&addrs[i]) {
+                meta.set_weight(self.rng.sample(&weights));
+            }
+        }
+
+        let capacity = capacity(&self.churn.cidr);
+        let adds = if capacity == 0 { 0 } else { adds };
+        let mut added = 0;
+        'adds: for _ in 0..adds {
+            for _ in 0..MAX_ADDR_ATTEMPTS {
+                let host = self.rng.gen_range(0, capacity) + 1;
+                let addr = SocketAddr::new(host_addr(&self.churn.cidr, host), self.churn.port);
+                if !endpoints.0.contains_key(&addr) {
+                    let h2 = self.rng.gen_bool(self.churn.h2);
+                    let weight = self.rng.sample(&weights);
+                    let meta = EndpointMeta::new(addr, h2, weight, BTreeMap::default(), None, None);
+                    endpoints.0.insert(addr, meta);
+                    added += 1;
+                    continue 'adds;
+                }
+            }
+            tracing::debug!(cidr = ?self.churn.cidr, "No free addresses to add");
+            break;
+        }
+
+        tracing::debug!(
+            dst = %self.dst,
+            removed = removes,
+            reweighted = reweights,
+            added,
+            endpoints = endpoints.0.len(),
+            "Churned"
+        );
+    }
+}
+
+fn sorted_addrs(endpoints: &Endpoints) -> Vec<SocketAddr> {
+    let mut addrs = endpoints.0.keys().cloned().collect::<Vec<_>>();
+    addrs.sort();
+    addrs
+}
+
+// === impl Owed ===
+
+impl Owed {
+    /// Adds `amount` to what is owed, and takes the whole part.
+    fn take(&mut self, amount: f64) -> usize {
+        self.0 += amount;
+        let whole = self.0.floor();
+        self.0 -= whole;
+        whole as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn churning(churn: &str) -> Churning {
+        let spec = format!("foo.ns.svc.cluster.local:8080={}", churn)
+            .parse::<ChurnSpec>()
+            .unwrap();
+        EndpointsChurner::new(spec, Duration::from_secs(1))
+            .dsts
+            .pop()
+            .unwrap()
+    }
+
+    fn churn(churn: &str, endpoints: &mut Endpoints, ticks: usize, interval: Duration) {
+        let mut churning = churning(churn);
+        for _ in 0..ticks {
+            churning.step(endpoints, interval);
+        }
+    }
+
+    #[test]
+    fn owes_fractional_changes_to_later_ticks() {
+        let mut owed = Owed::default();
+        let taken = (0..10).map(|_| owed.take(0.25)).collect::<Vec<_>>();
+        assert_eq!(taken, [0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(owed.take(2.5), 3);
+    }
+
+    #[test]
+    fn adds_and_removes_endpoints_at_their_rates() {
+        let mut endpoints = Endpoints::default();
+        let spec = "churn(cidr=10.0.0.0/16,port=8080,add=10,weight=uniform(1,100),seed=1)";
+        churn(spec, &mut endpoints, 10, Duration::from_millis(100));
+        assert_eq!(endpoints.0.len(), 10);
+        assert!(endpoints.0.keys().all(|addr| addr.port() == 8080));
+
+        let spec = "churn(cidr=10.0.0.0/16,port=8080,add=5,remove=8,seed=1)";
+        churn(spec, &mut endpoints, 1, Duration::from_secs(1));
+        assert_eq!(endpoints.0.len(), 7);
+
+        // No more endpoints are removed than there are.
+        let spec = "churn(cidr=10.0.0.0/16,port=8080,remove=100,seed=1)";
+        churn(spec, &mut endpoints, 1, Duration::from_secs(1));
+        assert!(endpoints.0.is_empty());
+    }
+
+    #[test]
+    fn reweights_endpoints_in_place() {
+        let mut endpoints = Endpoints::default();
+        churn(
+            "churn(cidr=10.0.0.0/16,port=8080,add=20,seed=1)",
+            &mut endpoints,
+            1,
+            Duration::from_secs(1),
+        );
+        let added = endpoints.clone();
+        churn(
+            "churn(cidr=10.0.0.0/16,port=8080,reweight=20,weight=7,seed=1)",
+            &mut endpoints,
+            1,
+            Duration::from_secs(1),
+        );
+        assert_eq!(sorted_addrs(&endpoints), sorted_addrs(&added));
+        assert_ne!(endpoints, added);
+    }
+
+    #[test]
+    fn the_same_seed_churns_the_same_way() {
+        let spec = "churn(cidr=10.0.0.0/16,port=8080,add=10,remove=3,reweight=5,h2=0.5,seed=9)";
+        let (mut a, mut b) = (Endpoints::default(), Endpoints::default());
+        churn(spec, &mut a, 5, Duration::from_secs(1));
+        churn(spec, &mut b, 5, Duration::from_secs(1));
+        assert_eq!(a, b);
+        assert_eq!(a.0.len(), 38);
+    }
+
+    #[test]
+    fn stops_adding_when_the_network_is_full() {
+        let mut endpoints = Endpoints::default();
+        churn(
+            "churn(cidr=10.0.0.0/30,port=80,add=10,seed=1)",
+            &mut endpoints,
+            3,
+            Duration::from_secs(1),
+        );
+        assert_eq!(endpoints.0.len(), 3);
+
+        let mut endpoints = Endpoints::default();
+        churn(
+            "churn(cidr=10.0.0.1/32,port=80,add=10,seed=1)",
+            &mut endpoints,
+            1,
+            Duration::from_secs(1),
+        );
+        assert!(endpoints.0.is_empty());
+    }
+}
@@ -1,53 +1,361 @@
-use crate::{EndpointsSpec, Error, OverridesSpec};
-use futures::prelude::*;
-use linkerd2_proxy_api::destination::{self as pb, destination_server::Destination};
+use crate::{
+    listen::Resettable,
+    metrics::{Rpc, UpdateKind},
+    ClientIdentity, DstPattern, EndpointsSpec, EndpointsTemplate, Error, Metrics, Normalization,
+    OverridesSpec, PatternsSpec, ServiceProfile, Upstream,
+};
+use futures::{prelude::*, stream::BoxStream};
+use linkerd2_proxy_api::{destination as pb, net};
+use prost::{
+    bytes::{Buf, BufMut, Bytes},
+    encoding::{DecodeContext, WireType},
+    Message,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
     default::Default,
     fmt,
     hash::Hash,
-    net::SocketAddr,
-    sync::{Arc, Weak},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tokio::sync::{mpsc, watch, RwLock};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{http, BoxFuture, HttpBody, Never, Service, StdError},
+    server::{Grpc, ServerStreamingService},
+    transport::NamedService,
+};
 use tracing_futures::Instrument;
 
 #[derive(Clone, Debug)]
 pub struct DstService {
     inner: Arc<Inner>,
+    metrics: Metrics,
+    upstream: Option<Upstream>,
+    patterns: Arc<Patterns>,
+    normalization: Normalization,
 }
 
-#[derive(Debug)]
+/// Changes the destinations served by a `DstService`.
+///
+/// Clones share the same destinations, so that several sources, like a
+/// watched directory and an endpoints churner, can update them at once.
+#[derive(Clone, Debug)]
 pub struct DstSender {
-    endpoints: HashMap<Dst, watch::Sender<Endpoints>>,
-    overrides: HashMap<Dst, watch::Sender<Overrides>>,
+    senders: Arc<tokio::sync::Mutex<Senders>>,
+    normalization: Normalization,
     inner: Weak<Inner>,
 }
 
+#[derive(Debug)]
+struct Senders {
+    endpoints: HashMap<Dst, EndpointsPublisher>,
+    overrides: HashMap<Dst, watch::Sender<Overrides>>,
+    /// Service profiles, by the name of the destination they are served for,
+    /// or by the authority of the only port they are served for.
+    profiles: HashMap<String, watch::Sender<ServiceProfile>>,
+}
+
+/// The endpoints templates served for destination patterns, and the
+/// endpoints rendered from them for each destination with open streams.
+#[derive(Debug, Default)]
+struct Patterns {
+    templates: Vec<(DstPattern, EndpointsTemplate)>,
+    rendered: Arc<Mutex<HashMap<Dst, Weak<Rendered>>>>,
+}
+
+/// Endpoints rendered for a destination, which are held by its streams and
+/// evicted once they have all ended.
+#[derive(Debug)]
+struct Rendered {
+    dst: Dst,
+    rx: watch::Receiver<Arc<EndpointsVersion>>,
+    /// Keeps the streams' watch open.
+    _publisher: EndpointsPublisher,
+    rendered: Weak<Mutex<HashMap<Dst, Weak<Rendered>>>>,
+}
+
+/// Publishes each version of a destination's endpoints to its streams.
+#[derive(Debug)]
+struct EndpointsPublisher {
+    tx: watch::Sender<Arc<EndpointsVersion>>,
+    current: Arc<EndpointsVersion>,
+}
+
+/// A version of a destination's endpoints, along with the updates that bring
+/// streams up to date with it.
+///
+/// The updates are computed and encoded at most once per version and shared
+/// by all of the destination's streams, rather than by each stream diffing
+/// the endpoints and encoding the updates itself.
+#[derive(Debug)]
+struct EndpointsVersion {
+    number: u64,
+    endpoints: Endpoints,
+    /// The updates sent to streams that have not seen any prior version,
+    /// computed when the first such stream needs them.
+    initial: Mutex<Option<Updates>>,
+    /// The updates sent to streams that have seen the prior version.
+    diff: Updates,
+}
+
+/// Updates shared by the streams they are sent on.
+type Updates = Arc<Vec<EncodedUpdate>>;
+
+/// The updates sent on a `Get` stream.
+type UpdateStream = mpsc::Receiver<GrpcResult<EncodedUpdate>>;
+
+/// An update, encoded once and then written as is to every stream it's sent
+/// on.
+///
+/// Clones share the encoded update, so that a stream that falls behind holds
+/// no copies of the updates it has yet to send.
+#[derive(Clone, Debug)]
+struct EncodedUpdate {
+    kind: UpdateKind,
+    buf: Bytes,
+}
+
+/// Serves the Destination API.
+///
+/// This replaces the generated `DestinationServer`, which would encode each
+/// update again for every `Get` stream it's sent on, so that streams are sent
+/// the updates they share as they were encoded.
+#[derive(Clone, Debug)]
+pub(crate) struct DstServer(DstService);
+
+/// Serves a `Get` stream.
+struct GetSvc(DstService);
+
+/// Serves a `GetProfile` stream.
+struct GetProfileSvc(DstService);
+
 impl DstSender {
     #[tracing::instrument(skip(self), name = "DstSender::send_endpoints", level = "info")]
     pub async fn send_endpoints(&mut self, dst: Dst, endpoints: Endpoints) -> Result<(), Error> {
-        if let Some(sender) = self.endpoints.get(&dst) {
+        let dst = self.normalization.normalize(dst);
+        let mut senders = self.senders.lock().await;
+        senders.send_endpoints(&self.inner, dst, endpoints).await
+    }
+
+    #[tracing::instrument(skip(self), name = "DstSender::send_overrides", level = "info")]
+    pub async fn send_overrides(&mut self, dst: Dst, overrides: Overrides) -> Result<(), Error> {
+        let dst = self.normalization.normalize(dst);
+        let mut senders = self.senders.lock().await;
+        senders.send_overrides(&self.inner, dst, overrides).await
+    }
+
+    /// Serves `profile` for every port of the destination it is named for.
+    ///
+    /// Overrides sent for a destination replace the profile's destination
+    /// overrides.
+    #[tracing::instrument(
+        skip(self, profile),
+        fields(name = %profile.name()),
+        name = "DstSender::send_profile",
+        level = "info"
+    )]
+    pub async fn send_profile(&mut self, profile: ServiceProfile) -> Result<(), Error> {
+        let name = self.normalization.name(profile.name());
+        let mut senders = self.senders.lock().await;
+        senders.send_profile(&self.inner, name, profile).await
+    }
+
+    /// Serves `profile` for `dst` only, rather than for every port of the
+    /// destination it is named for.
+    ///
+    /// Streams opened for `dst` are served this profile rather than one sent
+    /// with `send_profile`.
+    #[tracing::instrument(
+        skip(self, profile),
+        name = "DstSender::send_dst_profile",
+        level = "info"
+    )]
+    pub async fn send_dst_profile(
+        &mut self,
+        dst: Dst,
+        profile: ServiceProfile,
+    ) -> Result<(), Error> {
+        let dst = self.normalization.normalize(dst);
+        let mut senders = self.senders.lock().await;
+        senders
+            .send_profile(&self.inner, dst.to_string(), profile)
+            .await
+    }
+
+    /// Stops serving the service profile named `name`.
+    ///
+    /// Open streams continue with only their destination's overrides, if it
+    /// has any, and end otherwise.
+    #[tracing::instrument(skip(self), name = "DstSender::delete_profile", level = "info")]
+    pub async fn delete_profile(&mut self, name: &str) {
+        let name = self.normalization.name(name);
+        let mut senders = self.senders.lock().await;
+        senders.delete_profile(&self.inner, &name).await
+    }
+
+    /// Stops serving the service profile sent for `dst` with
+    /// `send_dst_profile`.
+    #[tracing::instrument(skip(self), name = "DstSender::delete_dst_profile", level = "info")]
+    pub async fn delete_dst_profile(&mut self, dst: Dst) {
+        let dst = self.normalization.normalize(dst);
+        let mut senders = self.senders.lock().await;
+        senders.delete_profile(&self.inner, &dst.to_string()).await
+    }
+
+    /// Replaces all endpoints and overrides with those in the given specs.
+    ///
+    /// Destinations that are not in the new specs are removed, ending their
+    /// open streams.
+    #[tracing::instrument(
+        skip(self, endpoints, overrides),
+        name = "DstSender::replace",
+        level = "info"
+    )]
+    pub async fn replace(
+        &mut self,
+        endpoints: EndpointsSpec,
+        overrides: OverridesSpec,
+    ) -> Result<(), Error> {
+        let endpoints = self.normalization.endpoints(endpoints);
+        let overrides = self.normalization.overrides(overrides);
+        let mut senders = self.senders.lock().await;
+        let removed = senders
+            .endpoints
+            .keys()
+            .filter(|dst| !endpoints.dsts.contains_key(dst))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dst in removed {
+            senders.delete_dst(&self.inner, dst).await;
+        }
+        for (dst, eps) in endpoints.dsts.into_iter() {
+            senders.send_endpoints(&self.inner, dst, eps).await?;
+        }
+
+        let removed = senders
+            .overrides
+            .keys()
+            .filter(|dst| !overrides.dsts.contains_key(dst))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dst in removed {
+            tracing::info!(?dst, "dropping overrides sender");
+            senders.overrides.remove(&dst);
+            if let Some(inner) = self.inner.upgrade() {
+                inner.overrides.write().await.remove(&dst);
+            }
+        }
+        for (dst, overrides) in overrides.dsts.into_iter() {
+            senders.send_overrides(&self.inner, dst, overrides).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the endpoints currently served for `dst`, if any.
+    pub async fn endpoints(&self, dst: &Dst) -> Option<Endpoints> {
+        let dst = self.normalization.normalize(dst.clone());
+        let inner = self.inner.upgrade()?;
+        let endpoints = inner.endpoints.read().await;
+        let rx = endpoints.get(&dst)?;
+        let current = rx.borrow().endpoints.clone();
+        Some(current)
+    }
+
+    #[tracing::instrument(skip(self), name = "DstSender::delete_dst", level = "info")]
+    pub async fn delete_dst(&mut self, dst: Dst) {
+        let dst = self.normalization.normalize(dst);
+        let mut senders = self.senders.lock().await;
+        senders.delete_dst(&self.inner, dst).await
+    }
+}
+
+// === impl Senders ===
+
+impl Senders {
+    async fn send_endpoints(
+        &mut self,
+        inner: &Weak<Inner>,
+        dst: Dst,
+        endpoints: Endpoints,
+    ) -> Result<(), Error> {
+        if let Some(publisher) = self.endpoints.get_mut(&dst) {
             tracing::info!("Dst present");
-            sender.broadcast(endpoints)?;
+            publisher.publish(&dst, endpoints)?;
         } else {
             tracing::info!("Dst non present");
-            if let Some(inner) = self.inner.upgrade() {
-                let (tx, rx) = watch::channel(endpoints);
-                self.endpoints.insert(dst.clone(), tx);
+            if let Some(inner) = inner.upgrade() {
+                let (publisher, rx) = EndpointsPublisher::new(endpoints);
+                self.endpoints.insert(dst.clone(), publisher);
                 inner.endpoints.write().await.insert(dst, rx);
             }
         }
         Ok(())
     }
 
-    #[tracing::instrument(skip(self), name = "DstSender::delete_dst", level = "info")]
-    pub async fn delete_dst(&mut self, dst: Dst) {
+    async fn send_overrides(
+        &mut self,
+        inner: &Weak<Inner>,
+        dst: Dst,
+        overrides: Overrides,
+    ) -> Result<(), Error> {
+        if let Some(sender) = self.overrides.get(&dst) {
+            tracing::info!("Dst present");
+            sender.broadcast(overrides)?;
+        } else {
+            tracing::info!("Dst non present");
+            if let Some(inner) = inner.upgrade() {
+                let (tx, rx) = watch::channel(overrides);
+                self.overrides.insert(dst.clone(), tx);
+                inner.overrides.write().await.insert(dst, rx);
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves `profile` for the destinations that `key`, a name or an
+    /// authority, refers to.
+    async fn send_profile(
+        &mut self,
+        inner: &Weak<Inner>,
+        key: String,
+        profile: ServiceProfile,
+    ) -> Result<(), Error> {
+        if let Some(sender) = self.profiles.get(&key) {
+            tracing::info!("Profile present");
+            sender.broadcast(profile)?;
+        } else {
+            tracing::info!("Profile non present");
+            if let Some(inner) = inner.upgrade() {
+                let (tx, rx) = watch::channel(profile);
+                self.profiles.insert(key.clone(), tx);
+                inner.profiles.write().await.insert(key, rx);
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_profile(&mut self, inner: &Weak<Inner>, key: &str) {
+        if self.profiles.remove(key).is_some() {
+            tracing::info!("dropping profile sender");
+            if let Some(inner) = inner.upgrade() {
+                inner.profiles.write().await.remove(key);
+            }
+        } else {
+            tracing::info!("Profile not found");
+        }
+    }
+
+    async fn delete_dst(&mut self, inner: &Weak<Inner>, dst: Dst) {
         if let Some(sender) = self.endpoints.remove(&dst) {
             tracing::info!("dropping sender");
             drop(sender);
-            if let Some(inner) = self.inner.upgrade() {
+            if let Some(inner) = inner.upgrade() {
                 inner.endpoints.write().await.remove(&dst);
             }
         } else {
@@ -58,8 +366,58 @@ impl DstSender {
 
 #[derive(Debug)]
 pub struct Inner {
-    endpoints: RwLock<HashMap<Dst, watch::Receiver<Endpoints>>>,
+    endpoints: RwLock<HashMap<Dst, watch::Receiver<Arc<EndpointsVersion>>>>,
     overrides: RwLock<HashMap<Dst, watch::Receiver<Overrides>>>,
+    profiles: RwLock<HashMap<String, watch::Receiver<ServiceProfile>>>,
+    drain_tx: watch::Sender<Option<FinalUpdate>>,
+    drain_rx: watch::Receiver<Option<FinalUpdate>>,
+    restart_tx: watch::Sender<Restarts>,
+    restart_rx: watch::Receiver<Restarts>,
+    outage: Mutex<Option<(Instant, Teardown)>>,
+}
+
+/// How many times the service has been restarted.
+///
+/// Streams and connections compare this with what it was when they were
+/// opened, so that no restart is missed, however quickly restarts follow each
+/// other.
+#[derive(Clone, Debug, Default)]
+struct Restarts {
+    /// Restarts with `Teardown::Status`.
+    statuses: u64,
+    /// The status of the last restart with `Teardown::Status`.
+    status: Option<(tonic::Code, String)>,
+    /// Restarts with `Teardown::Reset`.
+    resets: u64,
+}
+
+/// Watches for restarts after it was created.
+#[derive(Debug)]
+struct RestartWatch {
+    rx: watch::Receiver<Restarts>,
+    seen: Restarts,
+}
+
+/// The last message sent on open streams when the service is drained.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FinalUpdate {
+    /// Send `NoEndpoints { exists: false }` on `Get` streams, then end all
+    /// streams.
+    NoEndpoints,
+    /// End all streams with the given gRPC status.
+    Status(tonic::Code, String),
+    /// End all streams without a final message.
+    Close,
+}
+
+/// How open streams are torn down when the service is restarted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Teardown {
+    /// End all streams with the given gRPC status.
+    Status(tonic::Code, String),
+    /// Reset all connections served by a `Controller`, without ending their
+    /// streams.
+    Reset,
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
@@ -79,7 +437,8 @@ pub struct EndpointMeta {
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub struct Overrides(HashMap<Dst, u32>);
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Dst {
     name: String,
     port: u16,
@@ -102,13 +461,28 @@ impl DstService {
     }
 
     pub fn new(endpoints: EndpointsSpec, overrides: OverridesSpec) -> (DstSender, DstService) {
+        Self::normalized(Normalization::literal(), endpoints, overrides)
+    }
+
+    /// Serves `endpoints` and `overrides`, normalizing the names that
+    /// destinations are served and looked up by with `normalization`,
+    /// rather than matching them literally.
+    ///
+    /// The returned `DstSender` normalizes the names that it is sent, too.
+    pub fn normalized(
+        normalization: Normalization,
+        endpoints: EndpointsSpec,
+        overrides: OverridesSpec,
+    ) -> (DstSender, DstService) {
+        let endpoints = normalization.endpoints(endpoints);
+        let overrides = normalization.overrides(overrides);
         let mut endpoints_txs = HashMap::new();
         let mut endpoints_rxs = HashMap::new();
         for (dst, eps) in endpoints.dsts.into_iter() {
-            let (tx, rx) = watch::channel(eps.clone());
-            endpoints_txs.insert(dst.clone(), tx);
-            endpoints_rxs.insert(dst.clone(), rx);
             tracing::info!(?dst, ?eps, "added");
+            let (publisher, rx) = EndpointsPublisher::new(eps);
+            endpoints_txs.insert(dst.clone(), publisher);
+            endpoints_rxs.insert(dst, rx);
         }
 
         let mut overrides_txs = HashMap::new();
@@ -120,22 +494,204 @@ impl DstService {
             tracing::info!(?dst, ?eps, "added");
         }
 
+        let (drain_tx, drain_rx) = watch::channel(None);
+        let (restart_tx, restart_rx) = watch::channel(Restarts::default());
         let inner = Arc::new(Inner {
             endpoints: RwLock::new(endpoints_rxs),
             overrides: RwLock::new(overrides_rxs),
+            profiles: RwLock::new(HashMap::new()),
+            drain_tx,
+            drain_rx,
+            restart_tx,
+            restart_rx,
+            outage: Mutex::new(None),
         });
-        let sender = DstSender {
+        let senders = Senders {
             endpoints: endpoints_txs,
             overrides: overrides_txs,
+            profiles: HashMap::new(),
+        };
+        let sender = DstSender {
+            senders: Arc::new(tokio::sync::Mutex::new(senders)),
+            normalization: normalization.clone(),
             inner: Arc::downgrade(&inner),
         };
-        (sender, Self { inner })
+        let svc = Self {
+            inner,
+            metrics: Metrics::default(),
+            upstream: None,
+            patterns: Arc::new(Patterns::default()),
+            normalization,
+        };
+        (sender, svc)
+    }
+
+    /// Records the streams and updates served in `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Forwards lookups for destinations that are not served locally to
+    /// `upstream`, rather than rejecting them.
+    pub fn with_upstream(self, upstream: Upstream) -> Self {
+        Self {
+            upstream: Some(upstream),
+            ..self
+        }
+    }
+
+    /// Serves templated endpoints for destinations that match `patterns`,
+    /// when no endpoints are served for them.
+    ///
+    /// Each lookup is served the endpoints of the first pattern that matches
+    /// it, rendered for its destination. These never change, and are shared
+    /// by the destination's streams until they have all ended.
+    pub fn with_patterns(self, patterns: PatternsSpec) -> Self {
+        Self {
+            patterns: Arc::new(Patterns {
+                templates: patterns.patterns,
+                rendered: Arc::default(),
+            }),
+            ..self
+        }
+    }
+
+    /// Returns the endpoints of the first pattern that matches `dst`,
+    /// rendering them if `dst` has no open streams.
+    fn templated_endpoints(&self, dst: &Dst) -> Option<Arc<Rendered>> {
+        let mut rendered = self.patterns.rendered.lock().unwrap();
+        if let Some(endpoints) = rendered.get(dst).and_then(Weak::upgrade) {
+            return Some(endpoints);
+        }
+
+        let (pattern, template) = self
+            .patterns
+            .templates
+            .iter()
+            .find(|(pattern, _)| pattern.matches(dst))?;
+        match template.render(dst) {
+            Ok(endpoints) => {
+                tracing::info!(?pattern, "Matched pattern");
+                let (publisher, rx) = EndpointsPublisher::new(endpoints);
+                let endpoints = Arc::new(Rendered {
+                    dst: dst.clone(),
+                    rx,
+                    _publisher: publisher,
+                    rendered: Arc::downgrade(&self.patterns.rendered),
+                });
+                rendered.insert(dst.clone(), Arc::downgrade(&endpoints));
+                Some(endpoints)
+            }
+            Err(e) => {
+                tracing::warn!(?pattern, %e, "Failed to render endpoints template");
+                None
+            }
+        }
+    }
+
+    /// Returns the number of endpoints served for each destination.
+    pub(crate) async fn endpoint_counts(&self) -> Vec<(Dst, usize)> {
+        let mut counts = self
+            .inner
+            .endpoints
+            .read()
+            .await
+            .iter()
+            .map(|(dst, rx)| (dst.clone(), rx.borrow().endpoints.0.len()))
+            .collect::<Vec<_>>();
+        counts.sort_by_key(|(dst, _)| dst.to_string());
+        counts
+    }
+
+    /// Ends all open streams, and any streams opened later, after sending them
+    /// `final_update`.
+    #[tracing::instrument(skip(self), name = "DstService::drain", level = "info")]
+    pub fn drain(&self, final_update: FinalUpdate) {
+        tracing::info!("Draining streams");
+        let _ = self.inner.drain_tx.broadcast(Some(final_update));
+    }
+
+    /// Simulates a controller restart.
+    ///
+    /// All open streams are torn down with `teardown`. For the following
+    /// `outage`, new requests fail with `UNAVAILABLE`, or, if connections are
+    /// being reset, new connections are reset as soon as they are accepted.
+    /// The endpoints and overrides being served are kept unless they are
+    /// replaced with `DstSender::replace`.
+    #[tracing::instrument(skip(self), name = "DstService::restart", level = "info")]
+    pub fn restart(&self, teardown: Teardown, outage: Duration) {
+        tracing::info!("Restarting");
+        let mut current = self.inner.outage.lock().unwrap();
+        let mut restarts = self.inner.restart_rx.borrow().clone();
+        match teardown {
+            Teardown::Status(code, ref message) => {
+                restarts.statuses += 1;
+                restarts.status = Some((code, message.clone()));
+            }
+            Teardown::Reset => restarts.resets += 1,
+        }
+        *current = Some((Instant::now() + outage, teardown));
+        // Restarts are counted while the outage is locked, so that
+        // concurrent restarts are all counted.
+        let _ = self.inner.restart_tx.broadcast(restarts);
+    }
+
+    /// Watches for the service being restarted from now on.
+    fn restarts(&self) -> RestartWatch {
+        let rx = self.inner.restart_rx.clone();
+        let seen = rx.borrow().clone();
+        RestartWatch { rx, seen }
+    }
+
+    /// Returns how the service is being torn down, if it is in an outage.
+    fn outage(&self) -> Option<Teardown> {
+        match *self.inner.outage.lock().unwrap() {
+            Some((until, ref teardown)) if Instant::now() < until => Some(teardown.clone()),
+            _ => None,
+        }
+    }
+
+    fn check_outage(&self) -> GrpcResult<()> {
+        match self.outage() {
+            Some(_) => Err(tonic::Status::unavailable("controller restarting")),
+            None => Ok(()),
+        }
+    }
+
+    /// Wraps a connection so that it is reset when the service is restarted
+    /// with `Teardown::Reset`.
+    pub(crate) fn resettable<IO>(&self, io: IO) -> Resettable<IO> {
+        if let Some(Teardown::Reset) = self.outage() {
+            return Resettable::reset(io);
+        }
+
+        let mut restarts = self.restarts();
+        Resettable::new(io, async move { restarts.reset().await })
     }
 
-    #[tracing::instrument(skip(self), level = "info")]
-    async fn stream_endpoints(&self, dst: &Dst) -> mpsc::Receiver<GrpcResult<pb::Update>> {
-        let mut endpoints_rx = match self.inner.endpoints.read().await.get(dst) {
-            Some(rx) => rx.clone(),
+    #[tracing::instrument(skip(self, req), level = "info")]
+    async fn stream_endpoints(
+        &self,
+        dst: &Dst,
+        client_id: Option<ClientIdentity>,
+        req: pb::GetDestination,
+    ) -> UpdateStream {
+        let mut endpoints_rx = self.inner.endpoints.read().await.get(dst).cloned();
+        let mut rendered = None;
+        if endpoints_rx.is_none() {
+            rendered = self.templated_endpoints(dst);
+            endpoints_rx = rendered.as_ref().map(|r| r.rx.clone());
+        }
+        if let (None, Some(upstream)) = (&endpoints_rx, &self.upstream) {
+            tracing::info!("Forwarding to upstream");
+            let updates = upstream
+                .get(dst, req)
+                .await
+                .map(|updates| updates.map_ok(EncodedUpdate::new).boxed());
+            return self.forward(Rpc::Get, dst, client_id, updates);
+        }
+        let mut endpoints_rx = match endpoints_rx {
+            Some(rx) => rx,
             None => {
                 tracing::info!("Does not exist");
                 let (mut tx, rx) = mpsc::channel(1);
@@ -147,67 +703,59 @@ impl DstService {
         };
 
         let concrete_name = dst.to_string();
+        let mut drain = self.inner.drain_rx.clone();
+        let mut restarts = self.restarts();
+        let metrics = self.metrics.clone();
+        let stream = metrics.stream_opened(Rpc::Get, dst.clone(), client_id);
 
         tracing::info!("Serving endpoints");
         let (mut tx, rx) = mpsc::channel(8);
         tokio::spawn(
             async move {
-                let mut prev = HashMap::new();
-
-                while let Some(Endpoints(curr)) = endpoints_rx.recv().await {
-                    if curr.is_empty() {
-                        tx.send(Ok(pb::Update {
-                            update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints {
-                                exists: true,
-                            })),
-                        }))
-                        .await?
-                    } else {
-                        let added = curr
-                            .values()
-                            .filter(|meta| meta.is_add(&prev))
-                            .map(EndpointMeta::to_weighted_addr)
-                            .collect::<Vec<_>>();
-                        if !added.is_empty() {
-                            tracing::debug!(?added);
-
-                            let mut metric_labels = HashMap::default();
-                            metric_labels.insert("concrete".to_string(), concrete_name.clone());
-
-                            tx.send(Ok(pb::Update {
-                                update: Some(pb::update::Update::Add(pb::WeightedAddrSet {
-                                    addrs: added,
-                                    metric_labels,
-                                })),
-                            }))
-                            .await?;
-                        }
+                let _stream = stream;
+                let _rendered = rendered;
+                let mut prev: Option<Arc<EndpointsVersion>> = None;
 
-                        let removed = prev
-                            .keys()
-                            .filter(|addr| !curr.contains_key(addr))
-                            .map(Into::into)
-                            .collect::<Vec<_>>();
-                        if !removed.is_empty() {
-                            tracing::debug!(?removed);
-                            tx.send(Ok(pb::Update {
-                                update: Some(pb::update::Update::Remove(pb::AddrSet {
-                                    addrs: removed,
-                                })),
-                            }))
-                            .await?;
+                loop {
+                    let curr = tokio::select! {
+                        version = endpoints_rx.recv() => match version {
+                            Some(version) => version,
+                            None => break,
+                        },
+                        final_update = drained(&mut drain) => {
+                            tracing::debug!(?final_update, "Draining");
+                            if let Some(update) = EncodedUpdate::final_update(&final_update) {
+                                let kind = update.as_ref().ok().map(|update| update.kind);
+                                tx.send(update).await?;
+                                if let Some(kind) = kind {
+                                    metrics.update_sent(kind);
+                                }
+                            }
+                            return Ok(());
+                        }
+                        status = restarts.status() => {
+                            tracing::debug!(%status, "Restarting");
+                            tx.send(Err(status)).await?;
+                            return Ok(());
                         }
+                    };
+
+                    for update in curr.updates_since(&concrete_name, prev.as_deref()).iter() {
+                        tracing::debug!(?update);
+                        tx.send(Ok(update.clone())).await?;
+                        metrics.update_sent(update.kind);
                     }
 
-                    prev = curr;
+                    prev = Some(curr);
                 }
                 tracing::debug!("Watch ended");
-                tx.send(Ok(pb::Update {
+                tx.send(Ok(EncodedUpdate::new(pb::Update {
                     update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints {
                         exists: false,
                     })),
-                }))
+                })))
                 .await?;
+                metrics.update_sent(UpdateKind::NoEndpoints);
                 Ok(())
             }
             .map_err(|_: mpsc::error::SendError<_>| tracing::info!("Lookup closed"))
@@ -217,43 +765,92 @@ impl DstService {
         rx
     }
 
-    #[tracing::instrument(skip(self), level = "info")]
-    async fn stream_overrides(
+    #[tracing::instrument(skip(self, req), level = "info")]
+    async fn stream_profile(
         &self,
         dst: &Dst,
+        client_id: Option<ClientIdentity>,
+        req: pb::GetDestination,
     ) -> mpsc::Receiver<GrpcResult<pb::DestinationProfile>> {
-        let mut overrides_rx = match self.inner.overrides.read().await.get(dst) {
-            Some(rx) => rx.clone(),
-            None => {
-                tracing::info!("Does not exist");
-                let (mut tx, rx) = mpsc::channel(1);
-                let _ = tx
-                    .send(Err(tonic::Status::invalid_argument("not configured")))
-                    .await;
-                return rx;
-            }
+        let mut overrides_rx = self.inner.overrides.read().await.get(dst).cloned();
+        let mut profile_rx = {
+            // A profile sent for this port takes precedence over one sent
+            // for every port.
+            let profiles = self.inner.profiles.read().await;
+            profiles
+                .get(&dst.to_string())
+                .or_else(|| profiles.get(dst.name()))
+                .cloned()
         };
+        if overrides_rx.is_none() && profile_rx.is_none() {
+            if let Some(ref upstream) = self.upstream {
+                tracing::info!("Forwarding to upstream");
+                let profiles = upstream.get_profile(dst, req).await;
+                return self.forward(Rpc::GetProfile, dst, client_id, profiles);
+            }
+            tracing::info!("Does not exist");
+            let (mut tx, rx) = mpsc::channel(1);
+            let _ = tx
+                .send(Err(tonic::Status::invalid_argument("not configured")))
+                .await;
+            return rx;
+        }
 
-        tracing::info!("Serving endpoints");
+        let mut drain = self.inner.drain_rx.clone();
+        let mut restarts = self.restarts();
+        let metrics = self.metrics.clone();
+        let stream = metrics.stream_opened(Rpc::GetProfile, dst.clone(), client_id);
+
+        tracing::info!("Serving profile");
         let (mut tx, rx) = mpsc::channel(8);
         tokio::spawn(
             async move {
-                while let Some(Overrides(overrides)) = overrides_rx.recv().await {
-                    tracing::debug!(?overrides);
-
-                    let dst_overrides = overrides
-                        .iter()
-                        .map(|(dst, weight)| pb::WeightedDst {
-                            authority: dst.to_string(),
-                            weight: *weight,
-                        })
-                        .collect::<Vec<_>>();
-
-                    tx.send(Ok(pb::DestinationProfile {
-                        dst_overrides,
-                        ..Default::default()
-                    }))
-                    .await?;
+                let _stream = stream;
+                // Each watch yields its current value first, so that the
+                // initial profile combines both.
+                let mut overrides = match overrides_rx {
+                    Some(ref mut rx) => rx.recv().await,
+                    None => None,
+                };
+                let mut profile = match profile_rx {
+                    Some(ref mut rx) => rx.recv().await,
+                    None => None,
+                };
+                loop {
+                    tracing::debug!(?overrides, ?profile);
+                    tx.send(Ok(merge_profile(profile.as_ref(), overrides.as_ref())))
+                        .await?;
+                    metrics.update_sent(UpdateKind::Profile);
+
+                    tokio::select! {
+                        update = next_value(&mut overrides_rx) => {
+                            overrides = update;
+                            if overrides.is_none() {
+                                overrides_rx = None;
+                            }
+                        },
+                        update = next_value(&mut profile_rx) => {
+                            profile = update;
+                            if profile.is_none() {
+                                profile_rx = None;
+                            }
+                        },
+                        final_update = drained(&mut drain) => {
+                            tracing::debug!(?final_update, "Draining");
+                            if let Some(update) = final_update.profile_update() {
+                                tx.send(update).await?;
+                            }
+                            return Ok(());
+                        }
+                        status = restarts.status() => {
+                            tracing::debug!(%status, "Restarting");
+                            tx.send(Err(status)).await?;
+                            return Ok(());
+                        }
+                    }
+                    if overrides_rx.is_none() && profile_rx.is_none() {
+                        break;
+                    }
                 }
                 tracing::debug!("Watch ended");
                 Ok(())
@@ -264,44 +861,268 @@ impl DstService {
 
         rx
     }
-}
 
-#[tonic::async_trait]
-impl Destination for DstService {
-    type GetStream = mpsc::Receiver<GrpcResult<pb::Update>>;
-    type GetProfileStream = mpsc::Receiver<GrpcResult<pb::DestinationProfile>>;
+    /// Forwards a stream from the upstream controller until it ends, or the
+    /// service is drained or restarted.
+    fn forward<T: Forwarded>(
+        &self,
+        rpc: Rpc,
+        dst: &Dst,
+        client_id: Option<ClientIdentity>,
+        upstream: GrpcResult<BoxStream<'static, GrpcResult<T>>>,
+    ) -> mpsc::Receiver<GrpcResult<T>> {
+        let (mut tx, rx) = mpsc::channel(8);
+        let mut upstream = match upstream {
+            Ok(upstream) => upstream,
+            Err(status) => {
+                tracing::info!(%status, "Upstream lookup failed");
+                let _ = tx.try_send(Err(status));
+                return rx;
+            }
+        };
 
+        let mut drain = self.inner.drain_rx.clone();
+        let mut restarts = self.restarts();
+        let metrics = self.metrics.clone();
+        let stream = metrics.stream_opened(rpc, dst.clone(), client_id);
+        tokio::spawn(
+            async move {
+                let _stream = stream;
+                loop {
+                    let msg = tokio::select! {
+                        msg = upstream.next() => match msg {
+                            Some(msg) => msg,
+                            None => break,
+                        },
+                        final_update = drained(&mut drain) => {
+                            tracing::debug!(?final_update, "Draining");
+                            if let Some(update) = T::final_update(&final_update) {
+                                let kind = update.as_ref().ok().map(Forwarded::kind);
+                                tx.send(update).await?;
+                                if let Some(kind) = kind {
+                                    metrics.update_sent(kind);
+                                }
+                            }
+                            return Ok(());
+                        }
+                        status = restarts.status() => {
+                            tracing::debug!(%status, "Restarting");
+                            tx.send(Err(status)).await?;
+                            return Ok(());
+                        }
+                    };
+                    tracing::debug!(?msg);
+                    let kind = msg.as_ref().ok().map(Forwarded::kind);
+                    tx.send(msg).await?;
+                    if let Some(kind) = kind {
+                        metrics.update_sent(kind);
+                    }
+                }
+                tracing::debug!("Upstream ended");
+                Ok(())
+            }
+            .map_err(|_: mpsc::error::SendError<_>| tracing::info!("Lookup closed"))
+            .in_current_span(),
+        );
+
+        rx
+    }
+}
+
+impl DstService {
     async fn get(
         &self,
         req: tonic::Request<pb::GetDestination>,
-    ) -> GrpcResult<tonic::Response<Self::GetStream>> {
-        let pb::GetDestination { path, .. } = req.into_inner();
-        let dst = path
-            .parse()
-            .map_err(|_| tonic::Status::invalid_argument("invalid dst"))?;
-        let stream = self.stream_endpoints(&dst).await;
+    ) -> GrpcResult<tonic::Response<UpdateStream>> {
+        self.check_outage()?;
+        let client_id = ClientIdentity::from_request(&req);
+        if let Some(ref client_id) = client_id {
+            tracing::info!(%client_id, "Client authenticated");
+        }
+        let req = req.into_inner();
+        let dst = self
+            .normalization
+            .dst(&req.path)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid dst"))?;
+        let stream = self.stream_endpoints(&dst, client_id, req).await;
         Ok(tonic::Response::new(stream))
     }
 
     async fn get_profile(
         &self,
         req: tonic::Request<pb::GetDestination>,
-    ) -> GrpcResult<tonic::Response<Self::GetProfileStream>> {
-        let pb::GetDestination { path, .. } = req.into_inner();
-        let dst = path
-            .parse()
-            .map_err(|_| tonic::Status::invalid_argument("invalid dst"))?;
-        let stream = self.stream_overrides(&dst).await;
+    ) -> GrpcResult<tonic::Response<mpsc::Receiver<GrpcResult<pb::DestinationProfile>>>> {
+        self.check_outage()?;
+        let client_id = ClientIdentity::from_request(&req);
+        if let Some(ref client_id) = client_id {
+            tracing::info!(%client_id, "Client authenticated");
+        }
+        let req = req.into_inner();
+        let dst = self
+            .normalization
+            .dst(&req.path)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid dst"))?;
+        let stream = self.stream_profile(&dst, client_id, req).await;
         Ok(tonic::Response::new(stream))
     }
 }
 
+/// Resolves with the final update once the service is drained.
+async fn drained(drain: &mut watch::Receiver<Option<FinalUpdate>>) -> FinalUpdate {
+    loop {
+        match drain.recv().await {
+            Some(Some(final_update)) => return final_update,
+            Some(None) => {}
+            // The service has been dropped, so it can never be drained.
+            None => future::pending::<()>().await,
+        }
+    }
+}
+
+// === impl RestartWatch ===
+
+impl RestartWatch {
+    /// Resolves with the status to end a stream with once the service is
+    /// restarted with `Teardown::Status`, with the latest status if it has
+    /// been restarted more than once.
+    ///
+    /// Streams are not ended when connections are reset, so that they are
+    /// not closed cleanly before their connections are reset.
+    async fn status(&mut self) -> tonic::Status {
+        let seen = self.seen.statuses;
+        let restarts = self.next(|r| r.statuses > seen).await;
+        let (code, message) = restarts.status.expect("restarts must have a status");
+        tonic::Status::new(code, message)
+    }
+
+    /// Resolves once the service is restarted with `Teardown::Reset`.
+    async fn reset(&mut self) {
+        let seen = self.seen.resets;
+        self.next(|r| r.resets > seen).await;
+    }
+
+    async fn next(&mut self, restarted: impl Fn(&Restarts) -> bool) -> Restarts {
+        loop {
+            match self.rx.recv().await {
+                Some(restarts) if restarted(&restarts) => return restarts,
+                Some(_) => {}
+                // The service has been dropped, so it can never be restarted.
+                None => future::pending::<()>().await,
+            }
+        }
+    }
+}
+
+/// Resolves with the next value of a watch, or with `None` once it ends.
+///
+/// Never resolves if there is no watch.
+async fn next_value<T: Clone>(rx: &mut Option<watch::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// Serves a service profile with a destination's overrides, which replace
+/// the profile's own.
+fn merge_profile(
+    profile: Option<&ServiceProfile>,
+    overrides: Option<&Overrides>,
+) -> pb::DestinationProfile {
+    let mut merged = profile.map(ServiceProfile::to_pb).unwrap_or_default();
+    if let Some(Overrides(overrides)) = overrides {
+        merged.dst_overrides = weighted_dsts(overrides);
+    }
+    merged
+}
+
+/// Converts overrides to `WeightedDst`s, ordered by authority so that they
+/// are served in the same order every time.
+pub(crate) fn weighted_dsts(overrides: &HashMap<Dst, u32>) -> Vec<pb::WeightedDst> {
+    let mut dsts = overrides
+        .iter()
+        .map(|(dst, weight)| pb::WeightedDst {
+            authority: dst.to_string(),
+            weight: *weight,
+        })
+        .collect::<Vec<_>>();
+    dsts.sort_by(|a, b| a.authority.cmp(&b.authority));
+    dsts
+}
+
+// === impl FinalUpdate ===
+
+impl FinalUpdate {
+    fn endpoints_update(&self) -> Option<GrpcResult<pb::Update>> {
+        match self {
+            FinalUpdate::NoEndpoints => Some(Ok(pb::Update {
+                update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints {
+                    exists: false,
+                })),
+            })),
+            FinalUpdate::Status(code, message) => {
+                Some(Err(tonic::Status::new(*code, message.clone())))
+            }
+            FinalUpdate::Close => None,
+        }
+    }
+
+    fn profile_update(&self) -> Option<GrpcResult<pb::DestinationProfile>> {
+        match self {
+            FinalUpdate::Status(code, message) => {
+                Some(Err(tonic::Status::new(*code, message.clone())))
+            }
+            FinalUpdate::NoEndpoints | FinalUpdate::Close => None,
+        }
+    }
+}
+
+// === impl Forwarded ===
+
+/// A message forwarded from an upstream controller.
+trait Forwarded: fmt::Debug + Send + Sized + 'static {
+    fn kind(&self) -> UpdateKind;
+
+    /// The last message sent when the service is drained.
+    fn final_update(final_update: &FinalUpdate) -> Option<GrpcResult<Self>>;
+}
+
+impl Forwarded for EncodedUpdate {
+    fn kind(&self) -> UpdateKind {
+        self.kind
+    }
+
+    fn final_update(final_update: &FinalUpdate) -> Option<GrpcResult<Self>> {
+        final_update
+            .endpoints_update()
+            .map(|update| update.map(EncodedUpdate::new))
+    }
+}
+
+impl Forwarded for pb::DestinationProfile {
+    fn kind(&self) -> UpdateKind {
+        UpdateKind::Profile
+    }
+
+    fn final_update(final_update: &FinalUpdate) -> Option<GrpcResult<Self>> {
+        final_update.profile_update()
+    }
+}
+
 // === impl Dst ===
 
 impl Dst {
     pub fn new(name: String, port: u16) -> Dst {
         Dst { name, port }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
 }
 
 impl fmt::Display for Dst {
@@ -331,6 +1152,10 @@ impl EndpointMeta {
         }
     }
 
+    pub(crate) fn set_weight(&mut self, weight: u32) {
+        self.weight = weight;
+    }
+
     fn to_weighted_addr(&self) -> pb::WeightedAddr {
         let protocol_hint = if self.h2 {
             Some(pb::ProtocolHint {
@@ -365,6 +1190,32 @@ impl EndpointMeta {
         }
     }
 
+    /// Converts an endpoint received from a Destination controller.
+    pub(crate) fn from_weighted_addr(addr: &pb::WeightedAddr) -> Option<EndpointMeta> {
+        let address = socket_addr(addr.addr.as_ref()?)?;
+        let h2 = match addr.protocol_hint {
+            Some(pb::ProtocolHint {
+                protocol: Some(pb::protocol_hint::Protocol::H2(_)),
+            }) => true,
+            _ => false,
+        };
+        let tls_identity = addr.tls_identity.as_ref().and_then(|id| match id.strategy {
+            Some(pb::tls_identity::Strategy::DnsLikeIdentity(ref id)) => Some(id.name.clone()),
+            _ => None,
+        });
+        Some(EndpointMeta {
+            address,
+            h2,
+            weight: addr.weight,
+            metric_labels: addr.metric_labels.clone().into_iter().collect(),
+            tls_identity,
+            authority_override: addr
+                .authority_override
+                .as_ref()
+                .map(|o| o.authority_override.clone()),
+        })
+    }
+
     fn is_add(&self, prev: &HashMap<SocketAddr, EndpointMeta>) -> bool {
         match prev.get(&self.address) {
             Some(prev_ep) => prev_ep != self,
@@ -373,6 +1224,265 @@ impl EndpointMeta {
     }
 }
 
+// === impl Rendered ===
+
+impl Drop for Rendered {
+    fn drop(&mut self) {
+        if let Some(rendered) = self.rendered.upgrade() {
+            let mut rendered = rendered.lock().unwrap();
+            // A later lookup may have rendered the endpoints again.
+            if let Some(0) = rendered.get(&self.dst).map(Weak::strong_count) {
+                tracing::debug!(dst = %self.dst, "Evicting rendered endpoints");
+                rendered.remove(&self.dst);
+            }
+        }
+    }
+}
+
+// === impl EndpointsPublisher ===
+
+impl EndpointsPublisher {
+    fn new(endpoints: Endpoints) -> (Self, watch::Receiver<Arc<EndpointsVersion>>) {
+        let current = Arc::new(EndpointsVersion::first(endpoints));
+        let (tx, rx) = watch::channel(current.clone());
+        (Self { tx, current }, rx)
+    }
+
+    fn publish(&mut self, dst: &Dst, endpoints: Endpoints) -> Result<(), Error> {
+        let next = Arc::new(self.current.next(dst, endpoints));
+        self.tx.broadcast(next.clone())?;
+        self.current = next;
+        Ok(())
+    }
+}
+
+// === impl EndpointsVersion ===
+
+impl EndpointsVersion {
+    fn first(endpoints: Endpoints) -> Self {
+        Self {
+            number: 0,
+            endpoints,
+            initial: Mutex::new(None),
+            // No stream has seen a prior version.
+            diff: Updates::default(),
+        }
+    }
+
+    fn next(&self, dst: &Dst, endpoints: Endpoints) -> Self {
+        Self {
+            number: self.number + 1,
+            initial: Mutex::new(None),
+            diff: shared(diff(&dst.to_string(), &self.endpoints, &endpoints)),
+            endpoints,
+        }
+    }
+
+    /// Returns the updates that bring a stream that last saw `prev` up to
+    /// date with this version.
+    ///
+    /// Streams that fall behind by more than one version, because the watch
+    /// only yields the latest version, diff against what they last saw.
+    fn updates_since(&self, concrete_name: &str, prev: Option<&Self>) -> Updates {
+        match prev {
+            None => self
+                .initial
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| {
+                    shared(diff(concrete_name, &Endpoints::default(), &self.endpoints))
+                })
+                .clone(),
+            Some(prev) if prev.number + 1 == self.number => self.diff.clone(),
+            Some(prev) => shared(diff(concrete_name, &prev.endpoints, &self.endpoints)),
+        }
+    }
+}
+
+fn shared(updates: Vec<pb::Update>) -> Updates {
+    Arc::new(updates.into_iter().map(EncodedUpdate::new).collect())
+}
+
+/// Computes the updates that change `prev` into `curr`.
+fn diff(concrete_name: &str, prev: &Endpoints, curr: &Endpoints) -> Vec<pb::Update> {
+    let (Endpoints(prev), Endpoints(curr)) = (prev, curr);
+    if curr.is_empty() {
+        return vec![pb::Update {
+            update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints {
+                exists: true,
+            })),
+        }];
+    }
+
+    let mut updates = Vec::new();
+    let added = curr
+        .values()
+        .filter(|meta| meta.is_add(prev))
+        .map(EndpointMeta::to_weighted_addr)
+        .collect::<Vec<_>>();
+    if !added.is_empty() {
+        tracing::debug!(added = added.len());
+
+        let mut metric_labels = HashMap::default();
+        metric_labels.insert("concrete".to_string(), concrete_name.to_string());
+
+        updates.push(pb::Update {
+            update: Some(pb::update::Update::Add(pb::WeightedAddrSet {
+                addrs: added,
+                metric_labels,
+            })),
+        });
+    }
+
+    let removed = prev
+        .keys()
+        .filter(|addr| !curr.contains_key(addr))
+        .map(Into::into)
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        tracing::debug!(removed = removed.len());
+        updates.push(pb::Update {
+            update: Some(pb::update::Update::Remove(pb::AddrSet { addrs: removed })),
+        });
+    }
+    updates
+}
+
+pub(crate) fn update_kind(update: &pb::Update) -> UpdateKind {
+    match update.update {
+        Some(pb::update::Update::Add(_)) => UpdateKind::Add,
+        Some(pb::update::Update::Remove(_)) => UpdateKind::Remove,
+        Some(pb::update::Update::NoEndpoints(_)) | None => UpdateKind::NoEndpoints,
+    }
+}
+
+pub(crate) fn socket_addr(addr: &net::TcpAddress) -> Option<SocketAddr> {
+    let ip = match addr.ip.as_ref()?.ip.as_ref()? {
+        net::ip_address::Ip::Ipv4(ip) => IpAddr::from(ip.to_be_bytes()),
+        net::ip_address::Ip::Ipv6(ip) => {
+            let ip = (u128::from(ip.first) << 64) | u128::from(ip.last);
+            IpAddr::from(ip.to_be_bytes())
+        }
+    };
+    if addr.port > u32::from(u16::max_value()) {
+        return None;
+    }
+    Some(SocketAddr::new(ip, addr.port as u16))
+}
+
+// === impl EncodedUpdate ===
+
+impl EncodedUpdate {
+    fn new(update: pb::Update) -> Self {
+        let mut buf = Vec::with_capacity(update.encoded_len());
+        update
+            .encode(&mut buf)
+            .expect("buffer must have capacity for the update");
+        Self {
+            kind: update_kind(&update),
+            buf: buf.into(),
+        }
+    }
+}
+
+/// Writes the encoded update as is, so that it can be sent with tonic's
+/// `ProstCodec`. Updates are never decoded.
+impl Message for EncodedUpdate {
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&self.buf);
+    }
+
+    fn merge_field<B: Buf>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), prost::DecodeError> {
+        prost::encoding::skip_field(wire_type, tag, buf, ctx)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+// === impl DstServer ===
+
+impl DstServer {
+    const GET: &'static str = "/io.linkerd.proxy.destination.Destination/Get";
+    const GET_PROFILE: &'static str = "/io.linkerd.proxy.destination.Destination/GetProfile";
+
+    pub(crate) fn new(svc: DstService) -> Self {
+        Self(svc)
+    }
+}
+
+impl<B> Service<http::Request<B>> for DstServer
+where
+    B: HttpBody + Send + Sync + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Never;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.0.clone();
+        match req.uri().path() {
+            Self::GET => Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::default());
+                Ok(grpc.server_streaming(GetSvc(svc), req).await)
+            }),
+            Self::GET_PROFILE => Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::default());
+                Ok(grpc.server_streaming(GetProfileSvc(svc), req).await)
+            }),
+            _ => Box::pin(async move {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", "12")
+                    .body(BoxBody::empty())
+                    .unwrap())
+            }),
+        }
+    }
+}
+
+impl NamedService for DstServer {
+    const NAME: &'static str = "io.linkerd.proxy.destination.Destination";
+}
+
+impl ServerStreamingService<pb::GetDestination> for GetSvc {
+    type Response = EncodedUpdate;
+    type ResponseStream = UpdateStream;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+    fn call(&mut self, req: tonic::Request<pb::GetDestination>) -> Self::Future {
+        let svc = self.0.clone();
+        Box::pin(async move { svc.get(req).await })
+    }
+}
+
+impl ServerStreamingService<pb::GetDestination> for GetProfileSvc {
+    type Response = pb::DestinationProfile;
+    type ResponseStream = mpsc::Receiver<GrpcResult<pb::DestinationProfile>>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+    fn call(&mut self, req: tonic::Request<pb::GetDestination>) -> Self::Future {
+        let svc = self.0.clone();
+        Box::pin(async move { svc.get_profile(req).await })
+    }
+}
+
 // === impl Overrides ===
 
 impl Overrides {
@@ -380,3 +1490,232 @@ impl Overrides {
         Overrides(dsts)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONCRETE: &str = "web.ns.svc.cluster.local:8080";
+
+    fn endpoints(addrs: &[&str]) -> Endpoints {
+        Endpoints(
+            addrs
+                .iter()
+                .map(|addr| {
+                    let addr = addr.parse().unwrap();
+                    let meta = EndpointMeta::new(addr, false, 1, BTreeMap::new(), None, None);
+                    (addr, meta)
+                })
+                .collect(),
+        )
+    }
+
+    /// Summarizes updates as the addresses each adds or removes.
+    fn summarize(updates: &Updates) -> Vec<(&'static str, Vec<SocketAddr>)> {
+        updates
+            .iter()
+            .map(|update| {
+                let update = pb::Update::decode(update.buf.clone()).unwrap();
+                let (kind, mut addrs) = match update.update {
+                    Some(pb::update::Update::Add(ref add)) => {
+                        let addrs = add.addrs.iter().filter_map(|a| a.addr.as_ref());
+                        ("add", addrs.filter_map(socket_addr).collect::<Vec<_>>())
+                    }
+                    Some(pb::update::Update::Remove(ref remove)) => (
+                        "remove",
+                        remove.addrs.iter().filter_map(socket_addr).collect(),
+                    ),
+                    Some(pb::update::Update::NoEndpoints(ref no)) => {
+                        assert!(no.exists);
+                        ("none", Vec::new())
+                    }
+                    None => panic!("empty update"),
+                };
+                addrs.sort();
+                (kind, addrs)
+            })
+            .collect()
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn overrides_are_served_in_authority_order() {
+        let overrides = (0..20)
+            .map(|i| {
+                let dst = format!("web-{:02}.ns.svc.cluster.local:8080", i);
+                (dst.parse::<Dst>().unwrap(), i)
+            })
+            .collect::<HashMap<_, _>>();
+        let profile = merge_profile(None, Some(&Overrides::new(overrides)));
+        let served = profile
+            .dst_overrides
+            .into_iter()
+            .map(|dst| (dst.authority, dst.weight))
+            .collect::<Vec<_>>();
+        let expected = (0..20)
+            .map(|i| (format!("web-{:02}.ns.svc.cluster.local:8080", i), i))
+            .collect::<Vec<_>>();
+        assert_eq!(served, expected);
+    }
+
+    #[test]
+    fn encoded_updates_are_written_as_is() {
+        let update = diff(
+            CONCRETE,
+            &Endpoints::default(),
+            &endpoints(&["10.0.0.1:8080"]),
+        )
+        .pop()
+        .unwrap();
+        let encoded = EncodedUpdate::new(update.clone());
+        assert_eq!(encoded.kind, UpdateKind::Add);
+
+        let mut written = Vec::new();
+        encoded.encode(&mut written).unwrap();
+        let mut expected = Vec::new();
+        update.encode(&mut expected).unwrap();
+        assert_eq!(written, expected);
+        assert_eq!(pb::Update::decode(&written[..]).unwrap(), update);
+    }
+
+    #[test]
+    fn late_subscribers_get_all_current_endpoints() {
+        let dst = CONCRETE.parse::<Dst>().unwrap();
+        let v0 = EndpointsVersion::first(endpoints(&["10.0.0.1:8080", "10.0.0.2:8080"]));
+        let v1 = v0.next(&dst, endpoints(&["10.0.0.1:8080", "10.0.0.3:8080"]));
+        let v2 = v1.next(&dst, endpoints(&["10.0.0.3:8080", "10.0.0.4:8080"]));
+
+        assert_eq!(
+            summarize(&v0.updates_since(CONCRETE, None)),
+            [("add", addrs(&["10.0.0.1:8080", "10.0.0.2:8080"]))]
+        );
+        assert_eq!(
+            summarize(&v2.updates_since(CONCRETE, None)),
+            [("add", addrs(&["10.0.0.3:8080", "10.0.0.4:8080"]))]
+        );
+
+        // The initial updates are computed once and shared.
+        assert!(Arc::ptr_eq(
+            &v2.updates_since(CONCRETE, None),
+            &v2.updates_since(CONCRETE, None)
+        ));
+    }
+
+    #[test]
+    fn subscribers_get_the_diff_from_what_they_last_saw() {
+        let dst = CONCRETE.parse::<Dst>().unwrap();
+        let v0 = EndpointsVersion::first(endpoints(&["10.0.0.1:8080", "10.0.0.2:8080"]));
+        let v1 = v0.next(&dst, endpoints(&["10.0.0.1:8080", "10.0.0.3:8080"]));
+        let v2 = v1.next(&dst, endpoints(&["10.0.0.3:8080", "10.0.0.4:8080"]));
+
+        // Streams that saw the prior version share its diff.
+        assert_eq!(
+            summarize(&v1.updates_since(CONCRETE, Some(&v0))),
+            [
+                ("add", addrs(&["10.0.0.3:8080"])),
+                ("remove", addrs(&["10.0.0.2:8080"]))
+            ]
+        );
+        assert!(Arc::ptr_eq(
+            &v2.updates_since(CONCRETE, Some(&v1)),
+            &v2.updates_since(CONCRETE, Some(&v1))
+        ));
+
+        // Streams that fell behind are diffed against what they saw.
+        assert_eq!(
+            summarize(&v2.updates_since(CONCRETE, Some(&v0))),
+            [
+                ("add", addrs(&["10.0.0.3:8080", "10.0.0.4:8080"])),
+                ("remove", addrs(&["10.0.0.1:8080", "10.0.0.2:8080"]))
+            ]
+        );
+
+        let v3 = v2.next(&dst, Endpoints::default());
+        assert_eq!(
+            summarize(&v3.updates_since(CONCRETE, Some(&v2))),
+            [("none", vec![])]
+        );
+        assert_eq!(
+            summarize(&v3.updates_since(CONCRETE, None)),
+            [("none", vec![])]
+        );
+    }
+
+    #[test]
+    fn restarts_in_quick_succession_are_all_seen() {
+        let (_, svc) = DstService::empty();
+        let mut stream = svc.restarts();
+        let mut conn = svc.restarts();
+
+        // Neither restart is seen before both have been made.
+        svc.restart(Teardown::Reset, Duration::from_secs(0));
+        let message = "restarting".to_string();
+        svc.restart(
+            Teardown::Status(tonic::Code::Unavailable, message.clone()),
+            Duration::from_secs(0),
+        );
+        let status = stream.status().now_or_never().expect("stream must end");
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), message);
+        assert!(
+            conn.reset().now_or_never().is_some(),
+            "connection must reset"
+        );
+
+        // The latest status ends streams that see several at once.
+        let mut stream = svc.restarts();
+        for code in &[tonic::Code::Internal, tonic::Code::Aborted] {
+            svc.restart(
+                Teardown::Status(*code, message.clone()),
+                Duration::from_secs(0),
+            );
+        }
+        let status = stream.status().now_or_never().expect("stream must end");
+        assert_eq!(status.code(), tonic::Code::Aborted);
+
+        // Watches only see restarts made after they were created.
+        assert!(svc.restarts().status().now_or_never().is_none());
+        assert!(svc.restarts().reset().now_or_never().is_none());
+    }
+
+    #[test]
+    fn rendered_endpoints_are_evicted_with_their_last_stream() {
+        let (_, svc) = DstService::empty();
+        let svc = svc.with_patterns(
+            "*.ns.svc.cluster.local:8080=10.0.0.1:{port}"
+                .parse()
+                .unwrap(),
+        );
+        let dst = CONCRETE.parse::<Dst>().unwrap();
+        let rendered = || svc.patterns.rendered.lock().unwrap().len();
+
+        let first = svc.templated_endpoints(&dst).expect("pattern must match");
+        let second = svc.templated_endpoints(&dst).expect("pattern must match");
+        assert!(Arc::ptr_eq(&first, &second));
+        let served = first
+            .rx
+            .borrow()
+            .endpoints
+            .0
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(served, addrs(&["10.0.0.1:8080"]));
+        assert_eq!(rendered(), 1);
+
+        drop(first);
+        assert_eq!(rendered(), 1);
+        drop(second);
+        assert_eq!(rendered(), 0);
+
+        // Later lookups render the endpoints again.
+        let _third = svc.templated_endpoints(&dst).expect("pattern must match");
+        assert_eq!(rendered(), 1);
+        assert!(svc
+            .templated_endpoints(&"web.other.svc.cluster.local:8080".parse().unwrap())
+            .is_none());
+    }
+}
@@ -3,6 +3,7 @@ use crate::DstSender;
 use crate::EndpointMeta;
 use crate::Endpoints;
 use crate::Error;
+use crate::Metrics;
 use inotify::{Event, EventMask, Inotify, WatchMask};
 use inotify_sys as ffi;
 use serde_json;
@@ -12,14 +13,17 @@ use std::mem;
 use std::path::PathBuf;
 use std::string::String;
 use tokio::stream::StreamExt;
+use tokio::sync::oneshot;
 
-const EVENT_BUF_SZ: usize =
+pub(crate) const EVENT_BUF_SZ: usize =
     mem::size_of::<ffi::inotify_event>() + (libc::FILENAME_MAX as usize) + 1;
 
 #[derive(Debug)]
 pub struct FsWatcher {
     endpoints_dir: PathBuf,
     dst_sender: DstSender,
+    metrics: Metrics,
+    ready: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
@@ -54,6 +58,21 @@ impl FsWatcher {
         Self {
             endpoints_dir,
             dst_sender,
+            metrics: Metrics::default(),
+            ready: None,
+        }
+    }
+
+    /// Records the events handled in `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Notifies `ready` once changes to the directory are being watched.
+    pub fn with_ready(self, ready: oneshot::Sender<()>) -> Self {
+        Self {
+            ready: Some(ready),
+            ..self
         }
     }
 
@@ -109,10 +128,15 @@ impl FsWatcher {
         let mask = WatchMask::MODIFY | WatchMask::DELETE;
         inotify.add_watch(self.endpoints_dir.clone(), mask)?;
         let mut stream = inotify.event_stream(vec![0; EVENT_BUF_SZ])?;
+        if let Some(ready) = self.ready.take() {
+            let _ = ready.send(());
+        }
         while let Some(event) = stream.next().await {
             match event {
                 Ok(event) => {
-                    if let Err(e) = self.handle_event(event).await {
+                    let res = self.handle_event(event).await;
+                    self.metrics.fs_event(&res);
+                    if let Err(e) = res {
                         tracing::error!(%e, "error handing event");
                     }
                 }
@@ -0,0 +1,190 @@
+use crate::{Cidr, EndpointMeta, Endpoints};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Generates a large set of synthetic endpoints, e.g. for benchmarking load
+/// balancers.
+///
+/// Generators are parsed from specs like
+/// `gen(cidr=10.0.0.0/16,port=8080,count=5000,weight=uniform(1,100),h2=0.5)`.
+/// The same spec always generates the same endpoints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointsGenerator {
+    pub(crate) cidr: Cidr,
+    pub(crate) port: u16,
+    pub(crate) count: usize,
+    pub(crate) weight: Weight,
+    /// The probability that each endpoint supports h2 upgrading.
+    pub(crate) h2: f64,
+    pub(crate) seed: u64,
+}
+
+/// How generated endpoints are weighted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Weight {
+    Fixed(u32),
+    /// Uniformly distributed between the bounds, inclusive.
+    Uniform(u32, u32),
+}
+
+// === impl EndpointsGenerator ===
+
+impl EndpointsGenerator {
+    pub fn new(cidr: Cidr, port: u16, count: usize) -> Self {
+        Self {
+            cidr,
+            port,
+            count,
+            weight: Weight::Fixed(10_000),
+            h2: 0.0,
+            seed: 0,
+        }
+    }
+
+    pub fn weight(self, weight: Weight) -> Self {
+        Self { weight, ..self }
+    }
+
+    /// Sets the probability, between 0 and 1, that each endpoint supports h2
+    /// upgrading.
+    pub fn h2(self, h2: f64) -> Self {
+        Self { h2, ..self }
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Generates the endpoints.
+    ///
+    /// # Panics
+    ///
+    /// If the network has fewer than `count` addresses, the uniform weight
+    /// bounds are reversed, or the h2 probability is not between 0 and 1.
+    pub fn generate(&self) -> Endpoints {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let capacity = capacity(&self.cidr);
+        assert!(
+            self.count <= capacity,
+            "{} endpoints do not fit in {:?}",
+            self.count,
+            self.cidr
+        );
+
+        let weights = match self.weight {
+            Weight::Fixed(weight) => Uniform::new_inclusive(weight, weight),
+            Weight::Uniform(min, max) => Uniform::new_inclusive(min, max),
+        };
+        let hosts = rand::seq::index::sample(&mut rng, capacity, self.count);
+        let endpoints = hosts
+            .into_iter()
+            .map(|host| {
+                let addr = SocketAddr::new(host_addr(&self.cidr, host + 1), self.port);
+                let weight = rng.sample(&weights);
+                let h2 = rng.gen_bool(self.h2);
+                let meta = EndpointMeta::new(addr, h2, weight, BTreeMap::default(), None, None);
+                (addr, meta)
+            })
+            .collect();
+        Endpoints(endpoints)
+    }
+}
+
+/// The number of addresses in the network that endpoints may be assigned,
+/// excluding the network address itself.
+pub(crate) fn capacity(cidr: &Cidr) -> usize {
+    let bits = match cidr.addr() {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let host_bits = bits - u32::from(cidr.prefix_len());
+    1usize
+        .checked_shl(host_bits)
+        .map(|n| n - 1)
+        .unwrap_or_else(usize::max_value)
+}
+
+/// Returns the `host`th address in the network.
+pub(crate) fn host_addr(cidr: &Cidr, host: usize) -> IpAddr {
+    match cidr.addr() {
+        IpAddr::V4(net) => IpAddr::from((u32::from(net) + host as u32).to_be_bytes()),
+        IpAddr::V6(net) => IpAddr::from((u128::from(net) + host as u128).to_be_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator() -> EndpointsGenerator {
+        EndpointsGenerator::new("10.1.0.0/16".parse().unwrap(), 8080, 100)
+            .weight(Weight::Uniform(1, 100))
+            .h2(0.5)
+    }
+
+    fn weight(meta: &EndpointMeta) -> u64 {
+        serde_json::to_value(meta).unwrap()["weight"]
+            .as_u64()
+            .unwrap()
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_endpoints() {
+        let a = generator().seed(7).generate();
+        assert_eq!(a, generator().seed(7).generate());
+        assert_ne!(a, generator().seed(8).generate());
+    }
+
+    #[test]
+    fn generates_distinct_addresses_in_the_network() {
+        let endpoints = generator().generate();
+        assert_eq!(endpoints.0.len(), 100);
+        for (addr, meta) in endpoints.0.iter() {
+            assert_eq!(addr.port(), 8080);
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    assert_eq!(&ip.octets()[..2], &[10, 1]);
+                    assert_ne!(&ip.octets()[2..], &[0, 0], "network address");
+                }
+                ip => panic!("unexpected address {}", ip),
+            }
+            let weight = weight(meta);
+            assert!(weight >= 1 && weight <= 100, "weight {}", weight);
+        }
+    }
+
+    #[test]
+    fn fills_small_networks() {
+        let cidr = "192.168.0.0/30".parse().unwrap();
+        assert_eq!(capacity(&cidr), 3);
+        let endpoints = EndpointsGenerator::new(cidr, 80, 3).generate();
+        let mut addrs = endpoints
+            .0
+            .keys()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        addrs.sort();
+        assert_eq!(
+            addrs,
+            ["192.168.0.1:80", "192.168.0.2:80", "192.168.0.3:80"]
+        );
+        assert!(endpoints.0.values().all(|meta| weight(meta) == 10_000));
+    }
+
+    #[test]
+    fn capacity_excludes_the_network_address() {
+        assert_eq!(capacity(&"10.0.0.1/32".parse().unwrap()), 0);
+        assert_eq!(capacity(&"10.0.0.0/24".parse().unwrap()), 255);
+        assert_eq!(capacity(&"fd00::/120".parse().unwrap()), 255);
+        assert_eq!(capacity(&"fd00::/0".parse().unwrap()), usize::max_value());
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_if_the_network_is_too_small() {
+        EndpointsGenerator::new("10.0.0.0/30".parse().unwrap(), 80, 4).generate();
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    health::ServingStatus, tcp_incoming, Controller, DstSender, DstService, Error, FinalUpdate,
+    IdentityHandle, IdentityService, Teardown, TlsConfig,
+};
+use futures::prelude::*;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+use tracing_futures::Instrument;
+
+/// A controller running in the background on an ephemeral localhost port, for
+/// embedding in tests.
+///
+/// The controller is shut down gracefully when this is dropped.
+#[derive(Debug)]
+pub struct MockController {
+    addr: SocketAddr,
+    dst: DstSender,
+    dst_svc: DstService,
+    identity: IdentityHandle,
+    health: watch::Receiver<ServingStatus>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<Result<(), Error>>>,
+}
+
+// === impl MockController ===
+
+impl MockController {
+    /// Spawns a controller that initially serves no destinations or
+    /// identities.
+    pub async fn spawn() -> Result<Self, Error> {
+        Self::spawn_with(DstService::empty(), IdentityService::default()).await
+    }
+
+    /// Spawns a controller serving the given services.
+    pub async fn spawn_with(
+        services: (DstSender, DstService),
+        identity_svc: IdentityService,
+    ) -> Result<Self, Error> {
+        Self::spawn_with_tls(services, identity_svc, None).await
+    }
+
+    /// Spawns a controller serving the given services over TLS, if `tls` is
+    /// set.
+    pub async fn spawn_with_tls(
+        (dst, dst_svc): (DstSender, DstService),
+        identity_svc: IdentityService,
+        tls: Option<TlsConfig>,
+    ) -> Result<Self, Error> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let identity = identity_svc.handle();
+        let (shutdown, signal) = oneshot::channel();
+        let controller = Controller::new(dst_svc.clone(), identity_svc)
+            .with_tls(tls)
+            .with_shutdown(
+                signal.map(|_| ()),
+                FinalUpdate::NoEndpoints,
+                Duration::from_secs(1),
+            );
+
+        let health = controller.health();
+        let serve = controller.serve_with_incoming(tcp_incoming(listener));
+        let span = tracing::info_span!("MockController", listen.addr = %addr);
+        let task = tokio::spawn(serve.instrument(span));
+
+        Ok(Self {
+            addr,
+            dst,
+            dst_svc,
+            identity,
+            health,
+            shutdown: Some(shutdown),
+            task: Some(task),
+        })
+    }
+
+    /// The address the controller is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Changes the destinations served by the controller.
+    pub fn dst(&mut self) -> &mut DstSender {
+        &mut self.dst
+    }
+
+    /// Simulates a controller restart, tearing down all open destination
+    /// streams with `teardown` and rejecting requests for `outage`.
+    ///
+    /// The destinations being served are kept unless they are replaced with
+    /// `DstSender::replace`.
+    pub fn restart(&self, teardown: Teardown, outage: Duration) {
+        self.dst_svc.restart(teardown, outage)
+    }
+
+    /// Changes the identities served by the controller.
+    pub fn identity(&self) -> &IdentityHandle {
+        &self.identity
+    }
+
+    /// Resolves once the controller is accepting connections and reports
+    /// itself as serving to health checks, or once it has stopped.
+    pub fn ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut health = self.health.clone();
+        async move {
+            while let Some(status) = health.recv().await {
+                if status == ServingStatus::Serving {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Shuts down the controller gracefully, returning the error it failed
+    /// with, if any.
+    ///
+    /// Open `Get` streams are sent `NoEndpoints { exists: false }`, and open
+    /// connections are given a second to close.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        match self.task.take() {
+            Some(task) => task.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MockController {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
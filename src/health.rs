@@ -0,0 +1,136 @@
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tracing_futures::Instrument;
+
+mod pb {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub(crate) use self::pb::{health_check_response::ServingStatus, health_server::HealthServer};
+use self::pb::{health_server::Health, HealthCheckRequest, HealthCheckResponse};
+
+/// Serves the `grpc.health.v1.Health` service for the services on a
+/// listener.
+///
+/// All services share the same status, which is also reported for the
+/// empty service name.
+#[derive(Clone, Debug)]
+pub(crate) struct HealthService {
+    services: Arc<Vec<String>>,
+    status: watch::Receiver<ServingStatus>,
+}
+
+// === impl HealthService ===
+
+impl HealthService {
+    pub(crate) fn new(services: Vec<String>, status: watch::Receiver<ServingStatus>) -> Self {
+        Self {
+            services: Arc::new(services),
+            status,
+        }
+    }
+
+    fn is_known(&self, service: &str) -> bool {
+        service.is_empty() || self.services.iter().any(|s| s == service)
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = mpsc::Receiver<Result<HealthCheckResponse, tonic::Status>>;
+
+    async fn check(
+        &self,
+        req: tonic::Request<HealthCheckRequest>,
+    ) -> Result<tonic::Response<HealthCheckResponse>, tonic::Status> {
+        let HealthCheckRequest { service } = req.into_inner();
+        if !self.is_known(&service) {
+            return Err(tonic::Status::not_found(format!(
+                "unknown service '{}'",
+                service
+            )));
+        }
+
+        let status = *self.status.borrow();
+        Ok(tonic::Response::new(HealthCheckResponse {
+            status: status as i32,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        req: tonic::Request<HealthCheckRequest>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let HealthCheckRequest { service } = req.into_inner();
+        let known = self.is_known(&service);
+        let mut status_rx = self.status.clone();
+
+        let (mut tx, rx) = mpsc::channel(1);
+        tokio::spawn(
+            async move {
+                let mut prev = None;
+                while let Some(status) = status_rx.recv().await {
+                    // Unknown services are never known later, but the stream
+                    // is kept open, as the health checking protocol requires.
+                    let status = if known {
+                        status
+                    } else {
+                        ServingStatus::ServiceUnknown
+                    };
+                    if prev == Some(status) {
+                        continue;
+                    }
+                    prev = Some(status);
+
+                    tracing::debug!(?status);
+                    let rsp = HealthCheckResponse {
+                        status: status as i32,
+                    };
+                    if tx.send(Ok(rsp)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("Health::watch", %service)),
+        );
+
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pb::health_client::HealthClient;
+    use super::*;
+    use crate::{Error, MockController};
+
+    #[tokio::test]
+    async fn checks_served_services() -> Result<(), Error> {
+        let controller = MockController::spawn().await?;
+        controller.ready().await;
+        let mut client = HealthClient::connect(format!("http://{}", controller.addr())).await?;
+
+        for service in &[
+            "",
+            "io.linkerd.proxy.destination.Destination",
+            "io.linkerd.proxy.identity.Identity",
+        ] {
+            let req = HealthCheckRequest {
+                service: service.to_string(),
+            };
+            let rsp = client.check(req).await?.into_inner();
+            assert_eq!(rsp.status, ServingStatus::Serving as i32, "{:?}", service);
+        }
+
+        let req = HealthCheckRequest {
+            service: "io.linkerd.proxy.unknown.Unknown".to_string(),
+        };
+        let status = client
+            .check(req)
+            .await
+            .expect_err("service must be unknown");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        controller.shutdown().await
+    }
+}
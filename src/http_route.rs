@@ -0,0 +1,207 @@
+use crate::proxy_api::{grpc_route, http_route as pb, http_types};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Matches HTTP requests by path, method, and headers, all of which must
+/// match.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct HttpRouteMatch {
+    #[serde(default)]
+    pub path: Option<PathMatch>,
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Headers that must have exactly these values.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Matches gRPC requests by service, method, and headers, all of which must
+/// match.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct GrpcRouteMatch {
+    /// A fully-qualified service name, like `io.linkerd.proxy.outbound.OutboundPolicies`.
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Headers that must have exactly these values.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathMatch {
+    Exact(String),
+    Prefix(String),
+    Regex(String),
+}
+
+// === impl HttpRouteMatch ===
+
+impl HttpRouteMatch {
+    pub(crate) fn to_pb(&self) -> pb::HttpRouteMatch {
+        let path = self.path.as_ref().map(|path| pb::PathMatch {
+            kind: Some(match path {
+                PathMatch::Exact(path) => pb::path_match::Kind::Exact(path.clone()),
+                PathMatch::Prefix(path) => pb::path_match::Kind::Prefix(path.clone()),
+                PathMatch::Regex(path) => pb::path_match::Kind::Regex(path.clone()),
+            }),
+        });
+
+        pb::HttpRouteMatch {
+            path,
+            headers: headers_to_pb(&self.headers),
+            query_params: Vec::new(),
+            method: self.method.as_ref().map(|method| method_to_pb(method)),
+        }
+    }
+}
+
+// === impl GrpcRouteMatch ===
+
+impl GrpcRouteMatch {
+    pub(crate) fn to_pb(&self) -> grpc_route::GrpcRouteMatch {
+        let rpc = grpc_route::GrpcRpcMatch {
+            service: self.service.clone().unwrap_or_default(),
+            method: self.method.clone().unwrap_or_default(),
+        };
+        grpc_route::GrpcRouteMatch {
+            rpc: Some(rpc),
+            headers: headers_to_pb(&self.headers),
+        }
+    }
+}
+
+fn headers_to_pb(headers: &BTreeMap<String, String>) -> Vec<pb::HeaderMatch> {
+    headers
+        .iter()
+        .map(|(name, value)| pb::HeaderMatch {
+            name: name.clone(),
+            value: Some(pb::header_match::Value::Exact(value.clone().into_bytes())),
+        })
+        .collect()
+}
+
+fn method_to_pb(method: &str) -> http_types::HttpMethod {
+    use http_types::http_method::{Registered, Type};
+
+    let registered = match method {
+        "GET" => Registered::Get,
+        "POST" => Registered::Post,
+        "PUT" => Registered::Put,
+        "DELETE" => Registered::Delete,
+        "PATCH" => Registered::Patch,
+        "OPTIONS" => Registered::Options,
+        "CONNECT" => Registered::Connect,
+        "HEAD" => Registered::Head,
+        "TRACE" => Registered::Trace,
+        _ => {
+            return http_types::HttpMethod {
+                r#type: Some(Type::Unregistered(method.to_string())),
+            }
+        }
+    };
+    http_types::HttpMethod {
+        r#type: Some(Type::Registered(registered as i32)),
+    }
+}
+
+/// Matches `*.example.com` as a suffix, and any other host exactly.
+pub(crate) fn host_match(host: &str) -> pb::HostMatch {
+    let r#match = if host.starts_with("*.") {
+        let reverse_labels = host["*.".len()..]
+            .split('.')
+            .rev()
+            .map(String::from)
+            .collect();
+        pb::host_match::Match::Suffix(pb::host_match::Suffix { reverse_labels })
+    } else {
+        pb::host_match::Match::Exact(host.to_string())
+    };
+    pb::HostMatch {
+        r#match: Some(r#match),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_types::http_method::{Registered, Type};
+
+    #[test]
+    fn translates_http_matches() {
+        let route = HttpRouteMatch {
+            path: Some(PathMatch::Regex("/users/[0-9]+".to_string())),
+            method: Some("POST".to_string()),
+            headers: vec![("x-canary".to_string(), "true".to_string())]
+                .into_iter()
+                .collect(),
+        }
+        .to_pb();
+        assert_eq!(
+            route.path,
+            Some(pb::PathMatch {
+                kind: Some(pb::path_match::Kind::Regex("/users/[0-9]+".to_string())),
+            })
+        );
+        assert_eq!(
+            route.method.and_then(|m| m.r#type),
+            Some(Type::Registered(Registered::Post as i32))
+        );
+        assert_eq!(
+            route.headers,
+            [pb::HeaderMatch {
+                name: "x-canary".to_string(),
+                value: Some(pb::header_match::Value::Exact(b"true".to_vec())),
+            }]
+        );
+
+        let method = HttpRouteMatch {
+            method: Some("PURGE".to_string()),
+            ..HttpRouteMatch::default()
+        }
+        .to_pb()
+        .method;
+        assert_eq!(
+            method.and_then(|m| m.r#type),
+            Some(Type::Unregistered("PURGE".to_string()))
+        );
+
+        // An empty match matches every request.
+        let all = HttpRouteMatch::default().to_pb();
+        assert_eq!((all.path, all.method), (None, None));
+        assert!(all.headers.is_empty());
+    }
+
+    #[test]
+    fn translates_grpc_matches() {
+        let route = GrpcRouteMatch {
+            service: Some("io.linkerd.proxy.outbound.OutboundPolicies".to_string()),
+            method: None,
+            headers: BTreeMap::new(),
+        }
+        .to_pb();
+        assert_eq!(
+            route.rpc,
+            Some(grpc_route::GrpcRpcMatch {
+                service: "io.linkerd.proxy.outbound.OutboundPolicies".to_string(),
+                method: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn matches_hosts_exactly_or_by_suffix() {
+        assert_eq!(
+            host_match("*.example.com").r#match,
+            Some(pb::host_match::Match::Suffix(pb::host_match::Suffix {
+                reverse_labels: vec!["com".to_string(), "example".to_string()],
+            }))
+        );
+        assert_eq!(
+            host_match("www.example.com").r#match,
+            Some(pb::host_match::Match::Exact("www.example.com".to_string()))
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use crate::{
+    http_route::{host_match, HttpRouteMatch},
+    policy,
+    proxy_api::{
+        inbound::{self as pb, inbound_server_policies_server::InboundServerPolicies},
+        meta::Metadata,
+    },
+    spec::deserialize_duration,
+    Cidr, Error,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing_futures::Instrument;
+
+pub(crate) use self::pb::inbound_server_policies_server::InboundServerPoliciesServer;
+
+/// Serves the inbound policies of proxies' ports.
+#[derive(Clone, Debug)]
+pub struct InboundPolicyService {
+    policies: policy::Store<u16, InboundPolicy>,
+}
+
+/// Changes the policies served by an `InboundPolicyService` at runtime.
+#[derive(Debug)]
+pub struct InboundPolicySender {
+    policies: policy::Sender<u16, InboundPolicy>,
+}
+
+/// Loads the policies in a directory into an `InboundPolicySender`, and then
+/// keeps them up to date as the directory changes.
+///
+/// Each file is named for the port its policy applies to, e.g. `8080.yaml`,
+/// and contains the JSON or YAML representation of an `InboundPolicy`.
+#[derive(Debug)]
+pub struct InboundPolicyWatcher {
+    dir: PathBuf,
+    sender: InboundPolicySender,
+    ready: Option<oneshot::Sender<()>>,
+}
+
+/// The policy of an inbound port.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct InboundPolicy {
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// How long to wait to detect the protocol, if it is not known.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub detect_timeout: Option<Duration>,
+    #[serde(default)]
+    pub authorizations: Vec<Authorization>,
+    /// If there are no routes, all requests are routed to a default route.
+    #[serde(default)]
+    pub routes: Vec<InboundRoute>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Detect,
+    Http1,
+    Http2,
+    Grpc,
+    Opaque,
+    Tls,
+}
+
+/// Permits clients from `networks` that are authenticated by
+/// `authentication`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Authorization {
+    pub name: String,
+    /// If empty, clients from all networks are permitted.
+    #[serde(default)]
+    pub networks: Vec<Cidr>,
+    #[serde(default)]
+    pub authentication: Authentication,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Authentication {
+    Unauthenticated,
+    /// Requires mesh TLS from a client with one of `identities`, or with an
+    /// identity ending in one of `suffixes`. If both are empty, any client
+    /// identity is permitted.
+    MeshTls {
+        #[serde(default)]
+        identities: Vec<String>,
+        #[serde(default)]
+        suffixes: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct InboundRoute {
+    pub name: String,
+    /// Hosts such as `www.example.com` or `*.example.com`. If empty, all
+    /// hosts match.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// If empty, all requests match.
+    #[serde(default)]
+    pub matches: Vec<HttpRouteMatch>,
+    /// If empty, the port's authorizations apply.
+    #[serde(default)]
+    pub authorizations: Vec<Authorization>,
+}
+
+type GrpcResult<T> = Result<T, tonic::Status>;
+
+// === impl InboundPolicyService ===
+
+impl InboundPolicyService {
+    pub fn empty() -> (InboundPolicySender, InboundPolicyService) {
+        Self::new(HashMap::new())
+    }
+
+    pub fn new(
+        policies: HashMap<u16, InboundPolicy>,
+    ) -> (InboundPolicySender, InboundPolicyService) {
+        let (policies, store) = policy::Store::new(policies);
+        (InboundPolicySender { policies }, Self { policies: store })
+    }
+
+    /// Ends all open streams, and any streams opened later.
+    pub fn drain(&self) {
+        self.policies.drain()
+    }
+
+    async fn policy_rx(&self, spec: pb::PortSpec) -> GrpcResult<watch::Receiver<InboundPolicy>> {
+        let port = match spec.port {
+            port if port > 0 && port <= u16::max_value() as u32 => port as u16,
+            _ => return Err(tonic::Status::invalid_argument("invalid port")),
+        };
+        match self.policies.get(&port).await {
+            Some(rx) => Ok(rx),
+            None => {
+                tracing::info!("Does not exist");
+                Err(tonic::Status::invalid_argument("not configured"))
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl InboundServerPolicies for InboundPolicyService {
+    type WatchPortStream = mpsc::Receiver<GrpcResult<pb::Server>>;
+
+    async fn get_port(
+        &self,
+        req: tonic::Request<pb::PortSpec>,
+    ) -> GrpcResult<tonic::Response<pb::Server>> {
+        let spec = req.into_inner();
+        let span = tracing::info_span!("get_port", %spec.workload, spec.port);
+        let rx = self.policy_rx(spec).instrument(span).await?;
+        let server = rx.borrow().to_pb();
+        Ok(tonic::Response::new(server))
+    }
+
+    async fn watch_port(
+        &self,
+        req: tonic::Request<pb::PortSpec>,
+    ) -> GrpcResult<tonic::Response<Self::WatchPortStream>> {
+        let spec = req.into_inner();
+        let span = tracing::info_span!("watch_port", %spec.workload, spec.port);
+        let policy_rx = self.policy_rx(spec).instrument(span.clone()).await?;
+        tracing::info!(parent: &span, "Serving policy");
+        let rx = self.policies.stream(policy_rx, span, InboundPolicy::to_pb);
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+// === impl InboundPolicySender ===
+
+impl InboundPolicySender {
+    #[tracing::instrument(skip(self), name = "InboundPolicySender::send_policy", level = "info")]
+    pub async fn send_policy(&mut self, port: u16, policy: InboundPolicy) -> Result<(), Error> {
+        self.policies.send(port, policy).await
+    }
+
+    #[tracing::instrument(
+        skip(self),
+        name = "InboundPolicySender::delete_policy",
+        level = "info"
+    )]
+    pub async fn delete_policy(&mut self, port: u16) {
+        self.policies.delete(&port).await
+    }
+}
+
+// === impl InboundPolicyWatcher ===
+
+impl InboundPolicyWatcher {
+    pub fn new(dir: PathBuf, sender: InboundPolicySender) -> Self {
+        Self {
+            dir,
+            sender,
+            ready: None,
+        }
+    }
+
+    /// Notifies `ready` once the policies already in the directory have been
+    /// loaded.
+    pub fn with_ready(self, ready: oneshot::Sender<()>) -> Self {
+        Self {
+            ready: Some(ready),
+            ..self
+        }
+    }
+
+    pub async fn watch(&mut self) -> Result<(), Error> {
+        let ready = self.ready.take();
+        policy::watch_dir(&self.dir, &mut self.sender.policies, ready).await
+    }
+}
+
+// === impl InboundPolicy ===
+
+impl InboundPolicy {
+    fn to_pb(&self) -> pb::Server {
+        let authorizations = self
+            .authorizations
+            .iter()
+            .map(Authorization::to_pb)
+            .collect::<Vec<_>>();
+
+        let routes = if self.routes.is_empty() {
+            vec![pb::HttpRoute {
+                metadata: Some(Metadata::for_default("default")),
+                hosts: Vec::new(),
+                authorizations: authorizations.clone(),
+                rules: vec![pb::http_route::Rule {
+                    matches: vec![HttpRouteMatch::default().to_pb()],
+                }],
+            }]
+        } else {
+            self.routes
+                .iter()
+                .map(|route| route.to_pb(&authorizations))
+                .collect()
+        };
+
+        use pb::proxy_protocol::{Detect, Grpc, Http1, Http2, Kind, Opaque, Tls};
+        let kind = match self.protocol {
+            Protocol::Detect => Kind::Detect(Detect {
+                timeout: Some(
+                    self.detect_timeout
+                        .unwrap_or_else(|| Duration::from_secs(10))
+                        .into(),
+                ),
+                http_routes: routes,
+            }),
+            Protocol::Http1 => Kind::Http1(Http1 { routes }),
+            Protocol::Http2 => Kind::Http2(Http2 { routes }),
+            Protocol::Grpc => Kind::Grpc(Grpc {}),
+            Protocol::Opaque => Kind::Opaque(Opaque {}),
+            Protocol::Tls => Kind::Tls(Tls {}),
+        };
+
+        pb::Server {
+            protocol: Some(pb::ProxyProtocol { kind: Some(kind) }),
+            server_ips: Vec::new(),
+            authorizations,
+            labels: self.labels.clone().into_iter().collect(),
+        }
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::Detect
+    }
+}
+
+// === impl Authorization ===
+
+impl Authorization {
+    fn to_pb(&self) -> pb::Authz {
+        let networks = if self.networks.is_empty() {
+            vec!["0.0.0.0/0", "::/0"]
+                .into_iter()
+                .map(|net| net.parse().expect("network must be valid"))
+                .collect()
+        } else {
+            self.networks.clone()
+        };
+
+        pb::Authz {
+            networks: networks
+                .into_iter()
+                .map(|net| pb::Network {
+                    net: Some(net.into()),
+                    except: Vec::new(),
+                })
+                .collect(),
+            authentication: Some(self.authentication.to_pb()),
+            labels: HashMap::new(),
+            metadata: Some(Metadata::resource("AuthorizationPolicy", &self.name)),
+        }
+    }
+}
+
+// === impl Authentication ===
+
+impl Default for Authentication {
+    fn default() -> Self {
+        Authentication::Unauthenticated
+    }
+}
+
+impl Authentication {
+    fn to_pb(&self) -> pb::Authn {
+        use pb::authn::{permit_mesh_tls, Permit, PermitMeshTls, PermitUnauthenticated};
+
+        let permit = match self {
+            Authentication::Unauthenticated => Permit::Unauthenticated(PermitUnauthenticated {}),
+            Authentication::MeshTls {
+                identities,
+                suffixes,
+            } => {
+                let clients = if identities.is_empty() && suffixes.is_empty() {
+                    permit_mesh_tls::Clients::Unauthenticated(PermitUnauthenticated {})
+                } else {
+                    permit_mesh_tls::Clients::Identities(permit_mesh_tls::PermitClientIdentities {
+                        identities: identities
+                            .iter()
+                            .map(|name| pb::Identity { name: name.clone() })
+                            .collect(),
+                        suffixes: suffixes
+                            .iter()
+                            .map(|suffix| pb::IdentitySuffix {
+                                parts: suffix
+                                    .split('.')
+                                    .filter(|part| !part.is_empty())
+                                    .map(String::from)
+                                    .collect(),
+                            })
+                            .collect(),
+                    })
+                };
+                Permit::MeshTls(PermitMeshTls {
+                    clients: Some(clients),
+                })
+            }
+        };
+        pb::Authn {
+            permit: Some(permit),
+        }
+    }
+}
+
+// === impl InboundRoute ===
+
+impl InboundRoute {
+    fn to_pb(&self, default_authorizations: &[pb::Authz]) -> pb::HttpRoute {
+        let authorizations = if self.authorizations.is_empty() {
+            default_authorizations.to_vec()
+        } else {
+            self.authorizations
+                .iter()
+                .map(Authorization::to_pb)
+                .collect()
+        };
+
+        let matches = if self.matches.is_empty() {
+            vec![HttpRouteMatch::default().to_pb()]
+        } else {
+            self.matches.iter().map(HttpRouteMatch::to_pb).collect()
+        };
+
+        pb::HttpRoute {
+            metadata: Some(Metadata::resource("HTTPRoute", &self.name)),
+            hosts: self.hosts.iter().map(|host| host_match(host)).collect(),
+            authorizations,
+            rules: vec![pb::http_route::Rule { matches }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_api::{http_route as route_pb, net};
+
+    fn policy(yaml: &str) -> InboundPolicy {
+        serde_yaml::from_str(yaml).expect("policy must be valid")
+    }
+
+    fn network(cidr: &str) -> Option<net::IpNetwork> {
+        Some(cidr.parse::<Cidr>().unwrap().into())
+    }
+
+    fn networks(authz: &pb::Authz) -> Vec<Option<net::IpNetwork>> {
+        authz.networks.iter().map(|net| net.net.clone()).collect()
+    }
+
+    fn http_routes(server: &pb::Server) -> &[pb::HttpRoute] {
+        use pb::proxy_protocol::Kind;
+
+        match server.protocol.as_ref().and_then(|p| p.kind.as_ref()) {
+            Some(Kind::Detect(detect)) => &detect.http_routes,
+            Some(Kind::Http1(http)) => &http.routes,
+            Some(Kind::Http2(http)) => &http.routes,
+            kind => panic!("unexpected protocol {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn serves_a_default_route_with_the_ports_authorizations() {
+        let server = policy(
+            r#"
+authorizations:
+  - name: all
+  - name: mesh
+    networks: ["10.0.0.0/8"]
+    authentication:
+      mesh_tls:
+        suffixes: ["ns.serviceaccount.identity.linkerd.cluster.local"]
+"#,
+        )
+        .to_pb();
+
+        let all = &server.authorizations[0];
+        assert_eq!(networks(all), [network("0.0.0.0/0"), network("::/0")]);
+        assert_eq!(
+            all.metadata,
+            Some(Metadata::resource("AuthorizationPolicy", "all"))
+        );
+        assert_eq!(
+            all.authentication,
+            Some(Authentication::Unauthenticated.to_pb())
+        );
+
+        let mesh = &server.authorizations[1];
+        assert_eq!(networks(mesh), [network("10.0.0.0/8")]);
+        use pb::authn::{permit_mesh_tls::Clients, Permit};
+        match mesh.authentication.as_ref().and_then(|a| a.permit.as_ref()) {
+            Some(Permit::MeshTls(tls)) => match tls.clients {
+                Some(Clients::Identities(ref ids)) => {
+                    assert!(ids.identities.is_empty());
+                    assert_eq!(
+                        ids.suffixes[0].parts,
+                        [
+                            "ns",
+                            "serviceaccount",
+                            "identity",
+                            "linkerd",
+                            "cluster",
+                            "local"
+                        ]
+                    );
+                }
+                ref clients => panic!("unexpected clients {:?}", clients),
+            },
+            permit => panic!("unexpected permit {:?}", permit),
+        }
+
+        match server.protocol.as_ref().and_then(|p| p.kind.as_ref()) {
+            Some(pb::proxy_protocol::Kind::Detect(detect)) => {
+                assert_eq!(detect.timeout, Some(Duration::from_secs(10).into()));
+            }
+            kind => panic!("unexpected protocol {:?}", kind),
+        }
+        let routes = http_routes(&server);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].metadata, Some(Metadata::for_default("default")));
+        assert_eq!(routes[0].authorizations, server.authorizations);
+        assert_eq!(
+            routes[0].rules[0].matches,
+            [HttpRouteMatch::default().to_pb()]
+        );
+    }
+
+    #[test]
+    fn routes_have_their_own_authorizations_and_matches() {
+        let server = policy(
+            r#"
+protocol: http2
+authorizations:
+  - name: all
+routes:
+  - name: web
+    hosts: ["*.example.com", "www.example.org"]
+    matches:
+      - path:
+          prefix: /api
+        method: GET
+  - name: admin
+    authorizations:
+      - name: admin
+        authentication:
+          mesh_tls: {}
+"#,
+        )
+        .to_pb();
+        let routes = http_routes(&server);
+        assert_eq!(routes.len(), 2);
+
+        let web = &routes[0];
+        assert_eq!(web.metadata, Some(Metadata::resource("HTTPRoute", "web")));
+        assert_eq!(web.authorizations, server.authorizations);
+        assert_eq!(
+            web.hosts,
+            [host_match("*.example.com"), host_match("www.example.org")]
+        );
+        match web.hosts[0].r#match {
+            Some(route_pb::host_match::Match::Suffix(ref suffix)) => {
+                assert_eq!(suffix.reverse_labels, ["com", "example"]);
+            }
+            ref host => panic!("unexpected host match {:?}", host),
+        }
+        let matches = &web.rules[0].matches;
+        assert_eq!(
+            matches[0].path,
+            Some(route_pb::PathMatch {
+                kind: Some(route_pb::path_match::Kind::Prefix("/api".to_string())),
+            })
+        );
+        assert!(matches[0].method.is_some());
+
+        let admin = &routes[1];
+        assert_eq!(admin.authorizations.len(), 1);
+        assert_eq!(
+            admin.authorizations[0].metadata,
+            Some(Metadata::resource("AuthorizationPolicy", "admin"))
+        );
+        // Mesh TLS without identities permits any authenticated client.
+        use pb::authn::{permit_mesh_tls::Clients, Permit, PermitUnauthenticated};
+        match admin.authorizations[0]
+            .authentication
+            .as_ref()
+            .and_then(|a| a.permit.as_ref())
+        {
+            Some(Permit::MeshTls(tls)) => assert_eq!(
+                tls.clients,
+                Some(Clients::Unauthenticated(PermitUnauthenticated {}))
+            ),
+            permit => panic!("unexpected permit {:?}", permit),
+        }
+    }
+
+    #[tokio::test]
+    async fn watches_are_sent_policies_until_they_are_deleted() -> Result<(), Error> {
+        let (mut sender, svc) = InboundPolicyService::empty();
+        let spec = || {
+            tonic::Request::new(pb::PortSpec {
+                workload: "web".to_string(),
+                port: 8080,
+            })
+        };
+        let status = svc
+            .watch_port(spec())
+            .await
+            .expect_err("port must not exist");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        sender.send_policy(8080, InboundPolicy::default()).await?;
+        let mut watch = svc.watch_port(spec()).await?.into_inner();
+        let server = watch.recv().await.expect("policy must be served")?;
+        assert_eq!(server, InboundPolicy::default().to_pb());
+
+        let opaque = InboundPolicy {
+            protocol: Protocol::Opaque,
+            ..InboundPolicy::default()
+        };
+        sender.send_policy(8080, opaque.clone()).await?;
+        let server = watch.recv().await.expect("update must be served")?;
+        assert_eq!(server, opaque.to_pb());
+        assert_eq!(svc.get_port(spec()).await?.into_inner(), opaque.to_pb());
+
+        sender.delete_policy(8080).await;
+        assert!(watch.recv().await.is_none(), "watch must end");
+        let status = svc
+            .get_port(spec())
+            .await
+            .expect_err("port must be deleted");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        Ok(())
+    }
+}
@@ -0,0 +1,1225 @@
+//! Translates Kubernetes manifests, like those output by `kubectl get -o yaml`,
+//! into the destinations that a Destination controller would serve for them.
+
+use crate::{profile::Quantity, Dst, EndpointMeta, Endpoints, Error, Overrides, ServiceProfile};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+/// The label that associates an `EndpointSlice` with its `Service`.
+const SERVICE_NAME_LABEL: &str = "kubernetes.io/service-name";
+
+/// Kubernetes `Service`, `Endpoints`, `EndpointSlice` and `Pod` resources,
+/// Linkerd `ServiceProfile`s, SMI `TrafficSplit`s and Gateway API
+/// `HTTPRoute`s.
+///
+/// Each service port is served as a destination named like
+/// `svc.ns.svc.cluster.local:port`, with the ready addresses of the service's
+/// endpoints, on the port that the service port targets. Endpoints are
+/// labeled with their namespace, service and pod, and with the labels of
+/// their pod if it is among the manifests.
+#[derive(Clone, Debug)]
+pub struct K8sManifests {
+    cluster_domain: String,
+    services: HashMap<Key, Service>,
+    endpoints: HashMap<Key, EndpointsObject>,
+    slices: HashMap<Key, EndpointSlice>,
+    pods: HashMap<Key, Pod>,
+    /// Service profiles, by the name of the destination they are for.
+    profiles: HashMap<String, ServiceProfile>,
+    splits: HashMap<Key, TrafficSplit>,
+    http_routes: HashMap<Key, HttpRoute>,
+}
+
+/// A namespace and name.
+type Key = (String, String);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind")]
+enum Object {
+    Service(Service),
+    Endpoints(EndpointsObject),
+    EndpointSlice(EndpointSlice),
+    Pod(Pod),
+    ServiceProfile(ServiceProfile),
+    TrafficSplit(TrafficSplit),
+    #[serde(rename = "HTTPRoute")]
+    HttpRoute(HttpRoute),
+    List {
+        #[serde(default)]
+        items: Vec<Object>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ObjectMeta {
+    name: String,
+    #[serde(default)]
+    namespace: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectReference {
+    #[serde(default)]
+    kind: Option<String>,
+    name: String,
+    #[serde(default)]
+    namespace: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Service {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: ServiceSpec,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct ServiceSpec {
+    #[serde(default)]
+    ports: Vec<ServicePort>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServicePort {
+    #[serde(default)]
+    name: Option<String>,
+    port: u16,
+    #[serde(default)]
+    target_port: Option<IntOrString>,
+    #[serde(default)]
+    protocol: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum IntOrString {
+    Int(u16),
+    /// Named target ports are matched by the name of the service port.
+    String(serde::de::IgnoredAny),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct EndpointsObject {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    subsets: Vec<EndpointSubset>,
+}
+
+/// Not-ready addresses are ignored.
+#[derive(Clone, Debug, Deserialize)]
+struct EndpointSubset {
+    #[serde(default)]
+    addresses: Vec<EndpointAddress>,
+    #[serde(default)]
+    ports: Vec<EndpointPort>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointAddress {
+    ip: IpAddr,
+    #[serde(default)]
+    target_ref: Option<ObjectReference>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct EndpointPort {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    protocol: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct EndpointSlice {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    endpoints: Vec<SliceEndpoint>,
+    #[serde(default)]
+    ports: Vec<EndpointPort>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SliceEndpoint {
+    /// May be FQDNs, which are ignored.
+    addresses: Vec<String>,
+    #[serde(default)]
+    conditions: Conditions,
+    #[serde(default)]
+    target_ref: Option<ObjectReference>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct Conditions {
+    /// An unknown readiness is interpreted as ready.
+    #[serde(default)]
+    ready: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Pod {
+    metadata: ObjectMeta,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TrafficSplit {
+    metadata: ObjectMeta,
+    spec: TrafficSplitSpec,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TrafficSplitSpec {
+    /// The apex service, which may be a fully-qualified name.
+    service: String,
+    #[serde(default)]
+    backends: Vec<TrafficSplitBackend>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TrafficSplitBackend {
+    service: String,
+    weight: Quantity,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct HttpRoute {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: HttpRouteSpec,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpRouteSpec {
+    #[serde(default)]
+    parent_refs: Vec<RouteRef>,
+    #[serde(default)]
+    rules: Vec<HttpRouteRule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpRouteRule {
+    #[serde(default)]
+    backend_refs: Vec<RouteRef>,
+}
+
+/// A parent or backend reference of an `HTTPRoute`.
+#[derive(Clone, Debug, Deserialize)]
+struct RouteRef {
+    #[serde(default)]
+    group: Option<String>,
+    /// Defaults to `Gateway` for parents and to `Service` for backends.
+    #[serde(default)]
+    kind: Option<String>,
+    name: String,
+    #[serde(default)]
+    namespace: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    weight: Option<u32>,
+}
+
+/// A ready address of a service, and the ports it serves.
+#[derive(Debug)]
+struct Backend<'a> {
+    ip: IpAddr,
+    ports: &'a [EndpointPort],
+    target_ref: Option<&'a ObjectReference>,
+}
+
+// === impl K8sManifests ===
+
+impl K8sManifests {
+    /// Loads the manifests in each `.yaml`, `.yml` or `.json` file in `dir`.
+    ///
+    /// YAML files may contain several documents, and `List`s of resources
+    /// are flattened. Resources of other kinds are ignored.
+    pub fn load_dir(dir: &Path) -> Result<Self, Error> {
+        let mut manifests = Self::empty();
+
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths {
+            let is_json = match path.extension().and_then(OsStr::to_str) {
+                Some("json") => true,
+                Some("yaml") | Some("yml") => false,
+                _ => continue,
+            };
+            let contents = std::fs::read_to_string(&path)?;
+            let objects = if is_json {
+                vec![serde_json::from_str::<Object>(&contents)?]
+            } else {
+                documents(&contents)
+                    .map(serde_yaml::from_str::<Object>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("{}: {}", path.display(), e))?
+            };
+            for object in objects {
+                manifests.insert(object);
+            }
+        }
+        tracing::info!(
+            services = manifests.services.len(),
+            endpoints = manifests.endpoints.len(),
+            endpoint_slices = manifests.slices.len(),
+            pods = manifests.pods.len(),
+            service_profiles = manifests.profiles.len(),
+            traffic_splits = manifests.splits.len(),
+            http_routes = manifests.http_routes.len(),
+            "Loaded manifests"
+        );
+        Ok(manifests)
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self {
+            cluster_domain: "cluster.local".to_string(),
+            services: HashMap::new(),
+            endpoints: HashMap::new(),
+            slices: HashMap::new(),
+            pods: HashMap::new(),
+            profiles: HashMap::new(),
+            splits: HashMap::new(),
+            http_routes: HashMap::new(),
+        }
+    }
+
+    /// Sets the cluster domain that destinations are named in.
+    pub fn cluster_domain(self, cluster_domain: impl Into<String>) -> Self {
+        Self {
+            cluster_domain: cluster_domain.into(),
+            ..self
+        }
+    }
+
+    fn insert(&mut self, object: Object) {
+        match object {
+            Object::Service(svc) => {
+                self.services.insert(svc.metadata.key(), svc);
+            }
+            Object::Endpoints(eps) => {
+                self.endpoints.insert(eps.metadata.key(), eps);
+            }
+            Object::EndpointSlice(slice) => {
+                self.slices.insert(slice.metadata.key(), slice);
+            }
+            Object::Pod(pod) => {
+                self.pods.insert(pod.metadata.key(), pod);
+            }
+            Object::ServiceProfile(profile) => {
+                self.profiles.insert(profile.name().to_string(), profile);
+            }
+            Object::TrafficSplit(split) => {
+                self.splits.insert(split.metadata.key(), split);
+            }
+            Object::HttpRoute(route) => {
+                self.http_routes.insert(route.metadata.key(), route);
+            }
+            Object::List { items } => {
+                for item in items {
+                    self.insert(item);
+                }
+            }
+            Object::Other => {}
+        }
+    }
+
+    /// Adds or replaces a resource read from the Kubernetes API, which must
+    /// have its `kind` set.
+    pub(crate) fn apply(&mut self, resource: serde_json::Value) -> Result<(), Error> {
+        self.insert(serde_json::from_value(resource)?);
+        Ok(())
+    }
+
+    /// Removes a resource read from the Kubernetes API, which must have its
+    /// `kind` set.
+    pub(crate) fn delete(&mut self, resource: serde_json::Value) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Deleted {
+            kind: String,
+            metadata: ObjectMeta,
+        }
+
+        let Deleted { kind, metadata } = serde_json::from_value(resource)?;
+        let key = metadata.key();
+        match kind.as_str() {
+            "Service" => {
+                self.services.remove(&key);
+            }
+            "Endpoints" => {
+                self.endpoints.remove(&key);
+            }
+            "EndpointSlice" => {
+                self.slices.remove(&key);
+            }
+            "Pod" => {
+                self.pods.remove(&key);
+            }
+            "ServiceProfile" => {
+                self.profiles.remove(&metadata.name);
+            }
+            "TrafficSplit" => {
+                self.splits.remove(&key);
+            }
+            "HTTPRoute" => {
+                self.http_routes.remove(&key);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Removes all resources of `kind`, so that they can be listed again.
+    pub(crate) fn clear(&mut self, kind: &str) {
+        match kind {
+            "Service" => self.services.clear(),
+            "Endpoints" => self.endpoints.clear(),
+            "EndpointSlice" => self.slices.clear(),
+            "Pod" => self.pods.clear(),
+            "ServiceProfile" => self.profiles.clear(),
+            "TrafficSplit" => self.splits.clear(),
+            "HTTPRoute" => self.http_routes.clear(),
+            _ => {}
+        }
+    }
+
+    /// Returns the endpoints of each service port.
+    pub fn endpoints(&self) -> HashMap<Dst, Endpoints> {
+        let mut names = self
+            .services
+            .keys()
+            .chain(self.endpoints.keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        names.extend(self.slices.values().filter_map(|slice| {
+            let svc = slice.metadata.labels.get(SERVICE_NAME_LABEL)?;
+            Some((slice.metadata.namespace().to_string(), svc.clone()))
+        }));
+        names.sort();
+        names.dedup();
+
+        let mut dsts = HashMap::new();
+        for (ns, name) in names {
+            let backends = self.backends(&ns, &name);
+            for (port, target) in self.ports(&ns, &name, &backends) {
+                let mut endpoints = Endpoints::default();
+                for backend in backends.iter() {
+                    let target_port = match backend.target_port(&target) {
+                        Some(port) => port,
+                        None => continue,
+                    };
+                    let addr = SocketAddr::new(backend.ip, target_port);
+                    let labels = self.metric_labels(&ns, &name, backend.target_ref);
+                    let meta = EndpointMeta::new(addr, false, 10_000, labels, None, None);
+                    endpoints.0.insert(addr, meta);
+                }
+                let dst = self.dst(&ns, &name, port);
+                tracing::debug!(%dst, endpoints = endpoints.0.len());
+                dsts.insert(dst, endpoints);
+            }
+        }
+        dsts
+    }
+
+    /// Returns the service profiles, each of which is served for every port
+    /// of the destination it is named for.
+    pub fn profiles(&self) -> Vec<ServiceProfile> {
+        let mut profiles = self.profiles.values().cloned().collect::<Vec<_>>();
+        profiles.sort_by(|a, b| a.name().cmp(b.name()));
+        profiles
+    }
+
+    /// Returns the destination overrides of each port of the apex services of
+    /// traffic splits, and of the services that HTTP routes are attached to.
+    ///
+    /// Weights are served in thousandths, as the real controller does, so a
+    /// split backend weighted `500m` and a route backend weighted `1` are
+    /// served with weights of 500 and 1000. Routes' backends are weighted
+    /// across all of their rules, since destination overrides do not match
+    /// requests, and backends without a port are served on the parent's port.
+    /// Routes' parents must be services; routes attached to gateways are
+    /// ignored.
+    ///
+    /// When several resources configure the same service port, routes take
+    /// precedence over splits, and otherwise the resource that sorts first by
+    /// namespace and name wins. The others are ignored with a warning.
+    pub fn overrides(&self) -> HashMap<Dst, Overrides> {
+        let mut dsts = HashMap::new();
+        let mut routes = self.http_routes.iter().collect::<Vec<_>>();
+        routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (key, route) in routes {
+            let ns = route.metadata.namespace();
+            let mut weights = BTreeMap::<(&str, &str, Option<u16>), u32>::new();
+            let backends = route
+                .spec
+                .rules
+                .iter()
+                .flat_map(|rule| rule.backend_refs.iter())
+                .filter(|backend| backend.is_service(false));
+            for backend in backends {
+                let backend_ns = backend.namespace.as_deref().unwrap_or(ns);
+                let backend_key = (backend_ns, backend.name.as_str(), backend.port);
+                let weight = backend.weight.unwrap_or(1).saturating_mul(1000);
+                let total = weights.entry(backend_key).or_default();
+                *total = total.saturating_add(weight);
+            }
+            let parents = route
+                .spec
+                .parent_refs
+                .iter()
+                .filter(|parent| parent.is_service(true));
+            for parent in parents {
+                let parent_ns = parent.namespace.as_deref().unwrap_or(ns);
+                let ports = match parent.port {
+                    Some(port) => vec![port],
+                    None => self.service_ports(parent_ns, &parent.name),
+                };
+                for port in ports {
+                    let overrides = weights
+                        .iter()
+                        .map(|(&(backend_ns, backend, backend_port), weight)| {
+                            let dst = self.dst(backend_ns, backend, backend_port.unwrap_or(port));
+                            (dst, *weight)
+                        })
+                        .collect();
+                    let dst = self.dst(parent_ns, &parent.name, port);
+                    insert_overrides(&mut dsts, dst, overrides, "HTTPRoute", key);
+                }
+            }
+        }
+
+        let mut splits = self.splits.iter().collect::<Vec<_>>();
+        splits.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (key, split) in splits {
+            let ns = split.metadata.namespace();
+            let apex = split.spec.service.split('.').next().unwrap_or_default();
+            let mut weights = BTreeMap::<&str, u32>::new();
+            for backend in split.spec.backends.iter() {
+                match backend.weight.milli() {
+                    Ok(weight) => {
+                        let total = weights.entry(&backend.service).or_default();
+                        *total = total.saturating_add(weight);
+                    }
+                    Err(e) => tracing::warn!(
+                        %e,
+                        split = %split.metadata.name,
+                        backend = %backend.service,
+                        "Skipping backend"
+                    ),
+                }
+            }
+            for port in self.service_ports(ns, apex) {
+                let overrides = weights
+                    .iter()
+                    .map(|(backend, weight)| (self.dst(ns, backend, port), *weight))
+                    .collect();
+                insert_overrides(
+                    &mut dsts,
+                    self.dst(ns, apex, port),
+                    overrides,
+                    "TrafficSplit",
+                    key,
+                );
+            }
+        }
+        dsts
+    }
+
+    fn dst(&self, ns: &str, name: &str, port: u16) -> Dst {
+        Dst::new(format!("{}.{}.svc.{}", name, ns, self.cluster_domain), port)
+    }
+
+    /// Returns the ports of a service, as they are served by `endpoints`.
+    fn service_ports(&self, ns: &str, name: &str) -> Vec<u16> {
+        let backends = self.backends(ns, name);
+        let ports = self
+            .ports(ns, name, &backends)
+            .into_iter()
+            .map(|(port, _)| port)
+            .collect::<Vec<_>>();
+        if ports.is_empty() {
+            tracing::warn!(%ns, %name, "Service has no known ports");
+        }
+        ports
+    }
+
+    /// Returns the ready addresses of a service, from its endpoint slices if
+    /// it has any, or from its `Endpoints` otherwise.
+    fn backends(&self, ns: &str, name: &str) -> Vec<Backend<'_>> {
+        let slices = self
+            .slices
+            .values()
+            .filter(|slice| {
+                slice.metadata.namespace() == ns
+                    && slice
+                        .metadata
+                        .labels
+                        .get(SERVICE_NAME_LABEL)
+                        .map(String::as_str)
+                        == Some(name)
+            })
+            .collect::<Vec<_>>();
+        if !slices.is_empty() {
+            return slices
+                .into_iter()
+                .flat_map(|slice| {
+                    slice
+                        .endpoints
+                        .iter()
+                        .filter(|ep| ep.conditions.ready.unwrap_or(true))
+                        .flat_map(move |ep| {
+                            ep.addresses
+                                .iter()
+                                .filter_map(|addr| addr.parse().ok())
+                                .map(move |ip| Backend {
+                                    ip,
+                                    ports: &slice.ports,
+                                    target_ref: ep.target_ref.as_ref(),
+                                })
+                        })
+                })
+                .collect();
+        }
+
+        let key = (ns.to_string(), name.to_string());
+        match self.endpoints.get(&key) {
+            Some(eps) => eps
+                .subsets
+                .iter()
+                .flat_map(|subset| {
+                    subset.addresses.iter().map(move |addr| Backend {
+                        ip: addr.ip,
+                        ports: &subset.ports,
+                        target_ref: addr.target_ref.as_ref(),
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns each TCP port of a service, with the port it targets.
+    ///
+    /// If there is no `Service` manifest, each port its endpoints serve is
+    /// exposed as-is.
+    fn ports(&self, ns: &str, name: &str, backends: &[Backend<'_>]) -> Vec<(u16, Target)> {
+        let key = (ns.to_string(), name.to_string());
+        if let Some(svc) = self.services.get(&key) {
+            return svc
+                .spec
+                .ports
+                .iter()
+                .filter(|port| is_tcp(&port.protocol))
+                .map(|port| {
+                    let target = Target {
+                        name: non_empty(&port.name),
+                        port: match port.target_port {
+                            Some(IntOrString::Int(target)) => Some(target),
+                            Some(IntOrString::String(_)) => None,
+                            None => Some(port.port),
+                        },
+                    };
+                    (port.port, target)
+                })
+                .collect();
+        }
+
+        let mut ports = backends
+            .iter()
+            .flat_map(|backend| backend.ports.iter())
+            .filter(|port| is_tcp(&port.protocol))
+            .filter_map(|port| {
+                let target = Target {
+                    name: non_empty(&port.name),
+                    port: port.port,
+                };
+                Some((port.port?, target))
+            })
+            .collect::<Vec<_>>();
+        ports.sort_by_key(|(port, _)| *port);
+        ports.dedup_by_key(|(port, _)| *port);
+        ports
+    }
+
+    fn metric_labels(
+        &self,
+        ns: &str,
+        service: &str,
+        target_ref: Option<&ObjectReference>,
+    ) -> BTreeMap<String, String> {
+        let mut labels = BTreeMap::new();
+        if let Some(pod) = target_ref.filter(|r| r.kind.as_deref() == Some("Pod")) {
+            let pod_ns = pod.namespace.as_deref().unwrap_or(ns);
+            let key = (pod_ns.to_string(), pod.name.clone());
+            if let Some(pod) = self.pods.get(&key) {
+                for (k, v) in pod.metadata.labels.iter() {
+                    labels.insert(label_name(k), v.clone());
+                }
+            }
+            labels.insert("pod".to_string(), pod.name.clone());
+        }
+        labels.insert("namespace".to_string(), ns.to_string());
+        labels.insert("service".to_string(), service.to_string());
+        labels
+    }
+}
+
+/// The port a service port targets on its endpoints: the endpoint port with
+/// the same name, or else a port number.
+#[derive(Clone, Debug)]
+struct Target {
+    name: Option<String>,
+    port: Option<u16>,
+}
+
+// === impl Backend ===
+
+impl<'a> Backend<'a> {
+    fn target_port(&self, target: &Target) -> Option<u16> {
+        self.ports
+            .iter()
+            .find(|port| non_empty(&port.name) == target.name)
+            .and_then(|port| port.port)
+            .or(target.port)
+    }
+}
+
+// === impl RouteRef ===
+
+impl RouteRef {
+    /// Whether the reference is to a core `Service`.
+    fn is_service(&self, is_parent: bool) -> bool {
+        let core = match self.group.as_deref() {
+            None | Some("") | Some("core") => true,
+            Some(_) => false,
+        };
+        let kind = match self.kind.as_deref() {
+            Some(kind) => kind == "Service",
+            None => !is_parent,
+        };
+        core && kind
+    }
+}
+
+// === impl ObjectMeta ===
+
+impl ObjectMeta {
+    fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or("default")
+    }
+
+    fn key(&self) -> Key {
+        (self.namespace().to_string(), self.name.clone())
+    }
+}
+
+/// Serves `overrides` for `dst`, unless a resource that takes precedence
+/// already configured it.
+fn insert_overrides(
+    dsts: &mut HashMap<Dst, Overrides>,
+    dst: Dst,
+    overrides: HashMap<Dst, u32>,
+    kind: &str,
+    (ns, name): &Key,
+) {
+    use std::collections::hash_map::Entry;
+    match dsts.entry(dst) {
+        Entry::Vacant(entry) => {
+            entry.insert(Overrides::new(overrides));
+        }
+        Entry::Occupied(entry) => tracing::warn!(
+            dst = %entry.key(),
+            %kind,
+            %ns,
+            %name,
+            "Ignoring overrides for a destination that is already configured"
+        ),
+    }
+}
+
+fn is_tcp(protocol: &Option<String>) -> bool {
+    match protocol.as_deref() {
+        None | Some("TCP") => true,
+        Some(_) => false,
+    }
+}
+
+fn non_empty(name: &Option<String>) -> Option<String> {
+    name.clone().filter(|name| !name.is_empty())
+}
+
+/// Replaces the characters of a Kubernetes label name that are not valid in
+/// a Prometheus label name, e.g. `app.kubernetes.io/name` becomes
+/// `app_kubernetes_io_name`.
+fn label_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Splits a YAML stream into its non-empty documents.
+fn documents(yaml: &str) -> impl Iterator<Item = &str> {
+    let mut docs = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in yaml.split('\n') {
+        // Include the newline that was split on.
+        let len = (line.len() + 1).min(yaml.len() - offset);
+        if line.trim_end() == "---" || line.starts_with("--- ") {
+            docs.push(&yaml[start..offset]);
+            start = offset + len;
+        }
+        offset += len;
+    }
+    docs.push(&yaml[start..]);
+    docs.into_iter().filter(|doc| {
+        doc.lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifests(yaml: &str) -> K8sManifests {
+        let mut manifests = K8sManifests::empty();
+        for doc in documents(yaml) {
+            manifests.insert(serde_yaml::from_str(doc).unwrap());
+        }
+        manifests
+    }
+
+    fn addrs(manifests: &K8sManifests, dst: &str) -> Vec<SocketAddr> {
+        let endpoints = manifests.endpoints();
+        let mut addrs = endpoints[&dst.parse::<Dst>().unwrap()]
+            .0
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        addrs.sort();
+        addrs
+    }
+
+    fn parse(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    const SERVICE: &str = "
+kind: Service
+metadata:
+  name: web-svc
+  namespace: emojivoto
+spec:
+  ports:
+  - name: http
+    port: 80
+    targetPort: 8080
+  - name: admin
+    port: 9990
+    targetPort: admin-http
+  - name: grpc
+    port: 9090
+  - name: dns
+    port: 53
+    protocol: UDP
+";
+
+    #[test]
+    fn maps_service_ports_to_their_target_ports() {
+        let manifests = manifests(&format!(
+            "{}---
+kind: EndpointSlice
+metadata:
+  name: web-svc-abcde
+  namespace: emojivoto
+  labels:
+    kubernetes.io/service-name: web-svc
+endpoints:
+- addresses: [10.0.0.1]
+- addresses: [10.0.0.2]
+ports:
+- name: http
+  port: 8080
+- name: admin
+  port: 9991
+- name: grpc
+  port: 9090
+",
+            SERVICE
+        ));
+        let web = "web-svc.emojivoto.svc.cluster.local";
+        assert_eq!(
+            addrs(&manifests, &format!("{}:80", web)),
+            parse(&["10.0.0.1:8080", "10.0.0.2:8080"])
+        );
+        // Named target ports are looked up by the endpoint port's name.
+        assert_eq!(
+            addrs(&manifests, &format!("{}:9990", web)),
+            parse(&["10.0.0.1:9991", "10.0.0.2:9991"])
+        );
+        assert_eq!(
+            addrs(&manifests, &format!("{}:9090", web)),
+            parse(&["10.0.0.1:9090", "10.0.0.2:9090"])
+        );
+        // Only TCP ports are served.
+        assert!(!manifests
+            .endpoints()
+            .contains_key(&format!("{}:53", web).parse::<Dst>().unwrap()));
+    }
+
+    #[test]
+    fn only_serves_ready_addresses() {
+        let manifests = manifests(&format!(
+            "{}---
+kind: EndpointSlice
+metadata:
+  name: web-svc-abcde
+  namespace: emojivoto
+  labels:
+    kubernetes.io/service-name: web-svc
+endpoints:
+- addresses: [10.0.0.1]
+  conditions:
+    ready: true
+- addresses: [10.0.0.2]
+  conditions:
+    ready: false
+- addresses: [10.0.0.3]
+  conditions: {{}}
+- addresses: [web-0.example.com]
+ports:
+- name: http
+  port: 8080
+",
+            SERVICE
+        ));
+        assert_eq!(
+            addrs(&manifests, "web-svc.emojivoto.svc.cluster.local:80"),
+            parse(&["10.0.0.1:8080", "10.0.0.3:8080"])
+        );
+    }
+
+    #[test]
+    fn reads_endpoints_without_slices() {
+        let manifests = manifests(
+            "
+kind: Endpoints
+metadata:
+  name: web-svc
+  namespace: emojivoto
+subsets:
+- addresses:
+  - ip: 10.0.0.1
+  notReadyAddresses:
+  - ip: 10.0.0.2
+  ports:
+  - name: http
+    port: 8080
+",
+        );
+        // Without a service, the endpoints' own ports are served.
+        assert_eq!(
+            addrs(&manifests, "web-svc.emojivoto.svc.cluster.local:8080"),
+            parse(&["10.0.0.1:8080"])
+        );
+    }
+
+    #[test]
+    fn prefers_slices_to_endpoints() {
+        let manifests = manifests(&format!(
+            "{}---
+kind: Endpoints
+metadata:
+  name: web-svc
+  namespace: emojivoto
+subsets:
+- addresses:
+  - ip: 10.0.0.1
+  ports:
+  - name: http
+    port: 8080
+---
+kind: EndpointSlice
+metadata:
+  name: web-svc-abcde
+  namespace: emojivoto
+  labels:
+    kubernetes.io/service-name: web-svc
+endpoints:
+- addresses: [10.0.0.2]
+ports:
+- name: http
+  port: 8080
+",
+            SERVICE
+        ));
+        assert_eq!(
+            addrs(&manifests, "web-svc.emojivoto.svc.cluster.local:80"),
+            parse(&["10.0.0.2:8080"])
+        );
+    }
+
+    #[test]
+    fn labels_endpoints_with_their_pods() {
+        let manifests = manifests(&format!(
+            "{}---
+kind: Pod
+metadata:
+  name: web-0
+  namespace: emojivoto
+  labels:
+    app.kubernetes.io/name: web
+---
+kind: List
+items:
+- kind: EndpointSlice
+  metadata:
+    name: web-svc-abcde
+    namespace: emojivoto
+    labels:
+      kubernetes.io/service-name: web-svc
+  endpoints:
+  - addresses: [10.0.0.1]
+    targetRef:
+      kind: Pod
+      name: web-0
+  ports:
+  - name: http
+    port: 8080
+",
+            SERVICE
+        ));
+        let endpoints = manifests.endpoints();
+        let meta = &endpoints[&"web-svc.emojivoto.svc.cluster.local:80"
+            .parse::<Dst>()
+            .unwrap()]
+            .0[&"10.0.0.1:8080".parse::<SocketAddr>().unwrap()];
+        let labels = serde_json::to_value(meta).unwrap()["metric_labels"].clone();
+        assert_eq!(
+            labels,
+            serde_json::json!({
+                "app_kubernetes_io_name": "web",
+                "namespace": "emojivoto",
+                "pod": "web-0",
+                "service": "web-svc",
+            })
+        );
+    }
+
+    #[test]
+    fn names_destinations_in_the_cluster_domain() {
+        let manifests = manifests(SERVICE).cluster_domain("example.org");
+        assert!(manifests.endpoints().contains_key(
+            &"web-svc.emojivoto.svc.example.org:80"
+                .parse::<Dst>()
+                .unwrap()
+        ));
+    }
+
+    fn overrides(weights: &[(&str, u32)]) -> Overrides {
+        Overrides::new(
+            weights
+                .iter()
+                .map(|(dst, weight)| (dst.parse().unwrap(), *weight))
+                .collect(),
+        )
+    }
+
+    fn split(name: &str, backends: &[(&str, &str)]) -> String {
+        let backends = backends
+            .iter()
+            .map(|(service, weight)| format!("  - service: {}\n    weight: {}\n", service, weight))
+            .collect::<String>();
+        format!(
+            "kind: TrafficSplit
+metadata:
+  name: {}
+  namespace: emojivoto
+spec:
+  service: web-svc.emojivoto.svc.cluster.local
+  backends:
+{}",
+            name, backends
+        )
+    }
+
+    #[test]
+    fn serves_traffic_splits_for_each_service_port() {
+        let manifests = manifests(&format!(
+            "{}---\n{}",
+            SERVICE,
+            split("web-split", &[("web-v1", "1"), ("web-v2", "500m")])
+        ));
+        let dsts = manifests.overrides();
+        assert_eq!(dsts.len(), 3);
+        for port in &[80, 9990, 9090] {
+            let dst = format!("web-svc.emojivoto.svc.cluster.local:{}", port);
+            let v1 = format!("web-v1.emojivoto.svc.cluster.local:{}", port);
+            let v2 = format!("web-v2.emojivoto.svc.cluster.local:{}", port);
+            assert_eq!(
+                dsts[&dst.parse::<Dst>().unwrap()],
+                overrides(&[(v1.as_str(), 1000), (v2.as_str(), 500)])
+            );
+        }
+    }
+
+    #[test]
+    fn serves_http_routes_on_the_split_weight_scale() {
+        let manifests = manifests(&format!(
+            "{}---
+kind: HTTPRoute
+metadata:
+  name: web-route
+  namespace: emojivoto
+spec:
+  parentRefs:
+  - kind: Gateway
+    name: ingress
+  - kind: Service
+    name: web-svc
+    port: 80
+  rules:
+  - backendRefs:
+    - name: web-v3
+      port: 8080
+      weight: 3
+  - backendRefs:
+    - name: web-v3
+      port: 8080
+    - name: web-v4
+    - group: example.com
+      kind: Bucket
+      name: web-v5
+",
+            SERVICE
+        ));
+        let dsts = manifests.overrides();
+        // Backends' weights are summed across rules, and scaled like a
+        // split's weights in thousandths.
+        assert_eq!(dsts.len(), 1);
+        assert_eq!(
+            dsts[&"web-svc.emojivoto.svc.cluster.local:80"
+                .parse::<Dst>()
+                .unwrap()],
+            overrides(&[
+                ("web-v3.emojivoto.svc.cluster.local:8080", 4000),
+                ("web-v4.emojivoto.svc.cluster.local:80", 1000),
+            ])
+        );
+    }
+
+    #[test]
+    fn prefers_http_routes_to_traffic_splits() {
+        let manifests = manifests(&format!(
+            "{}---\n{}---
+kind: HTTPRoute
+metadata:
+  name: web-route
+  namespace: emojivoto
+spec:
+  parentRefs:
+  - kind: Service
+    name: web-svc
+    port: 80
+  rules:
+  - backendRefs:
+    - name: web-v3
+",
+            SERVICE,
+            split("a-split", &[("web-v1", "1")])
+        ));
+        let dsts = manifests.overrides();
+        assert_eq!(
+            dsts[&"web-svc.emojivoto.svc.cluster.local:80"
+                .parse::<Dst>()
+                .unwrap()],
+            overrides(&[("web-v3.emojivoto.svc.cluster.local:80", 1000)])
+        );
+        // The split still configures the ports the route does not.
+        assert_eq!(
+            dsts[&"web-svc.emojivoto.svc.cluster.local:9090"
+                .parse::<Dst>()
+                .unwrap()],
+            overrides(&[("web-v1.emojivoto.svc.cluster.local:9090", 1000)])
+        );
+    }
+
+    #[test]
+    fn prefers_resources_by_namespace_and_name() {
+        let manifests = manifests(&format!(
+            "{}---\n{}---\n{}---
+kind: HTTPRoute
+metadata:
+  name: a-route
+  namespace: emojivoto
+spec:
+  parentRefs:
+  - kind: Service
+    name: web-svc
+    port: 9090
+  rules:
+  - backendRefs:
+    - name: web-v3
+---
+kind: HTTPRoute
+metadata:
+  name: z-route
+  namespace: apps
+spec:
+  parentRefs:
+  - kind: Service
+    name: web-svc
+    namespace: emojivoto
+    port: 9090
+  rules:
+  - backendRefs:
+    - name: web-v4
+",
+            SERVICE,
+            split("b-split", &[("web-v2", "1")]),
+            split("a-split", &[("web-v1", "1")]),
+        ));
+        let dsts = manifests.overrides();
+        assert_eq!(
+            dsts[&"web-svc.emojivoto.svc.cluster.local:80"
+                .parse::<Dst>()
+                .unwrap()],
+            overrides(&[("web-v1.emojivoto.svc.cluster.local:80", 1000)])
+        );
+        // Backends are in the route's namespace unless they name another.
+        assert_eq!(
+            dsts[&"web-svc.emojivoto.svc.cluster.local:9090"
+                .parse::<Dst>()
+                .unwrap()],
+            overrides(&[("web-v4.apps.svc.cluster.local:9090", 1000)])
+        );
+    }
+
+    #[test]
+    fn splits_yaml_documents() {
+        let docs =
+            documents("---\nkind: Service\n--- # comment\n# only a comment\n---\nkind: Pod\n")
+                .collect::<Vec<_>>();
+        // Documents with nothing but comments are skipped.
+        assert_eq!(docs, ["kind: Service\n", "kind: Pod\n"]);
+    }
+}
@@ -0,0 +1,733 @@
+//! Watches a Kubernetes API server for the resources that `K8sManifests`
+//! translates, and serves them as they change.
+
+use crate::{Dst, DstSender, Endpoints, Error, K8sManifests, ServiceProfile};
+use futures::prelude::*;
+use hyper::{
+    client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    },
+    header,
+    service::Service,
+    Body, Client, Request, Response, Uri,
+};
+use rustls::{
+    internal::pemfile, Certificate, ClientConfig, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, TLSError,
+};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+use tracing_futures::Instrument;
+use webpki::{DNSName, DNSNameRef};
+
+/// How long to wait before listing a resource again after its watch fails.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The name that an API server's certificate is verified against when it is
+/// reached by IP address, as local clusters like kind and k3s are, since
+/// rustls only verifies DNS names. API servers' certificates include it.
+const DEFAULT_SERVER_NAME: &str = "kubernetes";
+
+/// A Kubernetes API server.
+///
+/// This is either a stand-in API server, like one run by a test, or a real
+/// one, reached over TLS as a kubeconfig describes it, or over plaintext HTTP
+/// through `kubectl proxy`.
+#[derive(Clone, Debug)]
+pub struct K8sApi {
+    server: String,
+    token: Option<String>,
+    namespace: Option<String>,
+    tls: Option<Tls>,
+}
+
+/// How the API server is reached over TLS.
+#[derive(Clone)]
+struct Tls {
+    config: Arc<ClientConfig>,
+    /// The name the API server's certificate is verified against.
+    server_name: DNSName,
+}
+
+/// Connects to the API server, over TLS if it is configured.
+#[derive(Clone, Debug)]
+struct Connector {
+    http: HttpConnector,
+    tls: Option<Tls>,
+}
+
+/// A connection to the API server.
+#[derive(Debug)]
+enum Conn {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// Accepts any certificate, for clusters configured with
+/// `insecure-skip-tls-verify`.
+struct SkipVerification;
+
+/// Watches `Service`s, `EndpointSlice`s and `ServiceProfile`s on a
+/// Kubernetes API server, and serves the destinations and profiles they
+/// translate to, as `K8sManifests` does.
+///
+/// Only destinations and profiles that the watcher has served are updated or
+/// removed by it, so it may run alongside other sources of destinations.
+#[derive(Debug)]
+pub struct K8sWatcher {
+    api: K8sApi,
+    cluster_domain: String,
+    ready: Option<oneshot::Sender<()>>,
+}
+
+/// A kind of resource that is watched.
+#[derive(Debug)]
+struct Resource {
+    kind: &'static str,
+    /// The API group and version's path, like `/api/v1`.
+    api: &'static str,
+    plural: &'static str,
+}
+
+static RESOURCES: [Resource; 3] = [
+    Resource {
+        kind: "Service",
+        api: "/api/v1",
+        plural: "services",
+    },
+    Resource {
+        kind: "EndpointSlice",
+        api: "/apis/discovery.k8s.io/v1",
+        plural: "endpointslices",
+    },
+    Resource {
+        kind: "ServiceProfile",
+        api: "/apis/linkerd.io/v1alpha2",
+        plural: "serviceprofiles",
+    },
+];
+
+#[derive(Debug)]
+enum Event {
+    /// All of the resources of a kind, which replace those seen before.
+    Listed(&'static str, Vec<serde_json::Value>),
+    Applied(serde_json::Value),
+    Deleted(serde_json::Value),
+}
+
+#[derive(Debug, Deserialize)]
+struct List {
+    metadata: ListMeta,
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListMeta {
+    resource_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: serde_json::Value,
+}
+
+/// The parts of a kubeconfig that are used.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Kubeconfig {
+    #[serde(default)]
+    clusters: Vec<Named<Cluster>>,
+    #[serde(default)]
+    contexts: Vec<Named<Context>>,
+    #[serde(default)]
+    users: Vec<Named<User>>,
+    current_context: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named<T> {
+    name: String,
+    #[serde(alias = "cluster", alias = "context", alias = "user")]
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Cluster {
+    server: String,
+    #[serde(default)]
+    certificate_authority: Option<PathBuf>,
+    #[serde(default)]
+    certificate_authority_data: Option<String>,
+    #[serde(default)]
+    insecure_skip_tls_verify: bool,
+    #[serde(default)]
+    tls_server_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Context {
+    cluster: String,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    namespace: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct User {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default, rename = "tokenFile")]
+    token_file: Option<PathBuf>,
+    #[serde(default)]
+    client_certificate: Option<PathBuf>,
+    #[serde(default)]
+    client_certificate_data: Option<String>,
+    #[serde(default)]
+    client_key: Option<PathBuf>,
+    #[serde(default)]
+    client_key_data: Option<String>,
+}
+
+/// What the watcher has served.
+#[derive(Debug, Default)]
+struct Served {
+    endpoints: HashMap<Dst, Endpoints>,
+    profiles: HashMap<String, ServiceProfile>,
+}
+
+// === impl K8sApi ===
+
+impl K8sApi {
+    /// Reads resources in all namespaces from the plaintext HTTP API server at
+    /// `server`, like `http://127.0.0.1:8001`.
+    pub fn new(server: impl Into<String>) -> Result<Self, Error> {
+        let server = server.into();
+        if !server.starts_with("http://") {
+            return Err(format!(
+                "{}: only plaintext HTTP API servers are supported; use a kubeconfig or \
+                 `kubectl proxy`",
+                server
+            )
+            .into());
+        }
+        Ok(Self {
+            server: server.trim_end_matches('/').to_string(),
+            token: None,
+            namespace: None,
+            tls: None,
+        })
+    }
+
+    /// Reads the API server, credentials and namespace of the current context
+    /// of the kubeconfig at `path`.
+    ///
+    /// HTTPS API servers are verified with the cluster's certificate
+    /// authority, unless it sets `insecure-skip-tls-verify`, and the user
+    /// authenticates with a bearer token or a client certificate. Relative
+    /// paths are read from the kubeconfig's directory.
+    pub fn from_kubeconfig(path: &Path) -> Result<Self, Error> {
+        let config = serde_yaml::from_str::<Kubeconfig>(&std::fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let context = find(&config.contexts, &config.current_context)?;
+        let cluster = find(&config.clusters, &context.cluster)?;
+        let anonymous = User::default();
+        let user = match context.user {
+            Some(ref user) => find(&config.users, user)?,
+            None => &anonymous,
+        };
+        let token = match (&user.token, &user.token_file) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(file)) => Some(read_to_string(dir, file)?.trim().to_string()),
+            (None, None) => None,
+        };
+
+        let server = cluster.server.trim_end_matches('/').to_string();
+        let tls = if server.starts_with("https://") {
+            Some(Tls::new(&server, cluster, user, dir)?)
+        } else if server.starts_with("http://") {
+            None
+        } else {
+            return Err(format!("{}: API servers must be HTTP or HTTPS URLs", server).into());
+        };
+        let api = Self {
+            server,
+            token: None,
+            namespace: None,
+            tls,
+        };
+        Ok(api.token(token).namespace(context.namespace.clone()))
+    }
+
+    /// Authenticates requests with a bearer token, if `token` is set.
+    pub fn token(self, token: Option<String>) -> Self {
+        Self { token, ..self }
+    }
+
+    /// Only reads resources in `namespace`, if it is set.
+    pub fn namespace(self, namespace: Option<String>) -> Self {
+        Self { namespace, ..self }
+    }
+
+    fn path(&self, resource: &Resource) -> String {
+        match self.namespace {
+            Some(ref ns) => format!("{}/namespaces/{}/{}", resource.api, ns, resource.plural),
+            None => format!("{}/{}", resource.api, resource.plural),
+        }
+    }
+
+    fn client(&self) -> Client<Connector> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        Client::builder().build(Connector {
+            http,
+            tls: self.tls.clone(),
+        })
+    }
+
+    async fn get(&self, client: &Client<Connector>, path: &str) -> Result<Response<Body>, Error> {
+        let mut req = Request::get(format!("{}{}", self.server, path))
+            .header(header::ACCEPT, "application/json");
+        if let Some(ref token) = self.token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let rsp = client.request(req.body(Body::empty())?).await?;
+        if !rsp.status().is_success() {
+            return Err(format!("GET {}: {}", path, rsp.status()).into());
+        }
+        Ok(rsp)
+    }
+}
+
+fn find<'a, T>(named: &'a [Named<T>], name: &str) -> Result<&'a T, Error> {
+    named
+        .iter()
+        .find(|n| n.name == name)
+        .map(|n| &n.value)
+        .ok_or_else(|| format!("kubeconfig has no entry named '{}'", name).into())
+}
+
+/// Reads a kubeconfig's inline base64 `data`, or else the file at `path`.
+fn read_data(
+    dir: &Path,
+    data: &Option<String>,
+    path: &Option<PathBuf>,
+) -> Result<Option<Vec<u8>>, Error> {
+    match (data, path) {
+        (Some(data), _) => Ok(Some(base64::decode(data.trim())?)),
+        (None, Some(path)) => Ok(Some(read(&dir.join(path))?)),
+        (None, None) => Ok(None),
+    }
+}
+
+fn read_to_string(dir: &Path, path: &Path) -> Result<String, Error> {
+    Ok(String::from_utf8(read(&dir.join(path))?)?)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, io::Error> {
+    std::fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))
+}
+
+// === impl Tls ===
+
+impl Tls {
+    fn new(server: &str, cluster: &Cluster, user: &User, dir: &Path) -> Result<Self, Error> {
+        let mut config = ClientConfig::new();
+        if cluster.insecure_skip_tls_verify {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(SkipVerification));
+        } else {
+            let ca = read_data(
+                dir,
+                &cluster.certificate_authority_data,
+                &cluster.certificate_authority,
+            )?
+            .ok_or("kubeconfig cluster has no certificate authority")?;
+            match config.root_store.add_pem_file(&mut &ca[..]) {
+                Ok((added, _)) if added > 0 => {}
+                _ => return Err("kubeconfig cluster has an invalid certificate authority".into()),
+            }
+        }
+
+        let cert = read_data(dir, &user.client_certificate_data, &user.client_certificate)?;
+        let key = read_data(dir, &user.client_key_data, &user.client_key)?;
+        match (cert, key) {
+            (Some(cert), Some(key)) => {
+                let certs = pemfile::certs(&mut &cert[..])
+                    .map_err(|()| "kubeconfig user has an invalid client certificate")?;
+                let key = pemfile::pkcs8_private_keys(&mut &key[..])
+                    .ok()
+                    .and_then(|keys| keys.into_iter().next())
+                    .or_else(|| {
+                        let keys = pemfile::rsa_private_keys(&mut &key[..]).ok()?;
+                        keys.into_iter().next()
+                    })
+                    .ok_or("kubeconfig user's client key must be a PKCS#8 or RSA private key")?;
+                config.set_single_client_cert(certs, key)?;
+            }
+            (None, None) => {}
+            _ => return Err("kubeconfig user must set both a client certificate and key".into()),
+        }
+
+        let host = server
+            .parse::<Uri>()?
+            .host()
+            .map(|host| {
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string()
+            })
+            .ok_or_else(|| format!("{}: API server has no host", server))?;
+        let server_name = match cluster.tls_server_name {
+            Some(ref name) => name.as_str(),
+            None if host.parse::<std::net::IpAddr>().is_ok() => DEFAULT_SERVER_NAME,
+            None => host.as_str(),
+        };
+        let server_name = DNSNameRef::try_from_ascii_str(server_name)
+            .map_err(|_| format!("{}: invalid TLS server name", server_name))?
+            .to_owned();
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let server_name: &str = self.server_name.as_ref().into();
+        f.debug_struct("Tls")
+            .field("server_name", &server_name)
+            .finish()
+    }
+}
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _: &RootCertStore,
+        _: &[Certificate],
+        _: DNSNameRef<'_>,
+        _: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// === impl Connector ===
+
+impl Service<Uri> for Connector {
+    type Response = Conn;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Conn, Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let tcp = self.http.call(uri);
+        let tls = self.tls.clone();
+        Box::pin(async move {
+            let tcp = tcp.await?;
+            match tls {
+                None => Ok(Conn::Plain(tcp)),
+                Some(tls) => {
+                    let connector = TlsConnector::from(tls.config);
+                    let tls = connector.connect(tls.server_name.as_ref(), tcp).await?;
+                    Ok(Conn::Tls(Box::new(tls)))
+                }
+            }
+        })
+    }
+}
+
+// === impl Conn ===
+
+impl Connection for Conn {
+    fn connected(&self) -> Connected {
+        match self {
+            Conn::Plain(tcp) => tcp.connected(),
+            Conn::Tls(tls) => tls.get_ref().0.connected(),
+        }
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Conn::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            Conn::Tls(tls) => Pin::new(tls).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Conn::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            Conn::Tls(tls) => Pin::new(tls).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+            Conn::Tls(tls) => Pin::new(tls).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            Conn::Tls(tls) => Pin::new(tls).poll_shutdown(cx),
+        }
+    }
+}
+
+// === impl K8sWatcher ===
+
+impl K8sWatcher {
+    pub fn new(api: K8sApi) -> Self {
+        Self {
+            api,
+            cluster_domain: "cluster.local".to_string(),
+            ready: None,
+        }
+    }
+
+    /// Sets the cluster domain that destinations are named in.
+    pub fn cluster_domain(self, cluster_domain: impl Into<String>) -> Self {
+        Self {
+            cluster_domain: cluster_domain.into(),
+            ..self
+        }
+    }
+
+    /// Notifies `ready` once every kind of resource has been listed and
+    /// served.
+    pub fn with_ready(self, ready: oneshot::Sender<()>) -> Self {
+        Self {
+            ready: Some(ready),
+            ..self
+        }
+    }
+
+    /// Serves the watched resources through `sender` until it fails.
+    ///
+    /// Watches that fail, or that the API server ends, are retried, listing
+    /// their resources again if they cannot be resumed.
+    pub async fn watch(self, sender: &mut DstSender) -> Result<(), Error> {
+        let client = self.api.client();
+        let (tx, mut rx) = mpsc::channel(100);
+        for resource in RESOURCES.iter() {
+            let span = tracing::info_span!("watch", kind = resource.kind);
+            let watch = watch_resource(client.clone(), self.api.clone(), resource, tx.clone());
+            tokio::spawn(watch.instrument(span));
+        }
+        drop(tx);
+
+        let mut manifests = K8sManifests::empty().cluster_domain(self.cluster_domain);
+        let mut served = Served::default();
+        let mut ready = self.ready;
+        let mut listed = HashSet::new();
+        while let Some(event) = rx.recv().await {
+            apply(&mut manifests, event, &mut listed);
+            // Serve all of the events that have arrived at once.
+            while let Ok(event) = rx.try_recv() {
+                apply(&mut manifests, event, &mut listed);
+            }
+            served.update(&manifests, sender).await?;
+            if listed.len() == RESOURCES.len() {
+                if let Some(ready) = ready.take() {
+                    tracing::info!("Listed all resources");
+                    let _ = ready.send(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn apply(manifests: &mut K8sManifests, event: Event, listed: &mut HashSet<&'static str>) {
+    tracing::debug!(?event);
+    let resources = match event {
+        Event::Listed(kind, resources) => {
+            listed.insert(kind);
+            manifests.clear(kind);
+            resources
+        }
+        Event::Applied(resource) => vec![resource],
+        Event::Deleted(resource) => {
+            if let Err(e) = manifests.delete(resource) {
+                tracing::warn!(%e, "Skipping invalid resource");
+            }
+            return;
+        }
+    };
+    for resource in resources {
+        if let Err(e) = manifests.apply(resource) {
+            tracing::warn!(%e, "Skipping invalid resource");
+        }
+    }
+}
+
+/// Lists and watches a kind of resource, sending its events on `events`
+/// until it is closed.
+async fn watch_resource(
+    client: Client<Connector>,
+    api: K8sApi,
+    resource: &'static Resource,
+    mut events: mpsc::Sender<Event>,
+) {
+    loop {
+        match list_and_watch(&client, &api, resource, &mut events).await {
+            Ok(()) => return,
+            Err(e) => {
+                tracing::warn!(%e, "Watch failed; listing again");
+                tokio::time::delay_for(RETRY_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Returns `Ok` once `events` is closed.
+async fn list_and_watch(
+    client: &Client<Connector>,
+    api: &K8sApi,
+    resource: &'static Resource,
+    events: &mut mpsc::Sender<Event>,
+) -> Result<(), Error> {
+    let path = api.path(resource);
+    let list = api.get(client, &path).await?;
+    let list = serde_json::from_slice::<List>(&hyper::body::to_bytes(list.into_body()).await?)?;
+    tracing::info!(items = list.items.len(), "Listed");
+    let mut version = list.metadata.resource_version;
+    // Listed items do not have their kind set.
+    let items = list
+        .items
+        .into_iter()
+        .map(|item| with_kind(item, resource))
+        .collect();
+    if events
+        .send(Event::Listed(resource.kind, items))
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    loop {
+        let watch = format!(
+            "{}?watch=true&allowWatchBookmarks=true&resourceVersion={}",
+            path, version
+        );
+        let mut body = api.get(client, &watch).await?.into_body();
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.next().await {
+            buf.extend_from_slice(&chunk?);
+            // Each event is a line of JSON.
+            while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+                let line = buf.drain(..=end).collect::<Vec<_>>();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let WatchEvent { kind, object } = serde_json::from_slice(&line)?;
+                if let Some(v) = object["metadata"]["resourceVersion"].as_str() {
+                    version = v.to_string();
+                }
+                let event = match kind.as_str() {
+                    "ADDED" | "MODIFIED" => Event::Applied(with_kind(object, resource)),
+                    "DELETED" => Event::Deleted(with_kind(object, resource)),
+                    // The version is too old to resume from, so the resources
+                    // must be listed again.
+                    "ERROR" => return Err(format!("watch error: {}", object["message"]).into()),
+                    _ => continue,
+                };
+                if events.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        tracing::debug!(%version, "Watch ended; resuming");
+    }
+}
+
+fn with_kind(mut object: serde_json::Value, resource: &Resource) -> serde_json::Value {
+    if let Some(object) = object.as_object_mut() {
+        object.insert("kind".to_string(), resource.kind.into());
+    }
+    object
+}
+
+// === impl Served ===
+
+impl Served {
+    /// Serves what has changed in `manifests` since it was last served.
+    async fn update(
+        &mut self,
+        manifests: &K8sManifests,
+        sender: &mut DstSender,
+    ) -> Result<(), Error> {
+        let endpoints = manifests.endpoints();
+        for dst in self.endpoints.keys() {
+            if !endpoints.contains_key(dst) {
+                sender.delete_dst(dst.clone()).await;
+            }
+        }
+        for (dst, eps) in endpoints.iter() {
+            if self.endpoints.get(dst) != Some(eps) {
+                sender.send_endpoints(dst.clone(), eps.clone()).await?;
+            }
+        }
+        self.endpoints = endpoints;
+
+        let profiles = manifests
+            .profiles()
+            .into_iter()
+            .map(|profile| (profile.name().to_string(), profile))
+            .collect::<HashMap<_, _>>();
+        for name in self.profiles.keys() {
+            if !profiles.contains_key(name) {
+                sender.delete_profile(name).await;
+            }
+        }
+        for (name, profile) in profiles.iter() {
+            if self.profiles.get(name) != Some(profile) {
+                sender.send_profile(profile.clone()).await?;
+            }
+        }
+        self.profiles = profiles;
+        Ok(())
+    }
+}
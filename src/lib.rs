@@ -1,17 +1,81 @@
+mod churn;
 mod destination;
 mod fs_watcher;
+mod generate;
+mod harness;
+mod health;
+mod http_route;
 mod identity;
+mod inbound;
+mod k8s;
+mod k8s_api;
+mod listen;
+mod loadgen;
+mod metrics;
+mod normalize;
+mod outbound;
+mod pattern;
+mod policy;
+mod profile;
+mod proxy_api;
+mod record;
+mod reflection;
+mod rollout;
 mod spec;
+mod tls;
+mod upstream;
 
-pub use self::destination::{Dst, DstSender, DstService, EndpointMeta, Endpoints, Overrides};
+pub use self::churn::{Churn, EndpointsChurner};
+pub use self::destination::{
+    Dst, DstSender, DstService, EndpointMeta, Endpoints, FinalUpdate, Overrides, Teardown,
+};
 pub use self::fs_watcher::FsWatcher;
-pub use self::identity::IdentityService;
-pub use self::spec::{EndpointsSpec, OverridesSpec};
+pub use self::generate::{EndpointsGenerator, Weight};
+pub use self::harness::MockController;
+pub use self::http_route::{GrpcRouteMatch, HttpRouteMatch, PathMatch};
+pub use self::identity::{
+    CertifyFaults, IdentitiesDir, IdentityError, IdentityErrorKind, IdentityHandle,
+    IdentityService, LoadError,
+};
+pub use self::inbound::{
+    Authentication, Authorization, InboundPolicy, InboundPolicySender, InboundPolicyService,
+    InboundPolicyWatcher, InboundRoute, Protocol,
+};
+pub use self::k8s::K8sManifests;
+pub use self::k8s_api::{K8sApi, K8sWatcher};
+pub use self::listen::{tcp_incoming, unix_incoming, Incoming, Io, Listen};
+pub use self::loadgen::{LoadGenerator, LoadReport};
+pub use self::metrics::Metrics;
+pub use self::normalize::Normalization;
+pub use self::outbound::{
+    Backend, Backoff, FailureAccrual, OutboundGrpcRoute, OutboundHttpRoute, OutboundPolicy,
+    OutboundPolicySender, OutboundPolicyService, OutboundPolicyWatcher, OutboundProtocol, Retry,
+    StatusRange, Target, Timeouts,
+};
+pub use self::pattern::{DstPattern, EndpointsTemplate};
+pub use self::profile::ServiceProfile;
+pub use self::record::{Recorder, Replayer};
+pub use self::rollout::{Rollout, RolloutRunner, RolloutTrigger};
+pub use self::spec::{
+    parse_duration, ChurnSpec, Cidr, EndpointsSpec, IdentityFaultsSpec, OverridesSpec,
+    PatternsSpec, RolloutSpec,
+};
+pub use self::tls::{ClientIdentity, TlsConfig};
+pub use self::upstream::{ProfileRewrites, Rewrites, Upstream};
 
-use linkerd2_proxy_api::{
-    destination::destination_server::DestinationServer, identity::identity_server::IdentityServer,
+use self::destination::DstServer;
+use self::health::{HealthServer, HealthService, ServingStatus};
+use self::inbound::InboundServerPoliciesServer;
+use self::outbound::OutboundPoliciesServer;
+use self::reflection::{ReflectionService, ServerReflectionServer};
+use futures::prelude::*;
+use linkerd2_proxy_api::identity::identity_server::IdentityServer;
+use std::{net::SocketAddr, pin::Pin, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{oneshot, watch},
 };
-use std::net::SocketAddr;
+use tonic::transport::{server::Connected, NamedService};
 use tracing_futures::Instrument;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -19,29 +83,390 @@ pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 pub struct Controller {
     dst_svc: DstService,
     identity_svc: IdentityService,
+    inbound_svc: InboundPolicyService,
+    outbound_svc: OutboundPolicyService,
+    tls: Option<TlsConfig>,
+    identity_listener: Option<(Listen, Option<TlsConfig>)>,
+    metrics: Option<(Listen, Metrics)>,
+    ready: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    descriptor_set: Option<Vec<u8>>,
+    shutdown: Shutdown,
+    health_tx: watch::Sender<ServingStatus>,
+    health_rx: watch::Receiver<ServingStatus>,
+}
+
+struct Shutdown {
+    signal: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    final_update: FinalUpdate,
+    timeout: Duration,
 }
 
 impl Controller {
     pub fn new(dst_svc: DstService, identity_svc: IdentityService) -> Controller {
+        let (health_tx, health_rx) = watch::channel(ServingStatus::NotServing);
         Controller {
             dst_svc,
             identity_svc,
+            inbound_svc: InboundPolicyService::empty().1,
+            outbound_svc: OutboundPolicyService::empty().1,
+            tls: None,
+            identity_listener: None,
+            metrics: None,
+            ready: Box::pin(future::ready(())),
+            descriptor_set: None,
+            shutdown: Shutdown::default(),
+            health_tx,
+            health_rx,
+        }
+    }
+
+    /// Serves `inbound_svc` alongside the destination service. Otherwise, no
+    /// ports have inbound policies.
+    pub fn with_inbound_policies(self, inbound_svc: InboundPolicyService) -> Controller {
+        Controller {
+            inbound_svc,
+            ..self
+        }
+    }
+
+    /// Serves `outbound_svc` alongside the destination service. Otherwise, no
+    /// targets have outbound policies.
+    pub fn with_outbound_policies(self, outbound_svc: OutboundPolicyService) -> Controller {
+        Controller {
+            outbound_svc,
+            ..self
+        }
+    }
+
+    /// Serves TLS rather than plaintext, if `tls` is set.
+    ///
+    /// If the identity service has its own listener, this only applies to the
+    /// destination service.
+    pub fn with_tls(self, tls: Option<TlsConfig>) -> Controller {
+        Controller { tls, ..self }
+    }
+
+    /// Serves the identity service on its own listener, rather than on the
+    /// same listener as the destination service.
+    pub fn with_identity_listener(
+        self,
+        listen: impl Into<Listen>,
+        tls: Option<TlsConfig>,
+    ) -> Controller {
+        Controller {
+            identity_listener: Some((listen.into(), tls)),
+            ..self
+        }
+    }
+
+    /// Serves Prometheus metrics at `/metrics` on their own listener.
+    ///
+    /// The same `metrics` may be passed to an `FsWatcher` to include its
+    /// events.
+    pub fn with_metrics(mut self, listen: impl Into<Listen>, metrics: Metrics) -> Controller {
+        self.dst_svc = self.dst_svc.with_metrics(metrics.clone());
+        self.identity_svc = self.identity_svc.with_metrics(metrics.clone());
+        Controller {
+            metrics: Some((listen.into(), metrics)),
+            ..self
+        }
+    }
+
+    /// Reports the controller's services as not serving to gRPC health checks
+    /// until `ready` resolves, e.g. once their initial state has been loaded,
+    /// and the controller is accepting connections.
+    ///
+    /// The services are reported as not serving again once shutdown starts.
+    pub fn with_ready(self, ready: impl Future<Output = ()> + Send + 'static) -> Controller {
+        Controller {
+            ready: Box::pin(ready),
+            ..self
+        }
+    }
+
+    /// Watches the status reported to gRPC health checks.
+    pub(crate) fn health(&self) -> watch::Receiver<ServingStatus> {
+        self.health_rx.clone()
+    }
+
+    /// Serves the descriptors in `descriptor_set`, an encoded
+    /// `FileDescriptorSet`, from the gRPC server reflection service, in
+    /// addition to those of the services the controller serves.
+    pub fn with_descriptor_set(self, descriptor_set: Option<Vec<u8>>) -> Controller {
+        Controller {
+            descriptor_set,
+            ..self
+        }
+    }
+
+    /// Shuts down gracefully once `signal` resolves.
+    ///
+    /// On shutdown, the controller stops accepting connections, sends
+    /// `final_update` on all open destination streams and ends them, then
+    /// waits up to `timeout` for open connections to close before closing
+    /// them forcibly.
+    pub fn with_shutdown(
+        self,
+        signal: impl Future<Output = ()> + Send + 'static,
+        final_update: FinalUpdate,
+        timeout: Duration,
+    ) -> Controller {
+        Controller {
+            shutdown: Shutdown {
+                signal: Box::pin(signal),
+                final_update,
+                timeout,
+            },
+            ..self
         }
     }
 
     pub async fn serve(self, addr: impl Into<SocketAddr>) -> Result<(), Error> {
-        let addr = addr.into();
-        let span = tracing::info_span!("Controller::serve", listen.addr = %addr);
-        tracing::info!(parent: &span, "Starting controller server...");
+        self.serve_on(Listen::Tcp(addr.into())).await
+    }
+
+    /// Serves on either a TCP address or a Unix domain socket.
+    pub async fn serve_on(self, listen: Listen) -> Result<(), Error> {
+        let span = tracing::info_span!("Controller::serve", listen.addr = %listen);
+        let incoming = listen.bind().instrument(span.clone()).await?;
+        self.serve_with_incoming(incoming).instrument(span).await
+    }
+
+    /// Serves connections accepted from `incoming`, such as those accepted by
+    /// a pre-bound listener.
+    pub async fn serve_with_incoming<I, IO, IE>(self, incoming: I) -> Result<(), Error>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+        IE: Into<Error>,
+    {
+        let Controller {
+            dst_svc,
+            identity_svc,
+            inbound_svc,
+            outbound_svc,
+            tls,
+            identity_listener,
+            metrics,
+            ready,
+            descriptor_set,
+            shutdown,
+            health_tx,
+            health_rx,
+        } = self;
+        let Shutdown {
+            signal,
+            final_update,
+            timeout,
+        } = shutdown;
+
+        let drain = dst_svc.clone();
+        let inbound_drain = inbound_svc.clone();
+        let outbound_drain = outbound_svc.clone();
+        let signal = async move {
+            signal.await;
+            tracing::info!(?final_update, "Shutting down...");
+            drain.drain(final_update);
+            inbound_drain.drain();
+            outbound_drain.drain();
+        }
+        .in_current_span()
+        .shared();
+
+        // Once shutdown starts, give open connections `timeout` to close.
+        let timed_out = signal
+            .clone()
+            .then(move |()| tokio::time::delay_for(timeout))
+            .shared();
+
+        // Connections to the destination service are reset when it is
+        // restarted with `Teardown::Reset`.
+        let resets = dst_svc.clone();
+        let (accepting_tx, accepting) = oneshot::channel();
+        let (incoming, closed) = listen::graceful(
+            incoming.map_ok(move |io| resets.resettable(io)),
+            accepting_tx,
+            signal.clone(),
+            timed_out.clone(),
+        );
+
+        let metrics_signal = signal.clone();
+        let metrics_dst_svc = dst_svc.clone();
+        let serve_metrics = async move {
+            let (listen, metrics) = match metrics {
+                Some(metrics) => metrics,
+                None => return Ok(()),
+            };
+            let span = tracing::info_span!("metrics", listen.addr = %listen);
+            tracing::info!(parent: &span, "Starting metrics server...");
+            let incoming = listen.bind().await?;
+            metrics
+                .serve(metrics_dst_svc, incoming, metrics_signal)
+                .instrument(span)
+                .await
+        };
 
-        tonic::transport::Server::builder()
-            .trace_fn(|headers| tracing::debug_span!("request", ?headers))
-            .add_service(DestinationServer::new(self.dst_svc))
-            .add_service(IdentityServer::new(self.identity_svc))
-            .serve(addr)
-            .instrument(span)
-            .await?;
+        // Report serving to health checks once ready and accepting
+        // connections on every listener, until shutdown starts. A listener
+        // that fails to start fails the controller.
+        let (identity_accepting_tx, identity_accepting) = oneshot::channel();
+        let ready = future::join3(ready, accepting, identity_accepting);
+        let health_signal = signal.clone();
+        let health = async move {
+            if let future::Either::Left(_) = future::select(ready, health_signal.clone()).await {
+                tracing::debug!("Ready");
+                let _ = health_tx.broadcast(ServingStatus::Serving);
+                health_signal.await;
+            }
+            let _ = health_tx.broadcast(ServingStatus::NotServing);
+            Ok::<(), Error>(())
+        };
 
-        Ok(())
+        let identity_signal = signal.clone();
+        let identity_timed_out = timed_out.clone();
+        let serve = async move {
+            let (identity_listen, identity_tls) = match identity_listener {
+                Some(listener) => listener,
+                None => {
+                    drop(identity_accepting_tx);
+                    tracing::info!(tls = tls.is_some(), "Starting controller server...");
+                    let (health, reflection) = introspection(
+                        vec![
+                            name::<DstServer>(),
+                            name::<IdentityServer<IdentityService>>(),
+                            name::<InboundServerPoliciesServer<InboundPolicyService>>(),
+                            name::<OutboundPoliciesServer<OutboundPolicyService>>(),
+                        ],
+                        health_rx,
+                        descriptor_set.as_deref(),
+                    )?;
+                    server(tls.as_ref())?
+                        .add_service(DstServer::new(dst_svc))
+                        .add_service(IdentityServer::new(identity_svc))
+                        .add_service(InboundServerPoliciesServer::new(inbound_svc))
+                        .add_service(OutboundPoliciesServer::new(outbound_svc))
+                        .add_service(health)
+                        .add_service(reflection)
+                        .serve_with_incoming(incoming)
+                        .await?;
+                    closed.await;
+                    return Ok(());
+                }
+            };
+
+            let dst_span = tracing::info_span!("destination", tls = tls.is_some());
+            tracing::info!(parent: &dst_span, "Starting destination server...");
+            let (health, reflection) = introspection(
+                vec![
+                    name::<DstServer>(),
+                    name::<InboundServerPoliciesServer<InboundPolicyService>>(),
+                    name::<OutboundPoliciesServer<OutboundPolicyService>>(),
+                ],
+                health_rx.clone(),
+                descriptor_set.as_deref(),
+            )?;
+            let dst = server(tls.as_ref())?
+                .add_service(DstServer::new(dst_svc))
+                .add_service(InboundServerPoliciesServer::new(inbound_svc))
+                .add_service(OutboundPoliciesServer::new(outbound_svc))
+                .add_service(health)
+                .add_service(reflection)
+                .serve_with_incoming(incoming);
+            let dst = async move {
+                dst.await?;
+                closed.await;
+                Ok::<(), Error>(())
+            }
+            .instrument(dst_span);
+
+            let identity_span = tracing::info_span!(
+                "identity",
+                listen.addr = %identity_listen,
+                tls = identity_tls.is_some()
+            );
+            tracing::info!(parent: &identity_span, "Starting identity server...");
+            let (identity_incoming, identity_closed) = listen::graceful(
+                identity_listen.bind().await?,
+                identity_accepting_tx,
+                identity_signal,
+                identity_timed_out,
+            );
+            let (health, reflection) = introspection(
+                vec![name::<IdentityServer<IdentityService>>()],
+                health_rx,
+                descriptor_set.as_deref(),
+            )?;
+            let identity = server(identity_tls.as_ref())?
+                .add_service(IdentityServer::new(identity_svc))
+                .add_service(health)
+                .add_service(reflection)
+                .serve_with_incoming(identity_incoming);
+            let identity = async move {
+                identity.await?;
+                identity_closed.await;
+                Ok::<(), Error>(())
+            }
+            .instrument(identity_span);
+
+            futures::try_join!(dst, identity)?;
+            Ok::<(), Error>(())
+        };
+        let serve = future::try_join3(serve, serve_metrics, health);
+
+        futures::pin_mut!(serve, timed_out);
+        match future::select(serve, timed_out).await {
+            future::Either::Left((res, _)) => res.map(|((), (), ())| ()),
+            future::Either::Right(((), _)) => {
+                tracing::warn!(?timeout, "Shutdown timed out; closing open connections");
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            signal: Box::pin(future::pending()),
+            final_update: FinalUpdate::NoEndpoints,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn server(tls: Option<&TlsConfig>) -> Result<tonic::transport::Server, Error> {
+    let server = tonic::transport::Server::builder()
+        .trace_fn(|headers| tracing::debug_span!("request", ?headers));
+    match tls {
+        Some(tls) => Ok(server.tls_config(tls.server_config()?)?),
+        None => Ok(server),
     }
 }
+
+/// Builds the health and reflection services for a listener serving
+/// `services`.
+fn introspection(
+    services: Vec<String>,
+    health: watch::Receiver<ServingStatus>,
+    descriptor_set: Option<&[u8]>,
+) -> Result<
+    (
+        HealthServer<HealthService>,
+        ServerReflectionServer<ReflectionService>,
+    ),
+    Error,
+> {
+    let health = HealthService::new(services.clone(), health);
+    let mut services = services;
+    services.push(name::<HealthServer<HealthService>>());
+    services.push(name::<ServerReflectionServer<ReflectionService>>());
+    let reflection = ReflectionService::new(services, descriptor_set)?;
+    Ok((
+        HealthServer::new(health),
+        ServerReflectionServer::new(reflection),
+    ))
+}
+
+fn name<S: NamedService>() -> String {
+    S::NAME.to_string()
+}
@@ -0,0 +1,277 @@
+use futures::prelude::*;
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    sync::{mpsc, oneshot},
+};
+use tonic::transport::server::Connected;
+
+/// An address that a controller listener binds to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// A connection accepted by a bound `Listen`.
+#[derive(Debug)]
+pub enum Io {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+pub type Incoming = Pin<Box<dyn Stream<Item = io::Result<Io>> + Send + 'static>>;
+
+/// A connection that fails with `ConnectionReset` once its reset future
+/// resolves.
+pub(crate) struct Resettable<IO> {
+    io: IO,
+    reset: Option<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>,
+}
+
+// === impl Listen ===
+
+impl Listen {
+    /// Binds the listener, returning a stream of accepted connections.
+    ///
+    /// A stale socket file left at a Unix socket path is removed before
+    /// binding.
+    pub async fn bind(&self) -> io::Result<Incoming> {
+        match self {
+            Listen::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                Ok(tcp_incoming(listener))
+            }
+            Listen::Unix(path) => {
+                match std::fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => {
+                        tracing::debug!(?path, "removing stale socket");
+                        std::fs::remove_file(path)?;
+                    }
+                    _ => {}
+                }
+                let listener = UnixListener::bind(path)?;
+                Ok(unix_incoming(listener))
+            }
+        }
+    }
+}
+
+impl From<SocketAddr> for Listen {
+    fn from(addr: SocketAddr) -> Self {
+        Listen::Tcp(addr)
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(addr) => fmt::Display::fmt(addr, f),
+            Listen::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Returns a stream of connections accepted by a pre-bound `TcpListener`.
+pub fn tcp_incoming(listener: TcpListener) -> Incoming {
+    let incoming = stream::unfold(listener, |mut listener| async move {
+        let accepted = listener.accept().await.and_then(|(tcp, _)| {
+            tcp.set_nodelay(true)?;
+            Ok(Io::Tcp(tcp))
+        });
+        Some((accepted, listener))
+    });
+    Box::pin(incoming)
+}
+
+/// Returns a stream of connections accepted by a pre-bound `UnixListener`.
+pub fn unix_incoming(listener: UnixListener) -> Incoming {
+    let incoming = stream::unfold(listener, |mut listener| async move {
+        let accepted = listener.accept().await.map(|(uds, _)| Io::Unix(uds));
+        Some((accepted, listener))
+    });
+    Box::pin(incoming)
+}
+
+/// Stops accepting connections from `incoming` once `shutdown` resolves, and
+/// resets the connections it accepted once `close` resolves.
+///
+/// Returns the accepted connections, along with a future that resolves once
+/// they have all been closed and no more will be accepted. `accepting` is
+/// notified once the server starts accepting connections.
+pub(crate) fn graceful<I, IO, IE>(
+    incoming: I,
+    accepting: oneshot::Sender<()>,
+    shutdown: impl Future<Output = ()>,
+    close: impl Future<Output = ()> + Clone + Send + 'static,
+) -> (
+    impl Stream<Item = Result<Resettable<IO>, IE>>,
+    impl Future<Output = ()>,
+)
+where
+    I: Stream<Item = Result<IO, IE>>,
+{
+    let (open_tx, mut open_rx) = mpsc::channel::<()>(1);
+    let accepted = stream::unfold(
+        (Box::pin(incoming), Some(accepting), Box::pin(shutdown)),
+        |(mut incoming, mut accepting, mut shutdown)| async move {
+            if let Some(accepting) = accepting.take() {
+                let _ = accepting.send(());
+            }
+            tokio::select! {
+                accepted = incoming.next() => {
+                    accepted.map(|accepted| (accepted, (incoming, accepting, shutdown)))
+                }
+                () = &mut shutdown => {
+                    tracing::debug!("Stopped accepting connections");
+                    None
+                }
+            }
+        },
+    )
+    .map_ok(move |io| {
+        // Each connection holds a sender until it is dropped, so that the
+        // receiver ends once all of them have been.
+        let open = open_tx.clone();
+        let close = close.clone();
+        Resettable::new(io, async move {
+            let _open = open;
+            close.await
+        })
+    });
+    let closed = async move {
+        let _ = open_rx.recv().await;
+    };
+    (accepted, closed)
+}
+
+// === impl Io ===
+
+impl Connected for Io {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Io::Tcp(tcp) => tcp.peer_addr().ok(),
+            Io::Unix(_) => None,
+        }
+    }
+}
+
+impl AsyncRead for Io {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Io::Tcp(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            Io::Unix(uds) => Pin::new(uds).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Io {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Io::Tcp(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            Io::Unix(uds) => Pin::new(uds).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Io::Tcp(tcp) => Pin::new(tcp).poll_flush(cx),
+            Io::Unix(uds) => Pin::new(uds).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Io::Tcp(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            Io::Unix(uds) => Pin::new(uds).poll_shutdown(cx),
+        }
+    }
+}
+
+// === impl Resettable ===
+
+impl<IO> Resettable<IO> {
+    pub(crate) fn new(io: IO, reset: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            io,
+            reset: Some(Box::pin(reset)),
+        }
+    }
+
+    /// Returns a connection that has already been reset.
+    pub(crate) fn reset(io: IO) -> Self {
+        Self { io, reset: None }
+    }
+
+    fn poll_reset(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        if let Some(reset) = self.reset.as_mut() {
+            if reset.as_mut().poll(cx).is_pending() {
+                return Ok(());
+            }
+            tracing::debug!("Resetting connection");
+            self.reset = None;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "connection reset by the controller",
+        ))
+    }
+}
+
+impl<IO: Connected> Connected for Resettable<IO> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.io.remote_addr()
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for Resettable<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_reset(cx)?;
+        Pin::new(&mut this.io).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for Resettable<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_reset(cx)?;
+        Pin::new(&mut this.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_reset(cx)?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_reset(cx)?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
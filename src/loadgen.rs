@@ -0,0 +1,499 @@
+use crate::{
+    destination::update_kind,
+    metrics::{Rpc, UpdateKind},
+    Dst, Error,
+};
+use futures::{future::AbortHandle, prelude::*, stream::BoxStream};
+use linkerd2_proxy_api::destination::{destination_client::DestinationClient, GetDestination};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tonic::transport::{Channel, Endpoint};
+use tracing_futures::Instrument;
+
+/// Simulates many proxies watching destinations on a Destination controller,
+/// either this mock or a real one, and reports on the updates they receive.
+///
+/// `Get` and `GetProfile` streams are spread evenly across the destinations,
+/// and are multiplexed over a connection per `streams_per_connection`
+/// streams.
+#[derive(Clone, Debug)]
+pub struct LoadGenerator {
+    addr: String,
+    dsts: Vec<Dst>,
+    gets: usize,
+    get_profiles: usize,
+    /// Streams opened per second, if they are not all opened at once.
+    ramp: Option<f64>,
+    streams_per_connection: usize,
+    context_token: String,
+    report_interval: Duration,
+}
+
+/// What the streams opened by a `LoadGenerator` have received.
+#[derive(Clone, Debug)]
+pub struct LoadReport {
+    elapsed: Duration,
+    opened: BTreeMap<Rpc, u64>,
+    failed: BTreeMap<Rpc, u64>,
+    ended: BTreeMap<Rpc, u64>,
+    updates: BTreeMap<UpdateKind, u64>,
+    /// How long each stream waited for its first update, in order.
+    first_updates: Vec<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Stats {
+    inner: Arc<Mutex<Counts>>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    opened: BTreeMap<Rpc, u64>,
+    failed: BTreeMap<Rpc, u64>,
+    ended: BTreeMap<Rpc, u64>,
+    updates: BTreeMap<UpdateKind, u64>,
+    first_updates: Vec<Duration>,
+}
+
+// === impl LoadGenerator ===
+
+impl LoadGenerator {
+    /// The slowest rate that streams may be opened at, one every 1000s.
+    pub const MIN_RAMP: f64 = 1e-3;
+
+    /// The fastest rate that streams may be opened at, one every nanosecond.
+    pub const MAX_RAMP: f64 = 1e9;
+
+    /// Watches `dsts` on the controller at `addr`, like
+    /// `http://127.0.0.1:8086`, with 100 `Get` streams opened at once.
+    ///
+    /// Fails if `dsts` is empty.
+    pub fn new(addr: impl Into<String>, dsts: Vec<Dst>) -> Result<Self, Error> {
+        if dsts.is_empty() {
+            return Err("at least one destination must be watched".into());
+        }
+        Ok(Self {
+            addr: addr.into(),
+            dsts,
+            gets: 100,
+            get_profiles: 0,
+            ramp: None,
+            streams_per_connection: 100,
+            context_token: String::new(),
+            report_interval: Duration::from_secs(10),
+        })
+    }
+
+    pub fn gets(self, gets: usize) -> Self {
+        Self { gets, ..self }
+    }
+
+    pub fn get_profiles(self, get_profiles: usize) -> Self {
+        Self {
+            get_profiles,
+            ..self
+        }
+    }
+
+    /// Opens `rate` streams per second, rather than all at once.
+    ///
+    /// Fails if `rate` is not between `MIN_RAMP` and `MAX_RAMP`.
+    pub fn ramp(self, rate: f64) -> Result<Self, Error> {
+        if !(Self::MIN_RAMP..=Self::MAX_RAMP).contains(&rate) {
+            return Err(format!(
+                "invalid ramp {}; streams must be opened at {} to {} per second",
+                rate,
+                Self::MIN_RAMP,
+                Self::MAX_RAMP
+            )
+            .into());
+        }
+        Ok(Self {
+            ramp: Some(rate),
+            ..self
+        })
+    }
+
+    pub fn streams_per_connection(self, streams_per_connection: usize) -> Self {
+        Self {
+            streams_per_connection: streams_per_connection.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the context token sent with each request, like the JSON
+    /// `{"ns":"default","nodeName":"node-1"}` that proxies send.
+    pub fn context_token(self, context_token: impl Into<String>) -> Self {
+        Self {
+            context_token: context_token.into(),
+            ..self
+        }
+    }
+
+    /// Sets how often progress is logged.
+    ///
+    /// Fails if `report_interval` is zero.
+    pub fn report_interval(self, report_interval: Duration) -> Result<Self, Error> {
+        if report_interval == Duration::from_secs(0) {
+            return Err("progress must be reported at a non-zero interval".into());
+        }
+        Ok(Self {
+            report_interval,
+            ..self
+        })
+    }
+
+    /// Opens streams until `shutdown` resolves, and then closes them and
+    /// reports what they received.
+    ///
+    /// Fails if a connection to the controller cannot be established.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<LoadReport, Error> {
+        let stats = Stats::default();
+        let start = Instant::now();
+        let mut streams = Vec::new();
+        let open = async {
+            self.open_streams(&stats, &mut streams).await?;
+            future::pending::<Result<(), Error>>().await
+        };
+        let res = tokio::select! {
+            res = open => res,
+            _ = self.log_progress(&stats, start) => unreachable!("progress is logged forever"),
+            _ = shutdown => Ok(()),
+        };
+        for stream in streams {
+            stream.abort();
+        }
+        res.map(|()| stats.report(start.elapsed()))
+    }
+
+    async fn open_streams(
+        &self,
+        stats: &Stats,
+        streams: &mut Vec<AbortHandle>,
+    ) -> Result<(), Error> {
+        let total = self.gets + self.get_profiles;
+        let mut ramp = self
+            .ramp
+            // Rates are bounded, so this is between 1ns and 1000s; the cast
+            // saturates rather than overflowing regardless.
+            .map(|rate| Duration::from_nanos((1e9 / rate).round().max(1.0) as u64))
+            .map(tokio::time::interval);
+        let mut client = None;
+        let (mut gets, mut get_profiles) = (0, 0);
+        for i in 0..total {
+            if let Some(ref mut ramp) = ramp {
+                ramp.tick().await;
+            }
+            if i % self.streams_per_connection == 0 {
+                let channel = Endpoint::from_shared(self.addr.clone())?.connect().await?;
+                client = Some(DestinationClient::new(channel));
+            }
+            let client = client.clone().expect("a client must be connected");
+
+            // Interleave the RPCs so that each is opened at the same rate.
+            let rpc = if get_profiles >= self.get_profiles
+                || (gets < self.gets && gets * self.get_profiles <= get_profiles * self.gets)
+            {
+                gets += 1;
+                Rpc::Get
+            } else {
+                get_profiles += 1;
+                Rpc::GetProfile
+            };
+            let dst = &self.dsts[i % self.dsts.len()];
+            let req = GetDestination {
+                scheme: "k8s".to_string(),
+                path: dst.to_string(),
+                context_token: self.context_token.clone(),
+            };
+            let span = tracing::debug_span!("stream", %rpc, %dst, id = i);
+            let (stream, handle) =
+                future::abortable(watch(client, rpc, req, stats.clone()).instrument(span));
+            tokio::spawn(stream);
+            streams.push(handle);
+        }
+        tracing::info!(streams = total, "Opened all streams");
+        Ok(())
+    }
+
+    async fn log_progress(&self, stats: &Stats, start: Instant) {
+        let mut interval = tokio::time::interval(self.report_interval);
+        // The first tick completes immediately.
+        interval.tick().await;
+        let mut prev = 0;
+        loop {
+            interval.tick().await;
+            let report = stats.report(start.elapsed());
+            let updates = report.updates.values().sum::<u64>();
+            tracing::info!(
+                open = report.open(),
+                failed = report.failed.values().sum::<u64>(),
+                updates,
+                rate = (updates - prev) as f64 / self.report_interval.as_secs_f64(),
+                "Progress"
+            );
+            prev = updates;
+        }
+    }
+}
+
+async fn watch(
+    mut client: DestinationClient<Channel>,
+    rpc: Rpc,
+    req: GetDestination,
+    stats: Stats,
+) {
+    let start = Instant::now();
+    let rsp: Result<BoxStream<'static, Result<UpdateKind, tonic::Status>>, tonic::Status> =
+        match rpc {
+            Rpc::Get => client.get(req).await.map(|rsp| {
+                rsp.into_inner()
+                    .map_ok(|update| update_kind(&update))
+                    .boxed()
+            }),
+            Rpc::GetProfile => client
+                .get_profile(req)
+                .await
+                .map(|rsp| rsp.into_inner().map_ok(|_| UpdateKind::Profile).boxed()),
+        };
+    let mut updates = match rsp {
+        Ok(updates) => {
+            stats.record(|c| *c.opened.entry(rpc).or_default() += 1);
+            updates
+        }
+        Err(status) => {
+            tracing::warn!(%status, "Failed to open stream");
+            stats.record(|c| *c.failed.entry(rpc).or_default() += 1);
+            return;
+        }
+    };
+
+    let mut first = true;
+    while let Some(update) = updates.next().await {
+        match update {
+            Ok(kind) => stats.record(|c| {
+                if first {
+                    c.first_updates.push(start.elapsed());
+                    first = false;
+                }
+                *c.updates.entry(kind).or_default() += 1;
+            }),
+            Err(status) => {
+                tracing::debug!(%status, "Stream failed");
+                break;
+            }
+        }
+    }
+    tracing::debug!("Stream ended");
+    stats.record(|c| *c.ended.entry(rpc).or_default() += 1);
+}
+
+// === impl Stats ===
+
+impl Stats {
+    fn record(&self, f: impl FnOnce(&mut Counts)) {
+        f(&mut *self.inner.lock().unwrap())
+    }
+
+    fn report(&self, elapsed: Duration) -> LoadReport {
+        let counts = self.inner.lock().unwrap();
+        LoadReport {
+            elapsed,
+            opened: counts.opened.clone(),
+            failed: counts.failed.clone(),
+            ended: counts.ended.clone(),
+            updates: counts.updates.clone(),
+            first_updates: counts.first_updates.clone(),
+        }
+    }
+}
+
+// === impl LoadReport ===
+
+impl LoadReport {
+    /// The number of streams that are still open.
+    pub fn open(&self) -> u64 {
+        self.opened.values().sum::<u64>() - self.ended.values().sum::<u64>()
+    }
+
+    /// Returns the `q`th quantile of the time streams waited for their first
+    /// update.
+    fn first_update_quantile(&self, q: f64) -> Option<Duration> {
+        let mut latencies = self.first_updates.clone();
+        latencies.sort();
+        let i = ((latencies.len() as f64 - 1.0) * q).round() as usize;
+        latencies.get(i).cloned()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(f, "elapsed: {:.2}s", secs)?;
+        for rpc in [Rpc::Get, Rpc::GetProfile].iter() {
+            let count = |counts: &BTreeMap<Rpc, u64>| counts.get(rpc).cloned().unwrap_or(0);
+            writeln!(
+                f,
+                "{} streams: {} opened, {} failed, {} ended",
+                rpc,
+                count(&self.opened),
+                count(&self.failed),
+                count(&self.ended)
+            )?;
+        }
+        for (kind, count) in self.updates.iter() {
+            writeln!(
+                f,
+                "{} updates: {} ({:.1}/s)",
+                kind,
+                count,
+                *count as f64 / secs
+            )?;
+        }
+        write!(f, "first update latency:")?;
+        for &(name, q) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("max", 1.0)].iter() {
+            match self.first_update_quantile(q) {
+                Some(latency) => write!(f, " {}={:.1}ms", name, latency.as_secs_f64() * 1000.0)?,
+                None => write!(f, " {}=n/a", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockController, Overrides};
+
+    const WEB: &str = "web.ns.svc.cluster.local:8080";
+    const API: &str = "api.ns.svc.cluster.local:8080";
+
+    async fn spawn(dsts: &[&str]) -> Result<MockController, Error> {
+        let mut controller = MockController::spawn().await?;
+        controller.ready().await;
+        for (i, dst) in dsts.iter().enumerate() {
+            let addr = format!("10.0.{}.1:8080", i);
+            controller
+                .dst()
+                .send_endpoints(dst.parse()?, addr.parse()?)
+                .await?;
+            let overrides = Overrides::new(Some((dst.parse()?, 1000)).into_iter().collect());
+            controller
+                .dst()
+                .send_overrides(dst.parse()?, overrides)
+                .await?;
+        }
+        Ok(controller)
+    }
+
+    fn loadgen(controller: &MockController, dsts: &[&str]) -> LoadGenerator {
+        let dsts = dsts.iter().map(|dst| dst.parse().unwrap()).collect();
+        LoadGenerator::new(format!("http://{}", controller.addr()), dsts).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_streams_and_updates() -> Result<(), Error> {
+        let mut controller = spawn(&[WEB, API]).await?;
+        let loadgen = loadgen(&controller, &[WEB, API])
+            .gets(6)
+            .get_profiles(4)
+            .streams_per_connection(3);
+
+        // Once every stream has its first update, update one destination,
+        // which half of the streams watch.
+        let mut dst = controller.dst().clone();
+        let shutdown = async move {
+            tokio::time::delay_for(Duration::from_millis(500)).await;
+            dst.send_endpoints(
+                WEB.parse().unwrap(),
+                "10.0.0.1:8080,10.0.0.2:8080".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+            let overrides =
+                Overrides::new(Some((API.parse().unwrap(), 1000)).into_iter().collect());
+            dst.send_overrides(WEB.parse().unwrap(), overrides)
+                .await
+                .unwrap();
+            tokio::time::delay_for(Duration::from_millis(500)).await;
+        };
+        let report = loadgen.run(shutdown).await?;
+
+        let counts = |rpcs: &[(Rpc, u64)]| rpcs.iter().cloned().collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            report.opened,
+            counts(&[(Rpc::Get, 6), (Rpc::GetProfile, 4)])
+        );
+        assert!(report.failed.is_empty());
+        assert!(report.ended.is_empty());
+        assert_eq!(report.open(), 10);
+        // Each stream gets its initial update, and the 3 `Get`s and 2
+        // `GetProfile`s of the updated destination get one more.
+        let updates = vec![(UpdateKind::Add, 9), (UpdateKind::Profile, 6)];
+        assert_eq!(
+            report.updates,
+            updates.into_iter().collect::<BTreeMap<_, _>>()
+        );
+
+        assert_eq!(report.first_updates.len(), 10);
+        assert!(report.elapsed >= Duration::from_secs(1));
+        let p50 = report.first_update_quantile(0.5).unwrap();
+        let max = report.first_update_quantile(1.0).unwrap();
+        assert!(p50 <= max && max < report.elapsed);
+
+        let summary = report.to_string();
+        assert!(summary.contains("get streams: 6 opened, 0 failed, 0 ended"));
+        assert!(summary.contains("get_profile streams: 4 opened, 0 failed, 0 ended"));
+        let rate = 9.0 / report.elapsed.as_secs_f64();
+        assert!(summary.contains(&format!("add updates: 9 ({:.1}/s)", rate)));
+        assert!(summary.contains(&format!("max={:.1}ms", max.as_secs_f64() * 1000.0)));
+
+        controller.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn ramps_streams_open() -> Result<(), Error> {
+        let controller = spawn(&[WEB]).await?;
+
+        // At 10 streams per second, the first stream is opened at once and
+        // the rest every 100ms, so only some are open after 250ms.
+        let ramped = loadgen(&controller, &[WEB]).gets(5).ramp(10.0)?;
+        let report = ramped
+            .clone()
+            .run(tokio::time::delay_for(Duration::from_millis(250)))
+            .await?;
+        let opened = report.opened.get(&Rpc::Get).cloned().unwrap_or(0);
+        assert!(opened >= 2 && opened < 5, "{} streams opened", opened);
+
+        let report = ramped
+            .run(tokio::time::delay_for(Duration::from_millis(750)))
+            .await?;
+        assert_eq!(report.opened.get(&Rpc::Get), Some(&5));
+        assert_eq!(report.updates.get(&UpdateKind::Add), Some(&5));
+
+        controller.shutdown().await
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let addr = "http://127.0.0.1:8086";
+        assert!(LoadGenerator::new(addr, vec![]).is_err());
+
+        let loadgen = LoadGenerator::new(addr, vec![WEB.parse().unwrap()]).unwrap();
+        for &rate in &[0.0, -1.0, 1e-20, 1e10, f64::INFINITY, f64::NAN] {
+            assert!(loadgen.clone().ramp(rate).is_err(), "ramp {}", rate);
+        }
+        for &rate in &[LoadGenerator::MIN_RAMP, 10.0, LoadGenerator::MAX_RAMP] {
+            assert!(loadgen.clone().ramp(rate).is_ok(), "ramp {}", rate);
+        }
+        assert!(loadgen
+            .clone()
+            .report_interval(Duration::from_secs(0))
+            .is_err());
+    }
+}
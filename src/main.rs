@@ -1,11 +1,18 @@
+use futures::FutureExt;
 use linkerd2_mock_dst::{
-    Controller, DstService, EndpointsSpec, FsWatcher, IdentityService, OverridesSpec,
+    ChurnSpec, Controller, Dst, DstService, EndpointsChurner, EndpointsSpec, FinalUpdate,
+    FsWatcher, IdentitiesDir, IdentityFaultsSpec, IdentityService, InboundPolicyService,
+    InboundPolicyWatcher, K8sApi, K8sManifests, K8sWatcher, Listen, LoadGenerator, Metrics,
+    Normalization, OutboundPolicyService, OutboundPolicyWatcher, OverridesSpec, PatternsSpec,
+    Recorder, Replayer, Rewrites, RolloutRunner, RolloutSpec, RolloutTrigger, Teardown, TlsConfig,
+    Upstream,
 };
 use std::error::Error;
 use std::fmt;
-use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
+use tokio::sync::oneshot;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -14,8 +21,93 @@ use structopt::StructOpt;
 )]
 struct CliOpts {
     /// The address that the mock destination and identity service will listen on.
-    #[structopt(long = "addr", default_value = "0.0.0.0:8086")]
-    addr: SocketAddr,
+    ///
+    /// This is either an `IP:PORT` socket address or a `unix:PATH` Unix domain socket path. If
+    /// `--identity-addr` is set, only the destination service listens on this address.
+    #[structopt(long = "addr", default_value = "0.0.0.0:8086", parse(try_from_str = parse_listen))]
+    addr: Listen,
+
+    /// The address that the mock identity service will listen on, if it should not share a
+    /// listener with the destination service.
+    ///
+    /// This is either an `IP:PORT` socket address or a `unix:PATH` Unix domain socket path.
+    #[structopt(long = "identity-addr", env = "LINKERD2_MOCK_DST_IDENTITY_ADDR", parse(try_from_str = parse_listen))]
+    identity_addr: Option<Listen>,
+
+    /// The address that Prometheus metrics are served on, at `/metrics`.
+    ///
+    /// This is either an `IP:PORT` socket address or a `unix:PATH` Unix domain socket path. If
+    /// unset, metrics are not served.
+    #[structopt(long = "metrics-addr", env = "LINKERD2_MOCK_DST_METRICS_ADDR", parse(try_from_str = parse_listen))]
+    metrics_addr: Option<Listen>,
+
+    /// Labels open streams in the metrics with the identities of the clients that opened them.
+    ///
+    /// Each client identity adds a series, so this should only be set when few clients connect.
+    #[structopt(long = "metrics-client-ids")]
+    metrics_client_ids: bool,
+
+    /// A file containing an encoded `FileDescriptorSet` to serve from the gRPC server reflection
+    /// service alongside the controller's own services, e.g. one generated with `protoc
+    /// --include_imports --descriptor_set_out`.
+    #[structopt(long = "descriptor-set", env = "LINKERD2_MOCK_DST_DESCRIPTOR_SET")]
+    descriptor_set: Option<PathBuf>,
+
+    /// A PEM file containing the certificate chain that the identity listener serves TLS with.
+    ///
+    /// If unset, the identity listener listens in plaintext. Requires `--identity-addr`.
+    #[structopt(
+        long = "identity-tls-cert",
+        env = "LINKERD2_MOCK_DST_IDENTITY_TLS_CERT",
+        requires_all = &["identity-addr", "identity-tls-key"]
+    )]
+    identity_tls_cert: Option<PathBuf>,
+
+    /// A PEM file containing the private key that the identity listener serves TLS with.
+    #[structopt(
+        long = "identity-tls-key",
+        env = "LINKERD2_MOCK_DST_IDENTITY_TLS_KEY",
+        requires = "identity-tls-cert"
+    )]
+    identity_tls_key: Option<PathBuf>,
+
+    /// A PEM file containing a trust anchor that identity clients' certificates are verified
+    /// against.
+    #[structopt(
+        long = "identity-tls-trust-anchor",
+        env = "LINKERD2_MOCK_DST_IDENTITY_TLS_TRUST_ANCHOR",
+        requires = "identity-tls-cert"
+    )]
+    identity_tls_trust_anchor: Option<PathBuf>,
+
+    /// A PEM file containing the certificate chain that the controller serves TLS with.
+    ///
+    /// If unset, the controller listens in plaintext. If `--identity-addr` is set, this only
+    /// applies to the destination service.
+    #[structopt(
+        long = "tls-cert",
+        env = "LINKERD2_MOCK_DST_TLS_CERT",
+        requires = "tls-key"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    /// A PEM file containing the private key that the controller serves TLS with.
+    #[structopt(
+        long = "tls-key",
+        env = "LINKERD2_MOCK_DST_TLS_KEY",
+        requires = "tls-cert"
+    )]
+    tls_key: Option<PathBuf>,
+
+    /// A PEM file containing a trust anchor that client certificates are verified against.
+    ///
+    /// If set, clients must present a certificate issued by this trust anchor.
+    #[structopt(
+        long = "tls-trust-anchor",
+        env = "LINKERD2_MOCK_DST_TLS_TRUST_ANCHOR",
+        requires = "tls-cert"
+    )]
+    tls_trust_anchor: Option<PathBuf>,
 
     /// A list of destination endpoints to serve.
     ///
@@ -24,9 +116,122 @@ struct CliOpts {
     /// semicolons. An endpoint consists of a an`IP:PORT` and the following optional suffixes:
     /// [`#h2` supports h2 upgrading, `#h2#<IDENTITY>` supports h2 upgrading and has the
     /// `<IDENTITY>` TLS identity, `##<IDENTITY>` has the `<IDENTITY>` TLS identity].
+    ///
+    /// Alternatively, `ENDPOINTS` may be a generator like
+    /// `gen(cidr=10.0.0.0/16,port=8080,count=5000,weight=uniform(1,100),h2=0.5,seed=1)`, which
+    /// generates `count` endpoints with random addresses in `cidr`. `weight` is either a number or
+    /// `uniform(MIN,MAX)`, `h2` is the probability that an endpoint supports h2 upgrading, and the
+    /// same `seed` always generates the same endpoints. Only `cidr`, `port` and `count` are
+    /// required.
     #[structopt(long = "endpoints", env = "LINKERD2_MOCK_DST_ENDPOINTS", default_value = "", parse(try_from_str = parse_endpoints))]
     endpoints: EndpointsSpec,
 
+    /// A list of destination patterns whose matches are served templated endpoints.
+    ///
+    /// This is parsed as a list of `PATTERN=TEMPLATE` pairs, separated by semicolons. `PATTERN`
+    /// is a DNS name and port, where the name may contain `*`s, which each match any part of a
+    /// single DNS label, or may be a regex like `regex(web-[0-9]+\.emojivoto\..*)`, and the port
+    /// may be a range like `8000-8100`, or `*` for any port. `TEMPLATE` is endpoints in the
+    /// `--endpoints` syntax, in which `{port}` is replaced with the port being looked up.
+    /// Destinations without endpoints are served the endpoints of the first pattern that matches
+    /// them.
+    #[structopt(
+        long = "patterns",
+        env = "LINKERD2_MOCK_DST_PATTERNS",
+        default_value = "",
+        parse(try_from_str = parse_patterns)
+    )]
+    patterns: PatternsSpec,
+
+    /// A directory of Kubernetes `Service`, `Endpoints`, `EndpointSlice` and `Pod` manifests,
+    /// like those output by `kubectl get -o yaml`, to serve endpoints for, and of Linkerd
+    /// `ServiceProfile`s, SMI `TrafficSplit`s and Gateway API `HTTPRoute`s to serve.
+    ///
+    /// Each `.yaml`, `.yml` or `.json` file may contain several resources. Each service port is
+    /// served as a `SERVICE.NAMESPACE.svc.CLUSTER_DOMAIN:PORT` destination, with the ready
+    /// addresses of the service's endpoints on the port it targets. Endpoints are labeled with
+    /// the labels of their pods. These are served in addition to `--endpoints`. Each
+    /// `ServiceProfile` is served for every port of the destination it is named for, with its
+    /// destination overrides replaced by any overrides for that port. The backends of each
+    /// `TrafficSplit`, and of each `HTTPRoute` attached to a service, are served as destination
+    /// overrides of each port of the apex or parent service, replacing any `--overrides` for
+    /// it.
+    #[structopt(
+        long = "k8s-manifests",
+        env = "LINKERD2_MOCK_DST_K8S_MANIFESTS",
+        conflicts_with = "endpoints-dir"
+    )]
+    k8s_manifests: Option<PathBuf>,
+
+    /// The URL of a Kubernetes API server whose `Service`s, `EndpointSlice`s and
+    /// `ServiceProfile`s are watched and served as they change.
+    ///
+    /// Resources are translated as `--k8s-manifests` translates them. Only plaintext HTTP API
+    /// servers are supported, like a stand-in API server or `kubectl proxy`; use `--kubeconfig`
+    /// to reach a cluster's API server directly.
+    #[structopt(
+        long = "k8s-api",
+        env = "LINKERD2_MOCK_DST_K8S_API",
+        conflicts_with = "kubeconfig"
+    )]
+    k8s_api: Option<String>,
+
+    /// A kubeconfig whose current context's API server is watched, as `--k8s-api` does.
+    ///
+    /// HTTPS API servers are verified with the cluster's certificate authority, unless it sets
+    /// `insecure-skip-tls-verify`. The context's bearer token or client certificate, if any,
+    /// authenticates requests, and only its namespace, if any, is watched.
+    #[structopt(long = "kubeconfig", env = "LINKERD2_MOCK_DST_KUBECONFIG")]
+    kubeconfig: Option<PathBuf>,
+
+    /// The namespace that is watched by `--k8s-api` or `--kubeconfig`, instead of all namespaces
+    /// or the kubeconfig context's namespace.
+    #[structopt(long = "k8s-namespace", env = "LINKERD2_MOCK_DST_K8S_NAMESPACE")]
+    k8s_namespace: Option<String>,
+
+    /// The cluster domain that destinations from Kubernetes manifests are named in, and that
+    /// names are qualified in by `--normalize-names`.
+    #[structopt(
+        long = "cluster-domain",
+        env = "LINKERD2_MOCK_DST_CLUSTER_DOMAIN",
+        default_value = "cluster.local"
+    )]
+    cluster_domain: String,
+
+    /// Normalizes the names that destinations are looked up by, like the real controller does.
+    ///
+    /// Names like `foo.ns.svc`, and names like `foo.ns` in `--default-namespace` or
+    /// `--namespaces`, are qualified in `--cluster-domain`, a trailing dot is ignored, names are
+    /// matched case-insensitively, and names without a port are looked up on `--default-port`. Otherwise, names are matched literally and must have a port.
+    #[structopt(long = "normalize-names")]
+    normalize_names: bool,
+
+    /// The namespace that single-label names like `foo` are qualified in by `--normalize-names`.
+    #[structopt(
+        long = "default-namespace",
+        env = "LINKERD2_MOCK_DST_DEFAULT_NAMESPACE",
+        requires = "normalize-names"
+    )]
+    default_namespace: Option<String>,
+
+    /// A comma-separated list of the namespaces that two-label names like `foo.ns` are qualified
+    /// in by `--normalize-names`. Other two-label names, like `example.com`, are left as they are.
+    #[structopt(
+        long = "namespaces",
+        env = "LINKERD2_MOCK_DST_NAMESPACES",
+        use_delimiter = true,
+        requires = "normalize-names"
+    )]
+    namespaces: Vec<String>,
+
+    /// The port that names without one are looked up on by `--normalize-names`. Defaults to 80.
+    #[structopt(
+        long = "default-port",
+        env = "LINKERD2_MOCK_DST_DEFAULT_PORT",
+        requires = "normalize-names"
+    )]
+    default_port: Option<u16>,
+
     /// A list of destination overrides to serve.
     ///
     /// This is parsed as a list of `DESTINATION=OVERRIDES` pairs, where `DESTINATION` is a DNS name
@@ -36,6 +241,51 @@ struct CliOpts {
     #[structopt(long = "overrides", env = "LINKERD2_MOCK_DST_OVERRIDES", default_value = "", parse(try_from_str = parse_overrides))]
     overrides: OverridesSpec,
 
+    /// A list of destinations whose endpoints are continuously churned.
+    ///
+    /// This is parsed as a list of `DESTINATION=CHURN` pairs, where `DESTINATION` is a DNS name
+    /// and port. Each pair is separated by semicolons. `CHURN` is of the form
+    /// `churn(cidr=10.0.0.0/16,port=8080,add=10,remove=10,reweight=50,weight=uniform(1,100),h2=0.5,seed=1)`,
+    /// where `add`, `remove` and `reweight` are the number of endpoints added, removed and
+    /// reweighted per second. New endpoints have random addresses in `cidr`. `weight` is either a
+    /// number or `uniform(MIN,MAX)`, `h2` is the probability that an added endpoint supports h2
+    /// upgrading, and the same `seed` always churns endpoints the same way. Only `cidr` and
+    /// `port` are required.
+    #[structopt(
+        long = "churn",
+        env = "LINKERD2_MOCK_DST_CHURN",
+        default_value = "",
+        parse(try_from_str = parse_churn)
+    )]
+    churn: ChurnSpec,
+
+    /// A list of destinations whose traffic is progressively shifted between two backends.
+    ///
+    /// This is parsed as a list of `DESTINATION=ROLLOUT` pairs, where `DESTINATION` is a DNS name
+    /// and port. Each pair is separated by semicolons. `ROLLOUT` is of the form
+    /// `rollout(from=foo-v1:80,to=foo-v2:80,step=10,interval=5s,pause=50)`, where `step` is the
+    /// percentage of traffic shifted from `from` to `to` every `interval`, and each `pause` is a
+    /// percentage at which the rollout waits to be resumed. Each step is served as the
+    /// destination's overrides. Paused rollouts are resumed when the process receives SIGUSR1,
+    /// and rollouts in progress are rolled back to `from` when it receives SIGUSR2. Only `from`
+    /// and `to` are required.
+    #[structopt(
+        long = "rollout",
+        env = "LINKERD2_MOCK_DST_ROLLOUT",
+        default_value = "",
+        parse(try_from_str = parse_rollout)
+    )]
+    rollout: RolloutSpec,
+
+    /// How often churned destinations are updated.
+    #[structopt(
+        long = "churn-interval",
+        env = "LINKERD2_MOCK_DST_CHURN_INTERVAL",
+        default_value = "100ms",
+        parse(try_from_str = parse_interval)
+    )]
+    churn_interval: Duration,
+
     /// A directory that is dynamically watched for endpoints updates
     ///
     /// The directory contains files with names in the form of {dst.name}:{port}. Each file should
@@ -48,6 +298,30 @@ struct CliOpts {
     )]
     endpoints_dir: Option<PathBuf>,
 
+    /// A directory that is dynamically watched for inbound policies.
+    ///
+    /// The directory contains files with names in the form of {port}.yaml, {port}.yml or
+    /// {port}.json. Each file should contain the json or yaml representation of an
+    /// `InboundPolicy`, which is served to proxies that watch that port. If unset, no ports have
+    /// inbound policies.
+    #[structopt(
+        long = "inbound-policies-dir",
+        env = "LINKERD2_MOCK_DST_INBOUND_POLICIES_DIR"
+    )]
+    inbound_policies_dir: Option<PathBuf>,
+
+    /// A directory that is dynamically watched for outbound policies.
+    ///
+    /// The directory contains files with names in the form of {dst.name}:{port}.yaml or
+    /// {ip}:{port}.yaml, with a .yml or .json extension also accepted. Each file should contain
+    /// the json or yaml representation of an `OutboundPolicy`, which is served to proxies that
+    /// watch that target. If unset, no targets have outbound policies.
+    #[structopt(
+        long = "outbound-policies-dir",
+        env = "LINKERD2_MOCK_DST_OUTBOUND_POLICIES_DIR"
+    )]
+    outbound_policies_dir: Option<PathBuf>,
+
     /// A directory containing identities that should be served by the identity service.
     ///
     /// The directory contains subdirectories that each represent an identity that should be served
@@ -56,6 +330,224 @@ struct CliOpts {
     /// is received for that name.
     #[structopt(long = "identities-dir", env = "LINKERD2_MOCK_DST_IDENTITIES_DIR")]
     identities_dir: Option<PathBuf>,
+
+    /// The name of the PEM file in each identity directory that contains the identity's
+    /// certificate, optionally followed by its intermediate certificates.
+    #[structopt(
+        long = "identity-cert-file",
+        env = "LINKERD2_MOCK_DST_IDENTITY_CERT_FILE",
+        default_value = "crt.pem"
+    )]
+    identity_cert_file: String,
+
+    /// The name of a private key file that each identity directory must contain, e.g. `key.p8`.
+    ///
+    /// If unset, keys are not checked.
+    #[structopt(
+        long = "identity-key-file",
+        env = "LINKERD2_MOCK_DST_IDENTITY_KEY_FILE"
+    )]
+    identity_key_file: Option<String>,
+
+    /// The name of a PEM file in each identity directory containing intermediate certificates to
+    /// append to those in the certificate file.
+    #[structopt(
+        long = "identity-chain-file",
+        env = "LINKERD2_MOCK_DST_IDENTITY_CHAIN_FILE"
+    )]
+    identity_chain_file: Option<String>,
+
+    /// Skip identity directories that fail to load, rather than exiting with an error.
+    #[structopt(long = "skip-invalid-identities")]
+    skip_invalid_identities: bool,
+
+    /// A list of faults to inject into certify responses.
+    ///
+    /// Faults are served by the identity listener, whether or not it is separate from the
+    /// destination listener.
+    ///
+    /// This is parsed as a list of `IDENTITY=FAULTS` pairs, where `IDENTITY` is an identity name
+    /// and `FAULTS` is a comma-separated list of faults. Each pair is separated by semicolons. A
+    /// fault is one of: [`fail(N)` fails the first `N` calls with `Unavailable`, `latency(DURATION)`
+    /// delays every response, `unavailable` always fails with `Unavailable`, `wrong-name(NAME)`
+    /// responds with the certificates of the `NAME` identity, `truncate-chain` omits the
+    /// intermediate certificates, `alternate(NAME)` alternates between this identity's and the
+    /// `NAME` identity's certificates]. Durations have a `ms`, `s` or `m` unit suffix.
+    #[structopt(long = "identity-faults", env = "LINKERD2_MOCK_DST_IDENTITY_FAULTS", default_value = "", parse(try_from_str = parse_identity_faults))]
+    identity_faults: IdentityFaultsSpec,
+
+    /// The last update sent on open destination streams when shutting down on SIGTERM or SIGINT.
+    ///
+    /// One of: [`no-endpoints` sends `NoEndpoints { exists: false }` on `Get` streams,
+    /// `status(CODE)` ends all streams with the `CODE` gRPC status, `close` ends all streams
+    /// without a final update]. `CODE` is either a number or a snake-case name like `unavailable`.
+    #[structopt(
+        long = "shutdown-update",
+        env = "LINKERD2_MOCK_DST_SHUTDOWN_UPDATE",
+        default_value = "no-endpoints",
+        parse(try_from_str = parse_final_update)
+    )]
+    shutdown_update: FinalUpdate,
+
+    /// How long to wait for open connections to close when shutting down, before closing them
+    /// forcibly.
+    #[structopt(
+        long = "shutdown-timeout",
+        env = "LINKERD2_MOCK_DST_SHUTDOWN_TIMEOUT",
+        default_value = "10s",
+        parse(try_from_str = parse_duration)
+    )]
+    shutdown_timeout: Duration,
+
+    /// How open destination streams are torn down when a controller restart is simulated on
+    /// SIGHUP.
+    ///
+    /// One of: [`status(CODE)` ends all streams with the `CODE` gRPC status, `reset` resets all
+    /// destination connections]. `CODE` is either a number or a snake-case name like
+    /// `unavailable`.
+    #[structopt(
+        long = "restart-teardown",
+        env = "LINKERD2_MOCK_DST_RESTART_TEARDOWN",
+        default_value = "status(unavailable)",
+        parse(try_from_str = parse_teardown)
+    )]
+    restart_teardown: Teardown,
+
+    /// How long the destination service is unavailable for after a controller restart is
+    /// simulated on SIGHUP.
+    #[structopt(
+        long = "restart-outage",
+        env = "LINKERD2_MOCK_DST_RESTART_OUTAGE",
+        default_value = "0s",
+        parse(try_from_str = parse_duration)
+    )]
+    restart_outage: Duration,
+
+    /// The URI of an upstream Destination controller, e.g. `http://10.0.0.1:8086`.
+    ///
+    /// Lookups for destinations that are not served locally are forwarded to the upstream
+    /// controller, and its responses are rewritten according to `--rewrites` before they are
+    /// served.
+    #[structopt(long = "upstream", env = "LINKERD2_MOCK_DST_UPSTREAM")]
+    upstream: Option<String>,
+
+    /// A JSON or YAML file describing how responses from the upstream controller are rewritten.
+    ///
+    /// Rewrites may drop endpoints with matching metric labels, strip endpoints' TLS identities,
+    /// delay updates, and override profiles' routes and destination overrides.
+    #[structopt(
+        long = "rewrites",
+        env = "LINKERD2_MOCK_DST_REWRITES",
+        requires = "upstream"
+    )]
+    rewrites: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Simulates many proxies watching destinations on a Destination controller, and reports the
+    /// updates they receive.
+    ///
+    /// The controller may be this mock or a real one. Progress is logged at the `info` level, and
+    /// a report is printed when the load generator is stopped.
+    Loadgen(LoadgenOpts),
+
+    /// Records the streams a Destination controller serves for some destinations to a file.
+    ///
+    /// Every update and profile received is written to the file with the time it was received,
+    /// until SIGTERM or SIGINT is received or all of the streams end.
+    Record(RecordOpts),
+
+    /// Serves a recording made with `record`, with the recorded timing.
+    ///
+    /// The destination controller is served as configured by the other options, with the
+    /// endpoints and destination overrides that were received at each point in the recording.
+    /// The final state is served once the replay finishes. Other sources of destinations, like
+    /// `--churn`, run alongside the replay.
+    Replay(ReplayOpts),
+}
+
+#[derive(Debug, StructOpt)]
+struct RecordOpts {
+    /// The URI of the Destination controller to record, e.g. `http://127.0.0.1:8086`.
+    #[structopt(long = "controller", default_value = "http://127.0.0.1:8086")]
+    controller: String,
+
+    /// A comma-separated list of destinations to record, each a DNS name and port.
+    #[structopt(long = "dsts", required = true, use_delimiter = true, parse(try_from_str = parse_dst))]
+    dsts: Vec<Dst>,
+
+    /// Only record `Get` streams, rather than `Get` and `GetProfile` streams.
+    #[structopt(long = "skip-profiles")]
+    skip_profiles: bool,
+
+    /// The context token sent with each request, e.g. `{"ns":"default"}`.
+    #[structopt(long = "context-token", default_value = "")]
+    context_token: String,
+
+    /// The file to write the recording to.
+    #[structopt(long = "output", short = "o")]
+    output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ReplayOpts {
+    /// A recording made with `record`.
+    #[structopt(name = "RECORDING")]
+    recording: PathBuf,
+
+    /// How many times faster than it was recorded to replay the recording, e.g. `0.5` to replay
+    /// it at half speed.
+    #[structopt(long = "speed", default_value = "1", parse(try_from_str = parse_speed))]
+    speed: f64,
+}
+
+#[derive(Debug, StructOpt)]
+struct LoadgenOpts {
+    /// The URI of the Destination controller to load, e.g. `http://127.0.0.1:8086`.
+    #[structopt(long = "controller", default_value = "http://127.0.0.1:8086")]
+    controller: String,
+
+    /// A comma-separated list of destinations to watch, each a DNS name and port.
+    ///
+    /// Streams are spread evenly across the destinations.
+    #[structopt(long = "dsts", required = true, use_delimiter = true, parse(try_from_str = parse_dst))]
+    dsts: Vec<Dst>,
+
+    /// The number of `Get` streams to open.
+    #[structopt(long = "gets", default_value = "100")]
+    gets: usize,
+
+    /// The number of `GetProfile` streams to open.
+    #[structopt(long = "get-profiles", default_value = "0")]
+    get_profiles: usize,
+
+    /// The number of streams to open per second.
+    ///
+    /// If unset, all streams are opened at once.
+    #[structopt(long = "ramp", parse(try_from_str = parse_ramp))]
+    ramp: Option<f64>,
+
+    /// The number of streams multiplexed over each connection to the controller.
+    #[structopt(long = "streams-per-connection", default_value = "100")]
+    streams_per_connection: usize,
+
+    /// The context token sent with each request, e.g. `{"ns":"default"}`.
+    #[structopt(long = "context-token", default_value = "")]
+    context_token: String,
+
+    /// How long to run for.
+    ///
+    /// If unset, the load generator runs until it receives SIGTERM or SIGINT.
+    #[structopt(long = "duration", parse(try_from_str = parse_duration))]
+    duration: Option<Duration>,
+
+    /// How often progress is logged.
+    #[structopt(long = "report-interval", default_value = "10s", parse(try_from_str = parse_interval))]
+    report_interval: Duration,
 }
 
 #[tokio::main]
@@ -68,49 +560,519 @@ async fn main() -> Result<(), Termination> {
         .with(tracing_subscriber::EnvFilter::from_default_env());
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let opts = CliOpts::from_args();
+    let mut opts = CliOpts::from_args();
+    let replay = match opts.command.take() {
+        Some(Command::Loadgen(opts)) => return loadgen(opts).await,
+        Some(Command::Record(opts)) => return record(opts).await,
+        Some(Command::Replay(opts)) => Some(opts),
+        None => None,
+    };
+
     let CliOpts {
         addr,
-        endpoints,
-        overrides,
+        identity_addr,
+        metrics_addr,
+        metrics_client_ids,
+        descriptor_set,
+        identity_tls_cert,
+        identity_tls_key,
+        identity_tls_trust_anchor,
+        tls_cert,
+        tls_key,
+        tls_trust_anchor,
+        mut endpoints,
+        patterns,
+        k8s_manifests,
+        k8s_api,
+        kubeconfig,
+        k8s_namespace,
+        cluster_domain,
+        normalize_names,
+        default_namespace,
+        namespaces,
+        default_port,
+        mut overrides,
+        churn,
+        churn_interval,
+        rollout,
         endpoints_dir,
+        inbound_policies_dir,
+        outbound_policies_dir,
         identities_dir,
+        identity_cert_file,
+        identity_key_file,
+        identity_chain_file,
+        skip_invalid_identities,
+        identity_faults,
+        shutdown_update,
+        shutdown_timeout,
+        restart_teardown,
+        restart_outage,
+        upstream,
+        rewrites,
+        command: _,
     } = opts;
     tracing::debug!(
         ?addr,
+        ?identity_addr,
+        ?metrics_addr,
+        metrics_client_ids,
+        ?descriptor_set,
+        ?identity_tls_cert,
+        ?identity_tls_key,
+        ?identity_tls_trust_anchor,
+        ?tls_cert,
+        ?tls_key,
+        ?tls_trust_anchor,
         ?endpoints,
+        ?patterns,
+        ?k8s_manifests,
+        ?k8s_api,
+        ?kubeconfig,
+        ?k8s_namespace,
+        ?cluster_domain,
+        ?normalize_names,
+        ?default_namespace,
+        ?namespaces,
+        ?default_port,
         ?overrides,
+        ?churn,
+        ?churn_interval,
+        ?rollout,
         ?endpoints_dir,
-        ?identities_dir
+        ?inbound_policies_dir,
+        ?outbound_policies_dir,
+        ?identities_dir,
+        ?identity_faults,
+        ?shutdown_update,
+        ?shutdown_timeout,
+        ?restart_teardown,
+        ?restart_outage,
+        ?upstream,
+        ?rewrites
     );
 
-    let identity_svc = IdentityService::new(identities_dir)?;
+    let identities_dir = identities_dir.map(|dir| {
+        IdentitiesDir::new(dir)
+            .cert_file(identity_cert_file)
+            .key_file(identity_key_file)
+            .chain_file(identity_chain_file)
+            .skip_invalid(skip_invalid_identities)
+    });
+    let identity_svc = IdentityService::new(identities_dir, identity_faults)?;
+    let tls = tls_config(tls_cert, tls_key, tls_trust_anchor);
+    let identity_tls = tls_config(
+        identity_tls_cert,
+        identity_tls_key,
+        identity_tls_trust_anchor,
+    );
+    let mut profiles = Vec::new();
+    if let Some(dir) = k8s_manifests {
+        let manifests = K8sManifests::load_dir(&dir)?.cluster_domain(cluster_domain.clone());
+        endpoints.extend(manifests.endpoints());
+        overrides.extend(manifests.overrides());
+        profiles = manifests.profiles();
+    }
+    let normalization = if normalize_names {
+        Normalization::cluster(cluster_domain.clone())
+            .default_namespace(default_namespace)
+            .namespaces(namespaces)
+            .default_port(Some(default_port.unwrap_or(80)))
+    } else {
+        Normalization::literal()
+    };
+    let k8s_api = match (k8s_api, kubeconfig) {
+        (Some(server), _) => Some(K8sApi::new(server)?),
+        (None, Some(path)) => Some(K8sApi::from_kubeconfig(&path)?),
+        (None, None) => None,
+    };
+    // Health checks report the controller as serving once the initial state
+    // of each watched source has been loaded. Identities are loaded before
+    // the controller starts.
+    let mut ready = Vec::new();
+    let k8s_watcher = k8s_api.map(|api| {
+        let api = match k8s_namespace {
+            Some(ns) => api.namespace(Some(ns)),
+            None => api,
+        };
+        let (ready_tx, ready_rx) = oneshot::channel();
+        ready.push(ready_rx);
+        K8sWatcher::new(api)
+            .cluster_domain(cluster_domain)
+            .with_ready(ready_tx)
+    });
+    let upstream = match upstream {
+        Some(addr) => {
+            let upstream = Upstream::connect(addr).await?;
+            match rewrites {
+                Some(path) => Some(upstream.with_rewrites(Rewrites::from_file(&path)?)),
+                None => Some(upstream),
+            }
+        }
+        None => None,
+    };
+    let descriptor_set = match descriptor_set {
+        Some(path) => Some(std::fs::read(path)?),
+        None => None,
+    };
+    let (inbound_sender, inbound_svc) = InboundPolicyService::empty();
+    let (outbound_sender, outbound_svc) = OutboundPolicyService::empty();
+    let inbound_watcher = inbound_policies_dir.map(|dir| {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        ready.push(ready_rx);
+        InboundPolicyWatcher::new(dir, inbound_sender).with_ready(ready_tx)
+    });
+    let outbound_watcher = outbound_policies_dir.map(|dir| {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        ready.push(ready_rx);
+        OutboundPolicyWatcher::new(dir, outbound_sender).with_ready(ready_tx)
+    });
+    let watch_policies = async move {
+        let inbound = async move {
+            match inbound_watcher {
+                Some(mut watcher) => watcher.watch().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let outbound = async move {
+            match outbound_watcher {
+                Some(mut watcher) => watcher.watch().await,
+                None => futures::future::pending().await,
+            }
+        };
+        futures::try_join!(inbound, outbound).map(|((), ())| ())
+    };
+    futures::pin_mut!(watch_policies);
+    let metrics = Metrics::default().with_client_ids(metrics_client_ids);
+    let controller_metrics = metrics.clone();
+    let controller = |dst_svc: DstService, ready: Vec<oneshot::Receiver<()>>| {
+        let dst_svc = match upstream {
+            Some(upstream) => dst_svc.with_upstream(upstream),
+            None => dst_svc,
+        };
+        let dst_svc = dst_svc.with_patterns(patterns);
+        tokio::spawn(restart_on_hangup(
+            dst_svc.clone(),
+            restart_teardown,
+            restart_outage,
+        ));
+        let controller = Controller::new(dst_svc, identity_svc)
+            .with_inbound_policies(inbound_svc)
+            .with_outbound_policies(outbound_svc)
+            .with_tls(tls)
+            .with_descriptor_set(descriptor_set)
+            // A source that fails before it is ready ends the process, so its
+            // readiness is not waited for.
+            .with_ready(futures::future::join_all(ready).map(|_| ()))
+            .with_shutdown(shutdown_signal(), shutdown_update, shutdown_timeout);
+        let controller = match metrics_addr {
+            Some(metrics_addr) => controller.with_metrics(metrics_addr, controller_metrics),
+            None => controller,
+        };
+        match identity_addr {
+            Some(identity_addr) => controller.with_identity_listener(identity_addr, identity_tls),
+            None => controller,
+        }
+    };
+
+    let (mut sender, dst_svc) = DstService::normalized(normalization, endpoints, overrides);
+    for profile in profiles {
+        sender.send_profile(profile).await?;
+    }
+    let fs_watcher = endpoints_dir.map(|dir| {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        ready.push(ready_rx);
+        FsWatcher::new(dir, sender.clone())
+            .with_metrics(metrics)
+            .with_ready(ready_tx)
+    });
+    let controller = controller(dst_svc, ready);
 
-    match endpoints_dir {
-        Some(endpoints) => {
-            let (sender, dst_svc) = DstService::empty();
-            let controller = Controller::new(dst_svc, identity_svc);
-            let mut fs_watcher = FsWatcher::new(endpoints, sender);
-            futures::try_join!(controller.serve(addr), fs_watcher.watch())?;
+    // Each source updates the destinations through its own clone of the
+    // sender, so that they all run at once.
+    let watch_dir = async move {
+        match fs_watcher {
+            Some(mut fs_watcher) => fs_watcher.watch().await,
+            None => Ok(()),
+        }
+    };
+    let mut replay_sender = sender.clone();
+    let replay_recording = async move {
+        match replay {
+            Some(ReplayOpts { recording, speed }) => {
+                Replayer::load(&recording)
+                    .await?
+                    .speed(speed)?
+                    .replay(&mut replay_sender)
+                    .await
+            }
+            None => Ok(()),
+        }
+    };
+    let mut k8s_sender = sender.clone();
+    let watch_k8s = async move {
+        match k8s_watcher {
+            Some(k8s_watcher) => k8s_watcher.watch(&mut k8s_sender).await,
+            None => Ok(()),
+        }
+    };
+    let mut rollout_sender = sender.clone();
+    let run_rollouts = async move {
+        if rollout.is_empty() {
+            return Ok(());
+        }
+        let (trigger, rollouts) = RolloutRunner::new(rollout);
+        tokio::spawn(trigger_rollouts_on_signals(trigger));
+        rollouts.run(&mut rollout_sender).await
+    };
+    let churn_endpoints = async move {
+        if churn.is_empty() {
+            return Ok(());
         }
-        None => {
-            let (_sender, dst_svc) = DstService::new(endpoints, overrides);
-            let controller = Controller::new(dst_svc, identity_svc);
-            controller.serve(addr).await?;
+        EndpointsChurner::new(churn, churn_interval)
+            .run(&mut sender)
+            .await
+    };
+    let update = async move {
+        futures::try_join!(
+            watch_dir,
+            replay_recording,
+            watch_k8s,
+            run_rollouts,
+            churn_endpoints
+        )?;
+        // Sources that finish leave their destinations served.
+        futures::future::pending::<Result<(), Termination>>().await
+    };
+    tokio::select! {
+        res = controller.serve_on(addr) => res?,
+        res = &mut watch_policies => res?,
+        res = update => res?,
+    }
+
+    Ok(())
+}
+
+async fn loadgen(opts: LoadgenOpts) -> Result<(), Termination> {
+    tracing::debug!(?opts);
+    let LoadgenOpts {
+        controller,
+        dsts,
+        gets,
+        get_profiles,
+        ramp,
+        streams_per_connection,
+        context_token,
+        duration,
+        report_interval,
+    } = opts;
+
+    let loadgen = LoadGenerator::new(controller, dsts)?
+        .gets(gets)
+        .get_profiles(get_profiles)
+        .streams_per_connection(streams_per_connection)
+        .context_token(context_token)
+        .report_interval(report_interval)?;
+    let loadgen = match ramp {
+        Some(rate) => loadgen.ramp(rate)?,
+        None => loadgen,
+    };
+    let stop = async move {
+        match duration {
+            Some(duration) => tokio::select! {
+                _ = tokio::time::delay_for(duration) => {}
+                _ = shutdown_signal() => {}
+            },
+            None => shutdown_signal().await,
         }
     };
+    let report = loadgen.run(stop).await?;
+    println!("{}", report);
+    Ok(())
+}
 
+async fn record(opts: RecordOpts) -> Result<(), Termination> {
+    tracing::debug!(?opts);
+    let RecordOpts {
+        controller,
+        dsts,
+        skip_profiles,
+        context_token,
+        output,
+    } = opts;
+
+    Recorder::new(controller, dsts)
+        .profiles(!skip_profiles)
+        .context_token(context_token)
+        .record(&output, shutdown_signal())
+        .await?;
     Ok(())
 }
 
+/// Resolves when the process receives SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut term, mut int) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(term), Ok(int)) => (term, int),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(%e, "Failed to register signal handlers");
+            return futures::future::pending().await;
+        }
+    };
+
+    tokio::select! {
+        _ = term.recv() => tracing::info!("Received SIGTERM"),
+        _ = int.recv() => tracing::info!("Received SIGINT"),
+    }
+}
+
+/// Simulates a controller restart each time the process receives SIGHUP.
+async fn restart_on_hangup(dst_svc: DstService, teardown: Teardown, outage: Duration) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hup = match signal(SignalKind::hangup()) {
+        Ok(hup) => hup,
+        Err(e) => {
+            tracing::error!(%e, "Failed to register signal handlers");
+            return;
+        }
+    };
+
+    while hup.recv().await.is_some() {
+        tracing::info!("Received SIGHUP");
+        dst_svc.restart(teardown.clone(), outage);
+    }
+}
+
+/// Resumes paused rollouts each time the process receives SIGUSR1, and rolls
+/// back rollouts in progress each time it receives SIGUSR2.
+async fn trigger_rollouts_on_signals(trigger: RolloutTrigger) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut usr1, mut usr2) = match (
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+    ) {
+        (Ok(usr1), Ok(usr2)) => (usr1, usr2),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(%e, "Failed to register signal handlers");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            Some(()) = usr1.recv() => {
+                tracing::info!("Received SIGUSR1");
+                trigger.resume();
+            }
+            Some(()) = usr2.recv() => {
+                tracing::info!("Received SIGUSR2");
+                trigger.rollback();
+            }
+            else => return,
+        }
+    }
+}
+
+fn tls_config(
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    trust_anchor: Option<PathBuf>,
+) -> Option<TlsConfig> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => {
+            Some(TlsConfig::new(cert, key).client_trust_anchor(trust_anchor))
+        }
+        _ => None,
+    }
+}
+
+fn parse_listen(s: &str) -> Result<Listen, Termination> {
+    s.parse().map_err(Into::into)
+}
+
+fn parse_dst(s: &str) -> Result<Dst, Termination> {
+    s.parse().map_err(Into::into)
+}
+
 fn parse_endpoints(s: &str) -> Result<EndpointsSpec, Termination> {
     s.parse().map_err(Into::into)
 }
 
+fn parse_patterns(s: &str) -> Result<PatternsSpec, Termination> {
+    s.parse().map_err(Into::into)
+}
+
 fn parse_overrides(s: &str) -> Result<OverridesSpec, Termination> {
     s.parse().map_err(Into::into)
 }
 
+fn parse_churn(s: &str) -> Result<ChurnSpec, Termination> {
+    s.parse().map_err(Into::into)
+}
+
+fn parse_rollout(s: &str) -> Result<RolloutSpec, Termination> {
+    s.parse().map_err(Into::into)
+}
+
+fn parse_final_update(s: &str) -> Result<FinalUpdate, Termination> {
+    s.parse().map_err(Into::into)
+}
+
+fn parse_teardown(s: &str) -> Result<Teardown, Termination> {
+    s.parse().map_err(Into::into)
+}
+
+fn parse_duration(s: &str) -> Result<Duration, Termination> {
+    linkerd2_mock_dst::parse_duration(s).map_err(Into::into)
+}
+
+/// Parses a rate of streams opened per second, which must be positive and
+/// leave at least a nanosecond between streams.
+fn parse_ramp(s: &str) -> Result<f64, Termination> {
+    let rate = s.parse::<f64>()?;
+    if !(LoadGenerator::MIN_RAMP..=LoadGenerator::MAX_RAMP).contains(&rate) {
+        return Err(format!(
+            "ramp must be between {} and {} streams per second",
+            LoadGenerator::MIN_RAMP,
+            LoadGenerator::MAX_RAMP
+        )
+        .into());
+    }
+    Ok(rate)
+}
+
+fn parse_speed(s: &str) -> Result<f64, Termination> {
+    let speed = s.parse::<f64>()?;
+    if !(Replayer::MIN_SPEED..=Replayer::MAX_SPEED).contains(&speed) {
+        return Err(format!(
+            "speed must be between {} and {}",
+            Replayer::MIN_SPEED,
+            Replayer::MAX_SPEED
+        )
+        .into());
+    }
+    Ok(speed)
+}
+
+/// Parses the period of a timer, which must not be zero.
+fn parse_interval(s: &str) -> Result<Duration, Termination> {
+    let interval = parse_duration(s)?;
+    if interval == Duration::from_secs(0) {
+        return Err("interval must not be zero".into());
+    }
+    Ok(interval)
+}
+
+fn parse_identity_faults(s: &str) -> Result<IdentityFaultsSpec, Termination> {
+    s.parse().map_err(Into::into)
+}
+
 struct Termination(Box<dyn Error>);
 
 impl fmt::Debug for Termination {
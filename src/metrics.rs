@@ -0,0 +1,351 @@
+use crate::{ClientIdentity, Dst, DstService, Error, Incoming};
+use futures::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Upper bounds of the stream lifetime histogram buckets, in seconds.
+const LIFETIME_BUCKETS: [f64; 8] = [0.1, 1.0, 10.0, 30.0, 60.0, 300.0, 1800.0, 3600.0];
+
+/// Counters describing what the controller has served, rendered in the
+/// Prometheus text format.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+    client_ids: bool,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Open streams, by the identity of the client that opened them, if
+    /// client identities are recorded and it authenticated with a client
+    /// certificate.
+    streams: HashMap<(Rpc, Dst, Option<ClientIdentity>), u64>,
+    updates: BTreeMap<UpdateKind, u64>,
+    certify: BTreeMap<i32, u64>,
+    fs_events: u64,
+    fs_errors: u64,
+    lifetimes: BTreeMap<Rpc, Histogram>,
+}
+
+/// A destination RPC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Rpc {
+    Get,
+    GetProfile,
+}
+
+/// A kind of update sent on a destination stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum UpdateKind {
+    Add,
+    Remove,
+    NoEndpoints,
+    Profile,
+}
+
+/// Records a stream as active until it is dropped.
+#[derive(Debug)]
+pub(crate) struct StreamGuard {
+    metrics: Metrics,
+    rpc: Rpc,
+    dst: Dst,
+    client_id: Option<ClientIdentity>,
+    opened: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; LIFETIME_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    /// Labels open streams with the identities of the clients that opened
+    /// them.
+    ///
+    /// Each client identity adds a series, so this should only be enabled
+    /// when there are few of them.
+    pub fn with_client_ids(self, client_ids: bool) -> Self {
+        Self { client_ids, ..self }
+    }
+
+    pub(crate) fn stream_opened(
+        &self,
+        rpc: Rpc,
+        dst: Dst,
+        client_id: Option<ClientIdentity>,
+    ) -> StreamGuard {
+        let client_id = client_id.filter(|_| self.client_ids);
+        let key = (rpc, dst.clone(), client_id.clone());
+        *self.lock().streams.entry(key).or_default() += 1;
+        StreamGuard {
+            metrics: self.clone(),
+            rpc,
+            dst,
+            client_id,
+            opened: Instant::now(),
+        }
+    }
+
+    pub(crate) fn update_sent(&self, kind: UpdateKind) {
+        *self.lock().updates.entry(kind).or_default() += 1;
+    }
+
+    pub(crate) fn certified(&self, code: tonic::Code) {
+        *self.lock().certify.entry(code as i32).or_default() += 1;
+    }
+
+    pub(crate) fn fs_event(&self, result: &Result<(), Error>) {
+        let mut inner = self.lock();
+        inner.fs_events += 1;
+        if result.is_err() {
+            inner.fs_errors += 1;
+        }
+    }
+
+    /// Renders all metrics, along with the number of endpoints currently
+    /// served for each destination.
+    pub async fn render(&self, dst_svc: &DstService) -> String {
+        let endpoints = dst_svc.endpoint_counts().await;
+        let mut out = String::new();
+        self.lock()
+            .render(&endpoints, self.client_ids, &mut out)
+            .expect("writing to a string cannot fail");
+        out
+    }
+
+    /// Serves metrics at `/metrics` on connections accepted from `incoming`
+    /// until `shutdown` resolves.
+    pub(crate) async fn serve(
+        self,
+        dst_svc: DstService,
+        incoming: Incoming,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        use hyper::{service, Body, Method, Request, Response, StatusCode};
+
+        let make_svc = service::make_service_fn(move |_| {
+            let metrics = self.clone();
+            let dst_svc = dst_svc.clone();
+            future::ok::<_, Infallible>(service::service_fn(move |req: Request<Body>| {
+                let metrics = metrics.clone();
+                let dst_svc = dst_svc.clone();
+                async move {
+                    match (req.method(), req.uri().path()) {
+                        (&Method::GET, "/metrics") => Response::builder()
+                            .header("content-type", "text/plain; version=0.0.4")
+                            .body(metrics.render(&dst_svc).await.into()),
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    }
+                }
+            }))
+        });
+
+        hyper::Server::builder(hyper::server::accept::from_stream(incoming))
+            .serve(make_svc)
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("metrics lock poisoned")
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    fn render(
+        &self,
+        endpoints: &[(Dst, usize)],
+        client_ids: bool,
+        out: &mut String,
+    ) -> fmt::Result {
+        writeln!(
+            out,
+            "# HELP mock_dst_streams_active Open destination streams."
+        )?;
+        writeln!(out, "# TYPE mock_dst_streams_active gauge")?;
+        let mut streams = self.streams.iter().collect::<Vec<_>>();
+        streams
+            .sort_by_key(|((rpc, dst, client_id), _)| (*rpc, dst.to_string(), client_id.clone()));
+        for ((rpc, dst, client_id), count) in streams {
+            write!(
+                out,
+                "mock_dst_streams_active{{rpc=\"{}\",dst=\"{}\"",
+                rpc,
+                Escape(dst)
+            )?;
+            if client_ids {
+                let client_id = client_id.as_ref().map(ClientIdentity::as_str).unwrap_or("");
+                write!(out, ",client_id=\"{}\"", Escape(&client_id))?;
+            }
+            writeln!(out, "}} {}", count)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP mock_dst_updates_total Updates sent on destination streams."
+        )?;
+        writeln!(out, "# TYPE mock_dst_updates_total counter")?;
+        for (kind, count) in &self.updates {
+            writeln!(out, "mock_dst_updates_total{{type=\"{}\"}} {}", kind, count)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP mock_dst_endpoints Endpoints served for each destination."
+        )?;
+        writeln!(out, "# TYPE mock_dst_endpoints gauge")?;
+        for (dst, count) in endpoints {
+            writeln!(
+                out,
+                "mock_dst_endpoints{{dst=\"{}\"}} {}",
+                Escape(dst),
+                count
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP mock_dst_certify_total Certify requests, by gRPC status."
+        )?;
+        writeln!(out, "# TYPE mock_dst_certify_total counter")?;
+        for (code, count) in &self.certify {
+            writeln!(
+                out,
+                "mock_dst_certify_total{{grpc_status=\"{}\"}} {}",
+                code, count
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP mock_dst_fs_watcher_events_total Endpoints directory events handled."
+        )?;
+        writeln!(out, "# TYPE mock_dst_fs_watcher_events_total counter")?;
+        writeln!(out, "mock_dst_fs_watcher_events_total {}", self.fs_events)?;
+        writeln!(out, "# HELP mock_dst_fs_watcher_errors_total Endpoints directory events that failed to be handled.")?;
+        writeln!(out, "# TYPE mock_dst_fs_watcher_errors_total counter")?;
+        writeln!(out, "mock_dst_fs_watcher_errors_total {}", self.fs_errors)?;
+
+        writeln!(out, "# HELP mock_dst_stream_lifetime_seconds How long closed destination streams were open.")?;
+        writeln!(out, "# TYPE mock_dst_stream_lifetime_seconds histogram")?;
+        for (rpc, histogram) in &self.lifetimes {
+            histogram.render(*rpc, out)?;
+        }
+
+        Ok(())
+    }
+}
+
+// === impl StreamGuard ===
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut inner = self.metrics.lock();
+        let key = (self.rpc, self.dst.clone(), self.client_id.take());
+        if let Some(count) = inner.streams.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                inner.streams.remove(&key);
+            }
+        }
+        inner
+            .lifetimes
+            .entry(self.rpc)
+            .or_default()
+            .observe(self.opened.elapsed().as_secs_f64());
+    }
+}
+
+// === impl Histogram ===
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LIFETIME_BUCKETS.iter()) {
+            if value <= *le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, rpc: Rpc, out: &mut String) -> fmt::Result {
+        for (count, le) in self.buckets.iter().zip(LIFETIME_BUCKETS.iter()) {
+            writeln!(
+                out,
+                "mock_dst_stream_lifetime_seconds_bucket{{rpc=\"{}\",le=\"{}\"}} {}",
+                rpc, le, count
+            )?;
+        }
+        writeln!(
+            out,
+            "mock_dst_stream_lifetime_seconds_bucket{{rpc=\"{}\",le=\"+Inf\"}} {}",
+            rpc, self.count
+        )?;
+        writeln!(
+            out,
+            "mock_dst_stream_lifetime_seconds_sum{{rpc=\"{}\"}} {}",
+            rpc, self.sum
+        )?;
+        writeln!(
+            out,
+            "mock_dst_stream_lifetime_seconds_count{{rpc=\"{}\"}} {}",
+            rpc, self.count
+        )
+    }
+}
+
+// === impl Rpc ===
+
+impl fmt::Display for Rpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rpc::Get => f.write_str("get"),
+            Rpc::GetProfile => f.write_str("get_profile"),
+        }
+    }
+}
+
+// === impl UpdateKind ===
+
+impl fmt::Display for UpdateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateKind::Add => f.write_str("add"),
+            UpdateKind::Remove => f.write_str("remove"),
+            UpdateKind::NoEndpoints => f.write_str("no_endpoints"),
+            UpdateKind::Profile => f.write_str("profile"),
+        }
+    }
+}
+
+/// Escapes a value for use in a label.
+struct Escape<'a, T>(&'a T);
+
+impl<T: fmt::Display> fmt::Display for Escape<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.to_string().chars() {
+            match c {
+                '\\' => f.write_str("\\\\")?,
+                '"' => f.write_str("\\\"")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{Dst, EndpointsSpec, OverridesSpec};
+
+/// How the names that destinations are served and looked up by are
+/// normalized before they are matched.
+///
+/// By default, names are matched literally and must have a port. Normalizing
+/// like the real controller resolves `foo`, `foo.ns`, `foo.ns.svc` and
+/// `FOO.ns.svc.cluster.local.` to `foo.ns.svc.cluster.local:80`, if `ns` is
+/// the default namespace or one of the known namespaces.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Normalization {
+    cluster_domain: Option<String>,
+    default_namespace: Option<String>,
+    namespaces: Vec<String>,
+    default_port: Option<u16>,
+    trim_trailing_dot: bool,
+    lowercase: bool,
+}
+
+// === impl Normalization ===
+
+impl Normalization {
+    /// Matches names literally.
+    pub fn literal() -> Self {
+        Self::default()
+    }
+
+    /// Normalizes names like the real controller in a cluster with the given
+    /// domain: names are qualified in it, are case-insensitive, may end
+    /// with a dot, and default to port 80.
+    pub fn cluster(cluster_domain: impl Into<String>) -> Self {
+        Self::literal()
+            .cluster_domain(Some(cluster_domain.into()))
+            .default_port(Some(80))
+            .trim_trailing_dot(true)
+            .lowercase(true)
+    }
+
+    /// Qualifies names like `foo.ns.svc`, and names like `foo.ns` in known
+    /// namespaces, in `cluster_domain`, if it is set.
+    pub fn cluster_domain(self, cluster_domain: Option<String>) -> Self {
+        Self {
+            cluster_domain,
+            ..self
+        }
+    }
+
+    /// Qualifies single-label names like `foo` in `default_namespace`, if it
+    /// and a cluster domain are set.
+    pub fn default_namespace(self, default_namespace: Option<String>) -> Self {
+        Self {
+            default_namespace,
+            ..self
+        }
+    }
+
+    /// Qualifies names like `foo.ns` in the cluster domain if `ns` is one of
+    /// `namespaces` or the default namespace.
+    ///
+    /// Other two-label names, like `example.com`, may be external names, so
+    /// they are left as they are.
+    pub fn namespaces(self, namespaces: Vec<String>) -> Self {
+        Self { namespaces, ..self }
+    }
+
+    /// Looks up names without a port on `default_port`, if it is set.
+    pub fn default_port(self, default_port: Option<u16>) -> Self {
+        Self {
+            default_port,
+            ..self
+        }
+    }
+
+    pub fn trim_trailing_dot(self, trim_trailing_dot: bool) -> Self {
+        Self {
+            trim_trailing_dot,
+            ..self
+        }
+    }
+
+    /// Matches names case-insensitively, by lowercasing them.
+    pub fn lowercase(self, lowercase: bool) -> Self {
+        Self { lowercase, ..self }
+    }
+
+    /// Returns the destination that `path` is normalized to, if it is valid.
+    pub fn dst(&self, path: &str) -> Option<Dst> {
+        let mut parts = path.splitn(2, ':');
+        let (name, port) = match (parts.next(), parts.next()) {
+            (Some(name), Some(port)) => (name, port.parse().ok()?),
+            (Some(name), None) => (name, self.default_port?),
+            (None, _) => return None,
+        };
+        Some(Dst::new(self.name(name), port))
+    }
+
+    /// Returns the destination that a served destination is normalized to.
+    pub fn normalize(&self, dst: Dst) -> Dst {
+        let name = self.name(dst.name());
+        Dst::new(name, dst.port())
+    }
+
+    /// Returns the name that a destination's name, without a port, is
+    /// normalized to.
+    pub fn name(&self, name: &str) -> String {
+        let mut name = name;
+        if self.trim_trailing_dot && name.ends_with('.') {
+            name = &name[..name.len() - 1];
+        }
+        let name = if self.lowercase {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        };
+        match self.cluster_domain {
+            Some(ref cluster_domain) => self.qualify(name, cluster_domain),
+            None => name,
+        }
+    }
+
+    pub(crate) fn endpoints(&self, spec: EndpointsSpec) -> EndpointsSpec {
+        let dsts = spec
+            .dsts
+            .into_iter()
+            .map(|(dst, endpoints)| (self.normalize(dst), endpoints))
+            .collect();
+        EndpointsSpec { dsts }
+    }
+
+    pub(crate) fn overrides(&self, spec: OverridesSpec) -> OverridesSpec {
+        let dsts = spec
+            .dsts
+            .into_iter()
+            .map(|(dst, overrides)| (self.normalize(dst), overrides))
+            .collect();
+        OverridesSpec { dsts }
+    }
+
+    /// Qualifies the name of a service in the cluster domain, as the real
+    /// controller does. Other names, like fully qualified ones, two-label
+    /// names outside of the known namespaces or IP addresses, are left as
+    /// they are.
+    fn qualify(&self, name: String, cluster_domain: &str) -> String {
+        let default_namespace = self.default_namespace.as_deref();
+        let qualified = {
+            let labels = name.split('.').collect::<Vec<_>>();
+            match (labels.as_slice(), default_namespace.as_ref()) {
+                ([svc], Some(ns)) | ([svc, ns, "svc"], _) => {
+                    Some(format!("{}.{}.svc.{}", svc, ns, cluster_domain))
+                }
+                ([svc, ns], _) if self.is_namespace(ns) => {
+                    Some(format!("{}.{}.svc.{}", svc, ns, cluster_domain))
+                }
+                _ => None,
+            }
+        };
+        qualified.unwrap_or(name)
+    }
+
+    fn is_namespace(&self, ns: &str) -> bool {
+        self.default_namespace.as_deref() == Some(ns) || self.namespaces.iter().any(|n| n == ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> Normalization {
+        Normalization::cluster("cluster.local")
+            .default_namespace(Some("ns".to_string()))
+            .namespaces(vec!["other".to_string()])
+    }
+
+    fn web(port: u16) -> Option<Dst> {
+        Some(Dst::new("foo.ns.svc.cluster.local".to_string(), port))
+    }
+
+    #[test]
+    fn qualifies_names_in_the_cluster_domain() {
+        let normalization = cluster();
+        assert_eq!(normalization.dst("foo"), web(80));
+        assert_eq!(normalization.dst("foo:8080"), web(8080));
+        assert_eq!(normalization.dst("foo.ns"), web(80));
+        assert_eq!(normalization.dst("foo.ns.svc"), web(80));
+        assert_eq!(normalization.dst("foo.ns.svc.cluster.local"), web(80));
+        assert_eq!(
+            normalization.dst("foo.other"),
+            Some(Dst::new("foo.other.svc.cluster.local".to_string(), 80))
+        );
+    }
+
+    #[test]
+    fn single_labels_require_a_default_namespace() {
+        let normalization = Normalization::cluster("cluster.local");
+        assert_eq!(
+            normalization.dst("foo"),
+            Some(Dst::new("foo".to_string(), 80))
+        );
+    }
+
+    #[test]
+    fn trims_trailing_dots_and_lowercases() {
+        let normalization = cluster();
+        assert_eq!(normalization.dst("foo.ns.svc.cluster.local.:80"), web(80));
+        assert_eq!(normalization.dst("FOO.NS.svc.cluster.local"), web(80));
+        assert_eq!(normalization.dst("Foo.Ns"), web(80));
+    }
+
+    #[test]
+    fn leaves_other_names() {
+        let normalization = cluster();
+        assert_eq!(
+            normalization.dst("api.example.com.:443"),
+            Some(Dst::new("api.example.com".to_string(), 443))
+        );
+        assert_eq!(
+            normalization.dst("10.1.0.1:8080"),
+            Some(Dst::new("10.1.0.1".to_string(), 8080))
+        );
+    }
+
+    #[test]
+    fn leaves_external_two_label_names() {
+        let normalization = cluster();
+        assert_eq!(
+            normalization.dst("example.com:443"),
+            Some(Dst::new("example.com".to_string(), 443))
+        );
+        assert_eq!(
+            normalization.dst("Foo.Bar."),
+            Some(Dst::new("foo.bar".to_string(), 80))
+        );
+        assert_eq!(
+            normalization.dst("foo.bar.svc"),
+            Some(Dst::new("foo.bar.svc.cluster.local".to_string(), 80))
+        );
+    }
+
+    #[test]
+    fn literal_names_are_unchanged() {
+        let normalization = Normalization::literal();
+        assert_eq!(normalization.dst("foo"), None);
+        assert_eq!(
+            normalization.dst("FOO.ns.:80"),
+            Some(Dst::new("FOO.ns.".to_string(), 80))
+        );
+        assert_eq!(normalization.dst("foo:http"), None);
+    }
+
+    #[test]
+    fn normalizes_served_destinations() {
+        let normalization = cluster();
+        let served = Dst::new("FOO.ns.svc.cluster.local.".to_string(), 80);
+        assert_eq!(Some(normalization.normalize(served)), web(80));
+        assert_eq!(normalization.name("foo.ns"), "foo.ns.svc.cluster.local");
+    }
+}
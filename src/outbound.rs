@@ -0,0 +1,901 @@
+use crate::{
+    http_route::{host_match, GrpcRouteMatch, HttpRouteMatch},
+    policy,
+    proxy_api::{
+        http_route::Timeouts as TimeoutsPb,
+        meta::Metadata,
+        outbound::{self as pb, outbound_policies_server::OutboundPolicies},
+    },
+    spec::{deserialize_codes, deserialize_duration},
+    Dst, Error,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, time::Duration};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing_futures::Instrument;
+
+pub(crate) use self::pb::outbound_policies_server::OutboundPoliciesServer;
+
+/// Serves the policies of proxies' outbound traffic, by target.
+#[derive(Clone, Debug)]
+pub struct OutboundPolicyService {
+    policies: policy::Store<Target, OutboundPolicy>,
+}
+
+/// Changes the policies served by an `OutboundPolicyService` at runtime.
+#[derive(Debug)]
+pub struct OutboundPolicySender {
+    policies: policy::Sender<Target, OutboundPolicy>,
+}
+
+/// Loads the policies in a directory into an `OutboundPolicySender`, and then
+/// keeps them up to date as the directory changes.
+///
+/// Each file is named for the target its policy applies to, e.g.
+/// `web.ns.svc.cluster.local:8080.yaml` or `10.0.0.1:8080.yaml`, and contains
+/// the JSON or YAML representation of an `OutboundPolicy`.
+#[derive(Debug)]
+pub struct OutboundPolicyWatcher {
+    dir: PathBuf,
+    sender: OutboundPolicySender,
+    ready: Option<oneshot::Sender<()>>,
+}
+
+/// The target of outbound traffic, as observed by the proxy.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Addr(SocketAddr),
+    Authority(Dst),
+}
+
+/// The policy of an outbound target.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct OutboundPolicy {
+    #[serde(default)]
+    pub protocol: OutboundProtocol,
+    /// How long to wait to detect the protocol, if it is not known.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub detect_timeout: Option<Duration>,
+    /// Routes for HTTP/1 and HTTP/2 traffic.
+    #[serde(default)]
+    pub http_routes: Vec<OutboundHttpRoute>,
+    /// Routes for gRPC traffic.
+    #[serde(default)]
+    pub grpc_routes: Vec<OutboundGrpcRoute>,
+    /// The backends of the default route, used if there are no routes for
+    /// the protocol. If empty, an authority target is its own backend.
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+    /// If unset, backends' endpoints are never marked as failed.
+    #[serde(default)]
+    pub failure_accrual: Option<FailureAccrual>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboundProtocol {
+    Detect,
+    Http1,
+    Http2,
+    Grpc,
+    Opaque,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct OutboundHttpRoute {
+    pub name: String,
+    /// Hosts such as `www.example.com` or `*.example.com`. If empty, all
+    /// hosts match.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// If empty, all requests match.
+    #[serde(default)]
+    pub matches: Vec<HttpRouteMatch>,
+    /// If empty, the policy's default backends are used.
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub retry: Option<Retry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct OutboundGrpcRoute {
+    pub name: String,
+    /// Hosts such as `www.example.com` or `*.example.com`. If empty, all
+    /// hosts match.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// If empty, all requests match.
+    #[serde(default)]
+    pub matches: Vec<GrpcRouteMatch>,
+    /// If empty, the policy's default backends are used.
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub retry: Option<Retry>,
+}
+
+/// A destination that traffic is balanced over, with endpoints discovered
+/// from the destination service.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Backend {
+    pub dst: Dst,
+    /// The backend's share of traffic relative to the route's other backends.
+    #[serde(default = "Backend::default_weight")]
+    pub weight: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct Timeouts {
+    /// Limits the time from when a request is received to when its response
+    /// is complete, including retries.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub request: Option<Duration>,
+    /// Limits the time spent waiting for each response.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub response: Option<Duration>,
+    /// Limits the time a stream may be idle.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub idle: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Retry {
+    pub max_retries: u32,
+    /// Statuses of HTTP responses that are retried, like `5xx`, `503` or
+    /// `500-504`. If empty, `5xx` responses are retried.
+    #[serde(default)]
+    pub statuses: Vec<StatusRange>,
+    /// Codes of gRPC responses that are retried, like `unavailable`. Only
+    /// `cancelled`, `deadline_exceeded`, `resource_exhausted`, `internal`
+    /// and `unavailable` may be retried. If empty, `unavailable` responses
+    /// are retried.
+    #[serde(default, deserialize_with = "deserialize_codes")]
+    pub codes: Vec<tonic::Code>,
+    /// Limits the time spent waiting for each attempt's response.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// If unset, retries back off from 25ms to 250ms.
+    #[serde(default)]
+    pub backoff: Option<Backoff>,
+}
+
+/// An inclusive range of HTTP statuses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct StatusRange {
+    start: u16,
+    end: u16,
+}
+
+/// Marks an endpoint as failed after `max_failures` consecutive failures,
+/// and backs off before probing it again.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct FailureAccrual {
+    pub max_failures: u32,
+    /// If unset, probes back off from 1s to 60s.
+    #[serde(default)]
+    pub backoff: Option<Backoff>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Backoff {
+    /// If unset, the default minimum is used.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub min: Option<Duration>,
+    /// If unset, the default maximum is used.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max: Option<Duration>,
+    /// The ratio of the backoff that may be randomly added to it.
+    #[serde(default)]
+    pub jitter: f32,
+}
+
+/// The largest request body that is buffered so that it may be retried.
+const MAX_RETRY_REQUEST_BYTES: u32 = 64 * 1024;
+
+type GrpcResult<T> = Result<T, tonic::Status>;
+
+// === impl OutboundPolicyService ===
+
+impl OutboundPolicyService {
+    pub fn empty() -> (OutboundPolicySender, OutboundPolicyService) {
+        Self::new(HashMap::new())
+    }
+
+    pub fn new(
+        policies: HashMap<Target, OutboundPolicy>,
+    ) -> (OutboundPolicySender, OutboundPolicyService) {
+        let (policies, store) = policy::Store::new(policies);
+        (OutboundPolicySender { policies }, Self { policies: store })
+    }
+
+    /// Ends all open streams, and any streams opened later.
+    pub fn drain(&self) {
+        self.policies.drain()
+    }
+
+    async fn policy_rx(
+        &self,
+        spec: pb::TrafficSpec,
+    ) -> GrpcResult<(Target, watch::Receiver<OutboundPolicy>)> {
+        let target = match spec.target {
+            Some(pb::traffic_spec::Target::Addr(addr)) => match addr.to_socket_addr() {
+                Some(addr) => Target::Addr(addr),
+                None => return Err(tonic::Status::invalid_argument("invalid address")),
+            },
+            Some(pb::traffic_spec::Target::Authority(authority)) => match authority.parse() {
+                Ok(dst) => Target::Authority(dst),
+                Err(_) => return Err(tonic::Status::invalid_argument("invalid authority")),
+            },
+            None => return Err(tonic::Status::invalid_argument("missing target")),
+        };
+        match self.policies.get(&target).await {
+            Some(rx) => Ok((target, rx)),
+            None => {
+                tracing::info!(?target, "Does not exist");
+                Err(tonic::Status::not_found("no policy for target"))
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl OutboundPolicies for OutboundPolicyService {
+    type WatchStream = mpsc::Receiver<GrpcResult<pb::OutboundPolicy>>;
+
+    async fn get(
+        &self,
+        req: tonic::Request<pb::TrafficSpec>,
+    ) -> GrpcResult<tonic::Response<pb::OutboundPolicy>> {
+        let spec = req.into_inner();
+        let span = tracing::info_span!("get", %spec.source_workload);
+        let (target, rx) = self.policy_rx(spec).instrument(span).await?;
+        let policy = rx.borrow().to_pb(&target);
+        Ok(tonic::Response::new(policy))
+    }
+
+    async fn watch(
+        &self,
+        req: tonic::Request<pb::TrafficSpec>,
+    ) -> GrpcResult<tonic::Response<Self::WatchStream>> {
+        let spec = req.into_inner();
+        let span = tracing::info_span!("watch", %spec.source_workload);
+        let (target, policy_rx) = self.policy_rx(spec).instrument(span.clone()).await?;
+        tracing::info!(parent: &span, ?target, "Serving policy");
+        let rx = self
+            .policies
+            .stream(policy_rx, span, move |policy| policy.to_pb(&target));
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+// === impl OutboundPolicySender ===
+
+impl OutboundPolicySender {
+    #[tracing::instrument(skip(self), name = "OutboundPolicySender::send_policy", level = "info")]
+    pub async fn send_policy(
+        &mut self,
+        target: Target,
+        policy: OutboundPolicy,
+    ) -> Result<(), Error> {
+        self.policies.send(target, policy).await
+    }
+
+    #[tracing::instrument(
+        skip(self),
+        name = "OutboundPolicySender::delete_policy",
+        level = "info"
+    )]
+    pub async fn delete_policy(&mut self, target: Target) {
+        self.policies.delete(&target).await
+    }
+}
+
+// === impl OutboundPolicyWatcher ===
+
+impl OutboundPolicyWatcher {
+    pub fn new(dir: PathBuf, sender: OutboundPolicySender) -> Self {
+        Self {
+            dir,
+            sender,
+            ready: None,
+        }
+    }
+
+    /// Notifies `ready` once the policies already in the directory have been
+    /// loaded.
+    pub fn with_ready(self, ready: oneshot::Sender<()>) -> Self {
+        Self {
+            ready: Some(ready),
+            ..self
+        }
+    }
+
+    pub async fn watch(&mut self) -> Result<(), Error> {
+        let ready = self.ready.take();
+        policy::watch_dir(&self.dir, &mut self.sender.policies, ready).await
+    }
+}
+
+// === impl OutboundPolicy ===
+
+impl OutboundPolicy {
+    fn to_pb(&self, target: &Target) -> pb::OutboundPolicy {
+        let backends = if self.backends.is_empty() {
+            match target {
+                Target::Authority(dst) => vec![Backend {
+                    dst: dst.clone(),
+                    weight: Backend::default_weight(),
+                }],
+                Target::Addr(_) => Vec::new(),
+            }
+        } else {
+            self.backends.clone()
+        };
+
+        let http_routes = if self.http_routes.is_empty() {
+            vec![OutboundHttpRoute {
+                name: String::new(),
+                hosts: Vec::new(),
+                matches: Vec::new(),
+                backends: Vec::new(),
+                timeouts: Timeouts::default(),
+                retry: None,
+            }
+            .to_pb(Metadata::for_default("default"), &backends)]
+        } else {
+            self.http_routes
+                .iter()
+                .map(|route| route.to_pb(Metadata::resource("HTTPRoute", &route.name), &backends))
+                .collect()
+        };
+        let failure_accrual = self.failure_accrual.as_ref().map(FailureAccrual::to_pb);
+
+        use pb::proxy_protocol::{Detect, Grpc, Http1, Http2, Kind, Opaque};
+        let kind = match self.protocol {
+            OutboundProtocol::Detect => Kind::Detect(Detect {
+                timeout: Some(
+                    self.detect_timeout
+                        .unwrap_or_else(|| Duration::from_secs(10))
+                        .into(),
+                ),
+                http1: Some(Http1 {
+                    routes: http_routes.clone(),
+                    failure_accrual: failure_accrual.clone(),
+                }),
+                http2: Some(Http2 {
+                    routes: http_routes,
+                    failure_accrual,
+                }),
+            }),
+            OutboundProtocol::Http1 => Kind::Http1(Http1 {
+                routes: http_routes,
+                failure_accrual,
+            }),
+            OutboundProtocol::Http2 => Kind::Http2(Http2 {
+                routes: http_routes,
+                failure_accrual,
+            }),
+            OutboundProtocol::Grpc => {
+                let routes = if self.grpc_routes.is_empty() {
+                    vec![OutboundGrpcRoute {
+                        name: String::new(),
+                        hosts: Vec::new(),
+                        matches: Vec::new(),
+                        backends: Vec::new(),
+                        timeouts: Timeouts::default(),
+                        retry: None,
+                    }
+                    .to_pb(Metadata::for_default("default"), &backends)]
+                } else {
+                    self.grpc_routes
+                        .iter()
+                        .map(|route| {
+                            route.to_pb(Metadata::resource("GRPCRoute", &route.name), &backends)
+                        })
+                        .collect()
+                };
+                Kind::Grpc(Grpc {
+                    routes,
+                    failure_accrual,
+                })
+            }
+            OutboundProtocol::Opaque => Kind::Opaque(Opaque {}),
+        };
+
+        let metadata = match target {
+            Target::Authority(dst) => Metadata::service(dst),
+            Target::Addr(addr) => Metadata::for_default(&addr.to_string()),
+        };
+        pb::OutboundPolicy {
+            protocol: Some(pb::ProxyProtocol { kind: Some(kind) }),
+            metadata: Some(metadata),
+        }
+    }
+}
+
+impl Default for OutboundProtocol {
+    fn default() -> Self {
+        OutboundProtocol::Detect
+    }
+}
+
+// === impl OutboundHttpRoute ===
+
+impl OutboundHttpRoute {
+    fn to_pb(&self, metadata: Metadata, default_backends: &[Backend]) -> pb::HttpRoute {
+        use pb::http_route::{distribution, Distribution, RouteBackend, WeightedRouteBackend};
+
+        let backends = if self.backends.is_empty() {
+            default_backends
+        } else {
+            &self.backends
+        };
+        let distribution = if backends.is_empty() {
+            distribution::Kind::Empty(distribution::Empty {})
+        } else {
+            distribution::Kind::RandomAvailable(distribution::RandomAvailable {
+                backends: backends
+                    .iter()
+                    .map(|backend| WeightedRouteBackend {
+                        backend: Some(RouteBackend {
+                            backend: Some(backend.to_pb()),
+                        }),
+                        weight: backend.weight,
+                    })
+                    .collect(),
+            })
+        };
+
+        let matches = if self.matches.is_empty() {
+            vec![HttpRouteMatch::default().to_pb()]
+        } else {
+            self.matches.iter().map(HttpRouteMatch::to_pb).collect()
+        };
+
+        pb::HttpRoute {
+            metadata: Some(metadata),
+            hosts: self.hosts.iter().map(|host| host_match(host)).collect(),
+            rules: vec![pb::http_route::Rule {
+                matches,
+                backends: Some(Distribution {
+                    kind: Some(distribution),
+                }),
+                timeouts: Some(self.timeouts.to_pb()),
+                retry: self.retry.as_ref().map(Retry::to_http_pb),
+            }],
+        }
+    }
+}
+
+// === impl OutboundGrpcRoute ===
+
+impl OutboundGrpcRoute {
+    fn to_pb(&self, metadata: Metadata, default_backends: &[Backend]) -> pb::GrpcRoute {
+        use pb::grpc_route::{distribution, Distribution, RouteBackend, WeightedRouteBackend};
+
+        let backends = if self.backends.is_empty() {
+            default_backends
+        } else {
+            &self.backends
+        };
+        let distribution = if backends.is_empty() {
+            distribution::Kind::Empty(distribution::Empty {})
+        } else {
+            distribution::Kind::RandomAvailable(distribution::RandomAvailable {
+                backends: backends
+                    .iter()
+                    .map(|backend| WeightedRouteBackend {
+                        backend: Some(RouteBackend {
+                            backend: Some(backend.to_pb()),
+                        }),
+                        weight: backend.weight,
+                    })
+                    .collect(),
+            })
+        };
+
+        let matches = if self.matches.is_empty() {
+            vec![GrpcRouteMatch::default().to_pb()]
+        } else {
+            self.matches.iter().map(GrpcRouteMatch::to_pb).collect()
+        };
+
+        pb::GrpcRoute {
+            metadata: Some(metadata),
+            hosts: self.hosts.iter().map(|host| host_match(host)).collect(),
+            rules: vec![pb::grpc_route::Rule {
+                matches,
+                backends: Some(Distribution {
+                    kind: Some(distribution),
+                }),
+                timeouts: Some(self.timeouts.to_pb()),
+                retry: self.retry.as_ref().map(Retry::to_grpc_pb),
+            }],
+        }
+    }
+}
+
+// === impl Backend ===
+
+impl Backend {
+    fn default_weight() -> u32 {
+        1
+    }
+
+    fn to_pb(&self) -> pb::Backend {
+        use pb::backend::{
+            balance_p2c::{Load, PeakEwma},
+            endpoint_discovery::{self, DestinationGet},
+            BalanceP2c, EndpointDiscovery, Kind,
+        };
+
+        let balancer = BalanceP2c {
+            discovery: Some(EndpointDiscovery {
+                kind: Some(endpoint_discovery::Kind::Dst(DestinationGet {
+                    path: self.dst.to_string(),
+                })),
+            }),
+            load: Some(Load::PeakEwma(PeakEwma {
+                default_rtt: Some(Duration::from_millis(30).into()),
+                decay: Some(Duration::from_secs(10).into()),
+            })),
+        };
+        pb::Backend {
+            metadata: Some(Metadata::service(&self.dst)),
+            kind: Some(Kind::Balancer(balancer)),
+            queue: Some(pb::Queue {
+                capacity: 100,
+                failfast_timeout: Some(Duration::from_secs(3).into()),
+            }),
+        }
+    }
+}
+
+// === impl Timeouts ===
+
+impl Timeouts {
+    fn to_pb(&self) -> TimeoutsPb {
+        TimeoutsPb {
+            response: self.response.map(Into::into),
+            request: self.request.map(Into::into),
+            idle: self.idle.map(Into::into),
+        }
+    }
+}
+
+// === impl Retry ===
+
+impl Retry {
+    fn to_http_pb(&self) -> pb::http_route::Retry {
+        use pb::http_route::retry::{conditions::StatusRange as StatusRangePb, Conditions};
+
+        let statuses = if self.statuses.is_empty() {
+            vec![StatusRange::new(500, 599)]
+        } else {
+            self.statuses.clone()
+        };
+        pb::http_route::Retry {
+            max_retries: self.max_retries,
+            max_request_bytes: MAX_RETRY_REQUEST_BYTES,
+            conditions: Some(Conditions {
+                status_ranges: statuses
+                    .iter()
+                    .map(|range| StatusRangePb {
+                        start: range.start.into(),
+                        end: range.end.into(),
+                    })
+                    .collect(),
+            }),
+            timeout: self.timeout.map(Into::into),
+            backoff: Some(self.backoff_pb()),
+        }
+    }
+
+    fn to_grpc_pb(&self) -> pb::grpc_route::Retry {
+        use tonic::Code;
+
+        let codes = if self.codes.is_empty() {
+            vec![Code::Unavailable]
+        } else {
+            self.codes.clone()
+        };
+        pb::grpc_route::Retry {
+            max_retries: self.max_retries,
+            max_request_bytes: MAX_RETRY_REQUEST_BYTES,
+            conditions: Some(pb::grpc_route::retry::Conditions {
+                cancelled: codes.contains(&Code::Cancelled),
+                deadine_exceeded: codes.contains(&Code::DeadlineExceeded),
+                resource_exhausted: codes.contains(&Code::ResourceExhausted),
+                internal: codes.contains(&Code::Internal),
+                unavailable: codes.contains(&Code::Unavailable),
+            }),
+            timeout: self.timeout.map(Into::into),
+            backoff: Some(self.backoff_pb()),
+        }
+    }
+
+    fn backoff_pb(&self) -> pb::ExponentialBackoff {
+        let default = Backoff {
+            min: Some(Duration::from_millis(25)),
+            max: Some(Duration::from_millis(250)),
+            jitter: 0.0,
+        };
+        self.backoff.as_ref().unwrap_or(&default).to_pb(&default)
+    }
+}
+
+// === impl StatusRange ===
+
+impl StatusRange {
+    pub(crate) fn new(start: u16, end: u16) -> Self {
+        Self { start, end }
+    }
+}
+
+// === impl FailureAccrual ===
+
+impl FailureAccrual {
+    fn to_pb(&self) -> pb::FailureAccrual {
+        use pb::failure_accrual::{ConsecutiveFailures, Kind};
+
+        let default = Backoff {
+            min: Some(Duration::from_secs(1)),
+            max: Some(Duration::from_secs(60)),
+            jitter: 0.5,
+        };
+        pb::FailureAccrual {
+            kind: Some(Kind::ConsecutiveFailures(ConsecutiveFailures {
+                max_failures: self.max_failures,
+                backoff: Some(self.backoff.as_ref().unwrap_or(&default).to_pb(&default)),
+            })),
+        }
+    }
+}
+
+// === impl Backoff ===
+
+impl Backoff {
+    /// Uses the bounds in `default` for any that are unset.
+    fn to_pb(&self, default: &Backoff) -> pb::ExponentialBackoff {
+        pb::ExponentialBackoff {
+            min_backoff: self.min.or(default.min).map(Into::into),
+            max_backoff: self.max.or(default.max).map(Into::into),
+            jitter_ratio: self.jitter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_api::net;
+
+    const WEB: &str = "web.ns.svc.cluster.local:8080";
+
+    fn retry(yaml: &str) -> Result<Retry, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    fn status_ranges(retry: &pb::http_route::Retry) -> Vec<(u32, u32)> {
+        retry
+            .conditions
+            .as_ref()
+            .expect("conditions must be set")
+            .status_ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    fn http1_routes(policy: &pb::OutboundPolicy) -> &[pb::HttpRoute] {
+        use pb::proxy_protocol::Kind;
+
+        match policy.protocol.as_ref().and_then(|p| p.kind.as_ref()) {
+            Some(Kind::Detect(detect)) => &detect.http1.as_ref().expect("http1 must be set").routes,
+            Some(Kind::Http1(http1)) => &http1.routes,
+            kind => panic!("unexpected protocol {:?}", kind),
+        }
+    }
+
+    /// Returns each backend of a route's only rule, with its weight.
+    fn backends(route: &pb::HttpRoute) -> Vec<(String, u32)> {
+        use pb::backend::{endpoint_discovery, Kind as BackendKind};
+        use pb::http_route::distribution::Kind;
+
+        let distribution = route.rules[0]
+            .backends
+            .as_ref()
+            .and_then(|d| d.kind.as_ref())
+            .expect("distribution must be set");
+        let backends = match distribution {
+            Kind::Empty(_) => return Vec::new(),
+            Kind::RandomAvailable(random) => &random.backends,
+            kind => panic!("unexpected distribution {:?}", kind),
+        };
+        backends
+            .iter()
+            .map(|weighted| {
+                let backend = weighted
+                    .backend
+                    .as_ref()
+                    .and_then(|b| b.backend.as_ref())
+                    .expect("backend must be set");
+                let path = match backend.kind {
+                    Some(BackendKind::Balancer(ref balancer)) => {
+                        match balancer.discovery.as_ref().and_then(|d| d.kind.as_ref()) {
+                            Some(endpoint_discovery::Kind::Dst(dst)) => dst.path.clone(),
+                            kind => panic!("unexpected discovery {:?}", kind),
+                        }
+                    }
+                    ref kind => panic!("unexpected backend {:?}", kind),
+                };
+                (path, weighted.weight)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retries_parse_statuses_and_codes() {
+        let retry = retry(
+            r#"
+max_retries: 3
+statuses: ["5xx", "429", "500-504"]
+codes: ["internal", "14"]
+timeout: 500ms
+"#,
+        )
+        .expect("retry must be valid");
+
+        let http = retry.to_http_pb();
+        assert_eq!(http.max_retries, 3);
+        assert_eq!(http.max_request_bytes, 64 * 1024);
+        assert_eq!(status_ranges(&http), [(500, 599), (429, 429), (500, 504)]);
+        assert_eq!(http.timeout, Some(Duration::from_millis(500).into()));
+
+        let grpc = retry.to_grpc_pb();
+        assert_eq!(grpc.max_request_bytes, MAX_RETRY_REQUEST_BYTES);
+        assert_eq!(
+            grpc.conditions,
+            Some(pb::grpc_route::retry::Conditions {
+                cancelled: false,
+                deadine_exceeded: false,
+                resource_exhausted: false,
+                internal: true,
+                unavailable: true,
+            })
+        );
+    }
+
+    #[test]
+    fn retries_default_to_5xx_and_unavailable() {
+        let retry = retry("max_retries: 1").expect("retry must be valid");
+
+        let http = retry.to_http_pb();
+        assert_eq!(status_ranges(&http), [(500, 599)]);
+        assert_eq!(
+            http.backoff,
+            Some(pb::ExponentialBackoff {
+                min_backoff: Some(Duration::from_millis(25).into()),
+                max_backoff: Some(Duration::from_millis(250).into()),
+                jitter_ratio: 0.0,
+            })
+        );
+
+        let conditions = retry
+            .to_grpc_pb()
+            .conditions
+            .expect("conditions must be set");
+        assert!(conditions.unavailable);
+        assert!(!conditions.cancelled && !conditions.internal);
+    }
+
+    #[test]
+    fn rejects_invalid_retries() {
+        for statuses in &["600", "99", "504-500", "5yy", "500-"] {
+            let yaml = format!("{{max_retries: 1, statuses: [\"{}\"]}}", statuses);
+            assert!(retry(&yaml).is_err(), "{}", statuses);
+        }
+        for code in &["bogus", "17"] {
+            let yaml = format!("{{max_retries: 1, codes: [\"{}\"]}}", code);
+            assert!(retry(&yaml).is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn default_routes_use_the_default_backends() {
+        let web = Target::Authority(WEB.parse().unwrap());
+        let policy = OutboundPolicy::default().to_pb(&web);
+        assert_eq!(
+            policy.metadata,
+            Some(Metadata::service(&WEB.parse().unwrap()))
+        );
+        let routes = http1_routes(&policy);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].metadata, Some(Metadata::for_default("default")));
+        assert_eq!(backends(&routes[0]), [(WEB.to_string(), 1)]);
+        assert_eq!(routes[0].rules[0].retry, None);
+
+        // Addresses have no default backend.
+        let addr = Target::Addr("10.0.0.1:8080".parse().unwrap());
+        let policy = OutboundPolicy::default().to_pb(&addr);
+        assert!(backends(&http1_routes(&policy)[0]).is_empty());
+
+        let policy = serde_yaml::from_str::<OutboundPolicy>(
+            r#"
+protocol: http1
+backends:
+  - dst: web-v1.ns.svc.cluster.local:8080
+    weight: 9
+  - dst: web-v2.ns.svc.cluster.local:8080
+http_routes:
+  - name: default
+  - name: canary
+    backends:
+      - dst: web-v2.ns.svc.cluster.local:8080
+"#,
+        )
+        .expect("policy must be valid")
+        .to_pb(&web);
+        let routes = http1_routes(&policy);
+        assert_eq!(
+            backends(&routes[0]),
+            [
+                ("web-v1.ns.svc.cluster.local:8080".to_string(), 9),
+                ("web-v2.ns.svc.cluster.local:8080".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            routes[1].metadata,
+            Some(Metadata::resource("HTTPRoute", "canary"))
+        );
+        assert_eq!(
+            backends(&routes[1]),
+            [("web-v2.ns.svc.cluster.local:8080".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn watches_are_sent_policies_until_they_are_deleted() -> Result<(), Error> {
+        let (mut sender, svc) = OutboundPolicyService::empty();
+        let target = Target::Addr("10.0.0.1:8080".parse().unwrap());
+        let spec = || {
+            let addr = "10.0.0.1:8080".parse::<SocketAddr>().unwrap();
+            tonic::Request::new(pb::TrafficSpec {
+                source_workload: "web".to_string(),
+                target: Some(pb::traffic_spec::Target::Addr(net::TcpAddress {
+                    ip: Some(addr.ip().into()),
+                    port: addr.port().into(),
+                })),
+            })
+        };
+        let status = svc.watch(spec()).await.expect_err("target must not exist");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        sender
+            .send_policy(target.clone(), OutboundPolicy::default())
+            .await?;
+        let mut watch = svc.watch(spec()).await?.into_inner();
+        let policy = watch.recv().await.expect("policy must be served")?;
+        assert_eq!(policy, OutboundPolicy::default().to_pb(&target));
+
+        let opaque = OutboundPolicy {
+            protocol: OutboundProtocol::Opaque,
+            ..OutboundPolicy::default()
+        };
+        sender.send_policy(target.clone(), opaque.clone()).await?;
+        let policy = watch.recv().await.expect("update must be served")?;
+        assert_eq!(policy, opaque.to_pb(&target));
+
+        sender.delete_policy(target).await;
+        assert!(watch.recv().await.is_none(), "watch must end");
+        let status = svc.get(spec()).await.expect_err("target must be deleted");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        Ok(())
+    }
+}
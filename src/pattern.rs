@@ -0,0 +1,137 @@
+use crate::{Dst, Endpoints, Error};
+use regex::Regex;
+use std::ops::RangeInclusive;
+
+/// Matches many destinations by name and port, such as
+/// `*.emojivoto.svc.cluster.local:8080` or `web-svc:8000-8100`.
+#[derive(Clone, Debug)]
+pub struct DstPattern {
+    name: Regex,
+    ports: RangeInclusive<u16>,
+}
+
+/// The endpoints served for each destination that a `DstPattern` matches.
+///
+/// Templates are endpoints, or an endpoints generator, in which `{port}` is
+/// replaced with the port of the destination being looked up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointsTemplate(pub(crate) String);
+
+// === impl DstPattern ===
+
+impl DstPattern {
+    /// Matches names like `glob` on any port, where each `*` matches any
+    /// part of a single DNS label.
+    pub fn glob(glob: &str) -> Self {
+        let name = glob
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join("[^.]*");
+        Self::regex(&name).expect("escaped globs are valid regexes")
+    }
+
+    /// Matches names that `regex` matches in full, on any port.
+    pub fn regex(regex: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            name: Regex::new(&format!("^(?:{})$", regex))?,
+            ports: 0..=u16::max_value(),
+        })
+    }
+
+    /// Only matches destinations with ports in `ports`.
+    pub fn ports(self, ports: RangeInclusive<u16>) -> Self {
+        Self { ports, ..self }
+    }
+
+    pub fn matches(&self, dst: &Dst) -> bool {
+        self.ports.contains(&dst.port()) && self.name.is_match(dst.name())
+    }
+}
+
+// === impl EndpointsTemplate ===
+
+impl EndpointsTemplate {
+    /// Returns the endpoints served for `dst`.
+    pub fn render(&self, dst: &Dst) -> Result<Endpoints, Error> {
+        let endpoints = self
+            .0
+            .replace("{port}", &dst.port().to_string())
+            .parse::<Endpoints>()?;
+        Ok(endpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dst(dst: &str) -> Dst {
+        dst.parse().unwrap()
+    }
+
+    #[test]
+    fn globs_match_within_labels() {
+        let pattern = DstPattern::glob("*.emojivoto.svc.cluster.local");
+        assert!(pattern.matches(&dst("web-svc.emojivoto.svc.cluster.local:80")));
+        assert!(pattern.matches(&dst("emoji-svc.emojivoto.svc.cluster.local:8080")));
+        assert!(!pattern.matches(&dst("a.web-svc.emojivoto.svc.cluster.local:80")));
+        assert!(!pattern.matches(&dst("web-svc.other.svc.cluster.local:80")));
+
+        let pattern = DstPattern::glob("web-*.ns.svc.cluster.local");
+        assert!(pattern.matches(&dst("web-1.ns.svc.cluster.local:80")));
+        assert!(pattern.matches(&dst("web-.ns.svc.cluster.local:80")));
+        assert!(!pattern.matches(&dst("api-1.ns.svc.cluster.local:80")));
+    }
+
+    #[test]
+    fn globs_escape_regex_syntax() {
+        let pattern = DstPattern::glob("web.ns");
+        assert!(pattern.matches(&dst("web.ns:80")));
+        assert!(!pattern.matches(&dst("webxns:80")));
+
+        let pattern = DstPattern::glob("c++(1).ns");
+        assert!(pattern.matches(&dst("c++(1).ns:80")));
+        assert!(!pattern.matches(&dst("cc1.ns:80")));
+    }
+
+    #[test]
+    fn regexes_match_whole_names() {
+        let pattern = DstPattern::regex(r"web-[0-9]+\.ns|api").unwrap();
+        assert!(pattern.matches(&dst("web-12.ns:80")));
+        assert!(pattern.matches(&dst("api:80")));
+        assert!(!pattern.matches(&dst("web-12.ns.svc.cluster.local:80")));
+        assert!(!pattern.matches(&dst("my-api:80")));
+        assert!(DstPattern::regex("web-(").is_err());
+    }
+
+    #[test]
+    fn ports_are_inclusive_ranges() {
+        let pattern = DstPattern::glob("web").ports(8000..=8100);
+        assert!(!pattern.matches(&dst("web:7999")));
+        assert!(pattern.matches(&dst("web:8000")));
+        assert!(pattern.matches(&dst("web:8100")));
+        assert!(!pattern.matches(&dst("web:8101")));
+        assert!(DstPattern::glob("web").matches(&dst("web:0")));
+        assert!(DstPattern::glob("web").matches(&dst("web:65535")));
+    }
+
+    #[test]
+    fn templates_render_the_requested_port() {
+        let template = EndpointsTemplate("10.0.0.1:{port},10.0.0.2:{port}#h2".to_string());
+        let endpoints = template.render(&dst("web:8081")).unwrap();
+        let mut addrs = endpoints
+            .0
+            .keys()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        addrs.sort();
+        assert_eq!(addrs, ["10.0.0.1:8081", "10.0.0.2:8081"]);
+
+        let template =
+            EndpointsTemplate("gen(cidr=10.0.0.0/24,port={port},count=10,seed=1)".to_string());
+        let endpoints = template.render(&dst("web:9000")).unwrap();
+        assert_eq!(endpoints.0.len(), 10);
+        assert!(endpoints.0.keys().all(|a| a.port() == 9000));
+    }
+}
@@ -0,0 +1,258 @@
+//! Storage shared by the policy services, which serve policies keyed by a
+//! port or a target that can each be watched until they are deleted.
+
+use crate::Error;
+use futures::prelude::*;
+use inotify::{EventMask, Inotify, WatchMask};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    hash::Hash,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Weak},
+};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+use tracing_futures::Instrument;
+
+/// Policies that can be watched by key.
+#[derive(Debug)]
+pub(crate) struct Store<K, P> {
+    inner: Arc<Inner<K, P>>,
+}
+
+/// Changes the policies in a `Store`.
+#[derive(Debug)]
+pub(crate) struct Sender<K, P> {
+    policies: HashMap<K, watch::Sender<P>>,
+    inner: Weak<Inner<K, P>>,
+}
+
+#[derive(Debug)]
+struct Inner<K, P> {
+    policies: RwLock<HashMap<K, watch::Receiver<P>>>,
+    drain_tx: watch::Sender<bool>,
+    drain_rx: watch::Receiver<bool>,
+}
+
+// === impl Store ===
+
+impl<K, P> Store<K, P>
+where
+    K: Clone + Eq + Hash + fmt::Debug,
+    P: Clone + fmt::Debug + Send + Sync + 'static,
+{
+    pub(crate) fn new(policies: HashMap<K, P>) -> (Sender<K, P>, Self) {
+        let mut txs = HashMap::new();
+        let mut rxs = HashMap::new();
+        for (key, policy) in policies.into_iter() {
+            tracing::info!(?key, ?policy, "added");
+            let (tx, rx) = watch::channel(policy);
+            txs.insert(key.clone(), tx);
+            rxs.insert(key, rx);
+        }
+
+        let (drain_tx, drain_rx) = watch::channel(false);
+        let inner = Arc::new(Inner {
+            policies: RwLock::new(rxs),
+            drain_tx,
+            drain_rx,
+        });
+        let sender = Sender {
+            policies: txs,
+            inner: Arc::downgrade(&inner),
+        };
+        (sender, Self { inner })
+    }
+
+    /// Ends all open streams, and any streams opened later.
+    pub(crate) fn drain(&self) {
+        let _ = self.inner.drain_tx.broadcast(true);
+    }
+
+    pub(crate) async fn get(&self, key: &K) -> Option<watch::Receiver<P>> {
+        self.inner.policies.read().await.get(key).cloned()
+    }
+
+    /// Streams each version of a policy, converted by `to_pb`, until it is
+    /// deleted or the store is drained.
+    pub(crate) fn stream<T, F>(
+        &self,
+        mut policy_rx: watch::Receiver<P>,
+        span: tracing::Span,
+        to_pb: F,
+    ) -> mpsc::Receiver<Result<T, tonic::Status>>
+    where
+        T: Send + 'static,
+        F: Fn(&P) -> T + Send + 'static,
+    {
+        let mut drain = self.inner.drain_rx.clone();
+        let (mut tx, rx) = mpsc::channel(8);
+        tokio::spawn(
+            async move {
+                loop {
+                    let policy = tokio::select! {
+                        policy = policy_rx.recv() => match policy {
+                            Some(policy) => policy,
+                            None => break,
+                        },
+                        _ = drained(&mut drain) => {
+                            tracing::debug!("Draining");
+                            return Ok(());
+                        }
+                    };
+                    tracing::debug!(?policy);
+                    tx.send(Ok(to_pb(&policy))).await?;
+                }
+                tracing::debug!("Watch ended");
+                Ok(())
+            }
+            .map_err(|_: mpsc::error::SendError<_>| tracing::info!("Watch closed"))
+            .instrument(span),
+        );
+        rx
+    }
+}
+
+impl<K, P> Clone for Store<K, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Resolves once the store is drained.
+async fn drained(drain: &mut watch::Receiver<bool>) {
+    loop {
+        match drain.recv().await {
+            Some(true) => return,
+            Some(false) => {}
+            // The store has been dropped, so it can never be drained.
+            None => future::pending::<()>().await,
+        }
+    }
+}
+
+// === impl Sender ===
+
+impl<K, P> Sender<K, P>
+where
+    K: Clone + Eq + Hash + fmt::Debug,
+    P: Clone + fmt::Debug,
+{
+    pub(crate) async fn send(&mut self, key: K, policy: P) -> Result<(), Error> {
+        if let Some(sender) = self.policies.get(&key) {
+            tracing::info!(?key, "Policy present");
+            sender
+                .broadcast(policy)
+                .map_err(|_| format!("policy for {:?} is no longer watched", key))?;
+        } else {
+            tracing::info!(?key, "Policy not present");
+            if let Some(inner) = self.inner.upgrade() {
+                let (tx, rx) = watch::channel(policy);
+                self.policies.insert(key.clone(), tx);
+                inner.policies.write().await.insert(key, rx);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn delete(&mut self, key: &K) {
+        if self.policies.remove(key).is_some() {
+            tracing::info!(?key, "dropping sender");
+            if let Some(inner) = self.inner.upgrade() {
+                inner.policies.write().await.remove(key);
+            }
+        } else {
+            tracing::info!(?key, "Policy not found");
+        }
+    }
+}
+
+/// Loads the policies in `dir` into `sender`, and then keeps them up to date
+/// as the directory changes.
+///
+/// Each file is named for its policy's key, with a `.yaml`, `.yml` or `.json`
+/// extension, and contains the JSON or YAML representation of the policy.
+pub(crate) async fn watch_dir<K, P>(
+    dir: &Path,
+    sender: &mut Sender<K, P>,
+    ready: Option<oneshot::Sender<()>>,
+) -> Result<(), Error>
+where
+    K: Clone + Eq + Hash + fmt::Debug + FromStr,
+    P: Clone + DeserializeOwned + fmt::Debug,
+{
+    let mut inotify = Inotify::init()?;
+    let mask = WatchMask::MODIFY | WatchMask::DELETE | WatchMask::MOVED_TO;
+    inotify.add_watch(dir, mask)?;
+
+    // Load the policies that already exist only once changes are being
+    // watched, so that none are missed.
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if let Err(e) = load(&path, sender).await {
+            tracing::error!(%e, ?path, "error loading policy");
+        }
+    }
+    if let Some(ready) = ready {
+        let _ = ready.send(());
+    }
+
+    let mut stream = inotify.event_stream(vec![0; crate::fs_watcher::EVENT_BUF_SZ])?;
+    while let Some(event) = stream.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::error!(%e, "inotify stream error");
+                continue;
+            }
+        };
+        let path = match event.name {
+            Some(name) => dir.join(name),
+            None => continue,
+        };
+        let res = if event.mask.contains(EventMask::DELETE) {
+            match key::<K>(&path) {
+                Some(key) => {
+                    sender.delete(&key).await;
+                    Ok(())
+                }
+                None => Err(format!("invalid file name {}", path.display()).into()),
+            }
+        } else {
+            load(&path, sender).await
+        };
+        if let Err(e) = res {
+            tracing::error!(%e, ?path, "error handling event");
+        }
+    }
+    Ok(())
+}
+
+async fn load<K, P>(path: &Path, sender: &mut Sender<K, P>) -> Result<(), Error>
+where
+    K: Clone + Eq + Hash + fmt::Debug + FromStr,
+    P: Clone + DeserializeOwned + fmt::Debug,
+{
+    let key = key(path).ok_or_else(|| format!("invalid file name {}", path.display()))?;
+    let contents = tokio::fs::read_to_string(path).await?;
+    let policy = match path.extension().and_then(OsStr::to_str) {
+        Some("json") => serde_json::from_str(&contents)?,
+        _ => serde_yaml::from_str(&contents)?,
+    };
+    sender.send(key, policy).await
+}
+
+/// Returns the key named by a policy file, like `8080.yaml`.
+fn key<K: FromStr>(path: &Path) -> Option<K> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") | Some("json") => {}
+        _ => return None,
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
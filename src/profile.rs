@@ -0,0 +1,659 @@
+//! Translates Linkerd `ServiceProfile` resources into the profiles that the
+//! Destination controller serves for them.
+
+use crate::Error;
+use linkerd2_proxy_api::{
+    destination as pb,
+    http_types::{
+        self,
+        http_method::{Registered, Type},
+    },
+};
+use serde::Deserialize;
+use std::{collections::HashMap, convert::TryFrom, time::Duration};
+
+/// The route timeout that the controller serves when a route has none.
+const DEFAULT_ROUTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A Linkerd `ServiceProfile` resource, as it would be applied to a cluster,
+/// and the profile it is served as.
+///
+/// Profiles are served for every port of the destination they are named for,
+/// like `web.ns.svc.cluster.local`. As the real controller does, routes
+/// without a timeout have a 10s timeout, and profiles without a retry budget
+/// allow retrying 20% of requests, and at least 10 per second, over 10s.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "Resource")]
+pub struct ServiceProfile {
+    name: String,
+    profile: pb::DestinationProfile,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resource {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: Spec,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Spec {
+    #[serde(default)]
+    routes: Vec<RouteSpec>,
+    #[serde(default)]
+    retry_budget: Option<RetryBudget>,
+    #[serde(default)]
+    dst_overrides: Vec<WeightedDst>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RouteSpec {
+    name: String,
+    condition: RequestMatch,
+    #[serde(default)]
+    response_classes: Vec<ResponseClass>,
+    #[serde(default)]
+    is_retryable: bool,
+    #[serde(default)]
+    timeout: Option<String>,
+}
+
+/// The fields that are set must all match.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestMatch {
+    #[serde(default)]
+    all: Vec<RequestMatch>,
+    #[serde(default)]
+    any: Vec<RequestMatch>,
+    #[serde(default)]
+    not: Option<Box<RequestMatch>>,
+    #[serde(default)]
+    path_regex: Option<String>,
+    #[serde(default)]
+    method: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponseClass {
+    condition: ResponseMatch,
+    #[serde(default)]
+    is_failure: bool,
+}
+
+/// The fields that are set must all match.
+#[derive(Debug, Default, Deserialize)]
+struct ResponseMatch {
+    #[serde(default)]
+    all: Vec<ResponseMatch>,
+    #[serde(default)]
+    any: Vec<ResponseMatch>,
+    #[serde(default)]
+    not: Option<Box<ResponseMatch>>,
+    #[serde(default)]
+    status: Option<StatusRange>,
+}
+
+/// An unset bound matches any status.
+#[derive(Debug, Deserialize)]
+struct StatusRange {
+    #[serde(default)]
+    min: Option<u32>,
+    #[serde(default)]
+    max: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RetryBudget {
+    retry_ratio: f32,
+    min_retries_per_second: u32,
+    ttl: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeightedDst {
+    authority: String,
+    weight: Quantity,
+}
+
+/// A Kubernetes quantity, like `1`, `0.5` or `500m`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Quantity {
+    Number(f64),
+    String(String),
+}
+
+// === impl ServiceProfile ===
+
+impl ServiceProfile {
+    /// The name of the destination the profile is served for, without a
+    /// port.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Serves `profile` for the destination `name`, as it was served by a
+    /// Destination controller.
+    pub fn from_pb(name: impl Into<String>, profile: pb::DestinationProfile) -> Self {
+        Self {
+            name: name.into(),
+            profile,
+        }
+    }
+
+    pub(crate) fn to_pb(&self) -> pb::DestinationProfile {
+        self.profile.clone()
+    }
+}
+
+impl TryFrom<Resource> for ServiceProfile {
+    type Error = Error;
+
+    fn try_from(Resource { metadata, spec }: Resource) -> Result<Self, Self::Error> {
+        let name = metadata.name;
+        let routes = spec
+            .routes
+            .into_iter()
+            .map(|route| {
+                let route_name = route.name.clone();
+                route
+                    .into_pb()
+                    .map_err(|e| format!("{}: route '{}': {}", name, route_name, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let retry_budget = match spec.retry_budget {
+            Some(budget) => budget
+                .into_pb()
+                .map_err(|e| format!("{}: retry budget: {}", name, e))?,
+            None => pb::RetryBudget {
+                retry_ratio: 0.2,
+                min_retries_per_second: 10,
+                ttl: Some(Duration::from_secs(10).into()),
+            },
+        };
+        let dst_overrides = spec
+            .dst_overrides
+            .into_iter()
+            .map(|dst| {
+                Ok(pb::WeightedDst {
+                    weight: dst.weight.milli()?,
+                    authority: dst.authority,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let profile = pb::DestinationProfile {
+            routes,
+            retry_budget: Some(retry_budget),
+            dst_overrides,
+            ..Default::default()
+        };
+        Ok(Self { name, profile })
+    }
+}
+
+// === impl RouteSpec ===
+
+impl RouteSpec {
+    fn into_pb(self) -> Result<pb::Route, Error> {
+        let timeout = match self.timeout {
+            Some(ref timeout) => parse_go_duration(timeout)?,
+            None => DEFAULT_ROUTE_TIMEOUT,
+        };
+        let response_classes = self
+            .response_classes
+            .iter()
+            .map(|class| {
+                Ok(pb::ResponseClass {
+                    condition: Some(class.condition.to_pb()?),
+                    is_failure: class.is_failure,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut metrics_labels = HashMap::new();
+        metrics_labels.insert("route".to_string(), self.name);
+        Ok(pb::Route {
+            condition: Some(self.condition.to_pb()?),
+            response_classes,
+            metrics_labels,
+            is_retryable: self.is_retryable,
+            timeout: Some(timeout.into()),
+        })
+    }
+}
+
+// === impl RequestMatch ===
+
+impl RequestMatch {
+    fn to_pb(&self) -> Result<pb::RequestMatch, Error> {
+        use pb::request_match::{Match, Seq};
+
+        let seq = |matches: &[RequestMatch]| {
+            let matches = matches
+                .iter()
+                .map(RequestMatch::to_pb)
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok::<_, Error>(Seq { matches })
+        };
+        let mut matches = Vec::new();
+        if !self.all.is_empty() {
+            matches.push(Match::All(seq(&self.all)?));
+        }
+        if !self.any.is_empty() {
+            matches.push(Match::Any(seq(&self.any)?));
+        }
+        if let Some(ref not) = self.not {
+            matches.push(Match::Not(Box::new(not.to_pb()?)));
+        }
+        if let Some(ref regex) = self.path_regex {
+            matches.push(Match::Path(pb::PathMatch {
+                regex: regex.clone(),
+            }));
+        }
+        if let Some(ref method) = self.method {
+            matches.push(Match::Method(method_to_pb(method)));
+        }
+        let wrap = |m| pb::RequestMatch { r#match: Some(m) };
+        all(matches, |matches| {
+            Match::All(Seq {
+                matches: matches.into_iter().map(wrap).collect(),
+            })
+        })
+        .map(wrap)
+        .ok_or_else(|| "a request match must have a field set".into())
+    }
+}
+
+// === impl ResponseMatch ===
+
+impl ResponseMatch {
+    fn to_pb(&self) -> Result<pb::ResponseMatch, Error> {
+        use pb::response_match::{Match, Seq};
+
+        let seq = |matches: &[ResponseMatch]| {
+            let matches = matches
+                .iter()
+                .map(ResponseMatch::to_pb)
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok::<_, Error>(Seq { matches })
+        };
+        let mut matches = Vec::new();
+        if !self.all.is_empty() {
+            matches.push(Match::All(seq(&self.all)?));
+        }
+        if !self.any.is_empty() {
+            matches.push(Match::Any(seq(&self.any)?));
+        }
+        if let Some(ref not) = self.not {
+            matches.push(Match::Not(Box::new(not.to_pb()?)));
+        }
+        if let Some(StatusRange { min, max }) = self.status {
+            let (min, max) = (min.unwrap_or(100), max.unwrap_or(599));
+            if min > max {
+                return Err(format!("status range {}-{} ends before it starts", min, max).into());
+            }
+            matches.push(Match::Status(pb::HttpStatusRange { min, max }));
+        }
+        let wrap = |m| pb::ResponseMatch { r#match: Some(m) };
+        all(matches, |matches| {
+            Match::All(Seq {
+                matches: matches.into_iter().map(wrap).collect(),
+            })
+        })
+        .map(wrap)
+        .ok_or_else(|| "a response match must have a field set".into())
+    }
+}
+
+/// Combines the matches of each field that is set, all of which must match.
+fn all<M>(mut matches: Vec<M>, seq: impl FnOnce(Vec<M>) -> M) -> Option<M> {
+    match matches.len() {
+        0 => None,
+        1 => matches.pop(),
+        _ => Some(seq(matches)),
+    }
+}
+
+fn method_to_pb(method: &str) -> http_types::HttpMethod {
+    let registered = match method {
+        "GET" => Registered::Get,
+        "POST" => Registered::Post,
+        "PUT" => Registered::Put,
+        "DELETE" => Registered::Delete,
+        "PATCH" => Registered::Patch,
+        "OPTIONS" => Registered::Options,
+        "CONNECT" => Registered::Connect,
+        "HEAD" => Registered::Head,
+        "TRACE" => Registered::Trace,
+        _ => {
+            return http_types::HttpMethod {
+                r#type: Some(Type::Unregistered(method.to_string())),
+            }
+        }
+    };
+    http_types::HttpMethod {
+        r#type: Some(Type::Registered(registered as i32)),
+    }
+}
+
+// === impl RetryBudget ===
+
+impl RetryBudget {
+    fn into_pb(self) -> Result<pb::RetryBudget, Error> {
+        if self.retry_ratio < 0.0 || !self.retry_ratio.is_finite() {
+            return Err(format!("invalid retry ratio {}", self.retry_ratio).into());
+        }
+        Ok(pb::RetryBudget {
+            retry_ratio: self.retry_ratio,
+            min_retries_per_second: self.min_retries_per_second,
+            ttl: Some(parse_go_duration(&self.ttl)?.into()),
+        })
+    }
+}
+
+// === impl Quantity ===
+
+impl Quantity {
+    /// Returns the quantity in thousandths, which is how destination
+    /// overrides' weights are served.
+    pub(crate) fn milli(&self) -> Result<u32, Error> {
+        let value = match self {
+            Quantity::Number(n) => *n,
+            Quantity::String(s) => {
+                let split = s
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or_else(|| s.len());
+                let (number, suffix) = s.split_at(split);
+                let scale = match suffix {
+                    "" => 1.0,
+                    "m" => 0.001,
+                    "k" => 1e3,
+                    "M" => 1e6,
+                    "G" => 1e9,
+                    "Ki" => 1024.0,
+                    "Mi" => 1024.0 * 1024.0,
+                    "Gi" => 1024.0 * 1024.0 * 1024.0,
+                    _ => return Err(format!("invalid quantity '{}'", s).into()),
+                };
+                let number = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid quantity '{}'", s))?;
+                number * scale
+            }
+        };
+        let milli = (value * 1000.0).round();
+        // This also rejects NaN, which cannot be cast.
+        if !(milli >= 0.0 && milli <= f64::from(u32::max_value())) {
+            return Err(format!("quantity {:?} is out of range", self).into());
+        }
+        Ok(milli as u32)
+    }
+}
+
+/// Parses a duration in the format of Go's `time.ParseDuration`, like
+/// `300ms`, `1.5s` or `1m30s`.
+fn parse_go_duration(s: &str) -> Result<Duration, Error> {
+    let invalid = || format!("invalid duration '{}'", s);
+    if s == "0" {
+        return Ok(Duration::from_secs(0));
+    }
+    let mut rest = s;
+    let mut total = 0.0;
+    if rest.is_empty() {
+        return Err(invalid().into());
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(split);
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or_else(|| tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        // Durations are summed in nanoseconds, so that e.g. `100us` is not
+        // rounded down by a fractional number of seconds.
+        let nanos = match unit {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            _ => return Err(invalid().into()),
+        };
+        total += number * nanos;
+        rest = tail;
+    }
+    let total = total.round();
+    if !(total.is_finite() && total < u64::max_value() as f64) {
+        return Err(format!("duration '{}' is out of range", s).into());
+    }
+    Ok(Duration::from_nanos(total as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pb::{request_match, response_match};
+
+    fn profile(yaml: &str) -> Result<pb::DestinationProfile, serde_yaml::Error> {
+        serde_yaml::from_str::<ServiceProfile>(yaml).map(|profile| profile.to_pb())
+    }
+
+    #[test]
+    fn translates_routes() {
+        let profile = profile(
+            "
+apiVersion: linkerd.io/v1alpha2
+kind: ServiceProfile
+metadata:
+  name: web-svc.emojivoto.svc.cluster.local
+  namespace: emojivoto
+spec:
+  routes:
+  - name: GET /api/list
+    condition:
+      method: GET
+      pathRegex: /api/list
+    responseClasses:
+    - condition:
+        status:
+          min: 500
+      isFailure: true
+    isRetryable: true
+    timeout: 300ms
+  - name: POST /api/vote
+    condition:
+      any:
+      - method: POST
+      - method: PROPFIND
+",
+        )
+        .unwrap();
+        assert_eq!(profile.routes.len(), 2);
+
+        let list = &profile.routes[0];
+        assert_eq!(list.metrics_labels["route"], "GET /api/list");
+        assert!(list.is_retryable);
+        assert_eq!(list.timeout, Some(Duration::from_millis(300).into()));
+        match list.condition.as_ref().and_then(|c| c.r#match.as_ref()) {
+            Some(request_match::Match::All(all)) => {
+                let matches = all
+                    .matches
+                    .iter()
+                    .filter_map(|m| m.r#match.clone())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    matches,
+                    [
+                        request_match::Match::Path(pb::PathMatch {
+                            regex: "/api/list".to_string()
+                        }),
+                        request_match::Match::Method(method_to_pb("GET")),
+                    ]
+                );
+            }
+            condition => panic!("unexpected condition: {:?}", condition),
+        }
+        assert_eq!(
+            list.response_classes,
+            [pb::ResponseClass {
+                condition: Some(pb::ResponseMatch {
+                    r#match: Some(response_match::Match::Status(pb::HttpStatusRange {
+                        min: 500,
+                        max: 599
+                    })),
+                }),
+                is_failure: true,
+            }]
+        );
+
+        let vote = &profile.routes[1];
+        assert!(!vote.is_retryable);
+        assert_eq!(vote.timeout, Some(DEFAULT_ROUTE_TIMEOUT.into()));
+        match vote.condition.as_ref().and_then(|c| c.r#match.as_ref()) {
+            Some(request_match::Match::Any(any)) => {
+                let methods = any
+                    .matches
+                    .iter()
+                    .filter_map(|m| m.r#match.clone())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    methods,
+                    [
+                        request_match::Match::Method(method_to_pb("POST")),
+                        request_match::Match::Method(http_types::HttpMethod {
+                            r#type: Some(Type::Unregistered("PROPFIND".to_string())),
+                        }),
+                    ]
+                );
+            }
+            condition => panic!("unexpected condition: {:?}", condition),
+        }
+    }
+
+    #[test]
+    fn translates_retry_budgets_and_overrides() {
+        let profile = profile(
+            "
+metadata:
+  name: web-svc.emojivoto.svc.cluster.local
+spec:
+  retryBudget:
+    retryRatio: 0.5
+    minRetriesPerSecond: 5
+    ttl: 1m30s
+  dstOverrides:
+  - authority: web-svc-v1.emojivoto.svc.cluster.local:80
+    weight: 500m
+  - authority: web-svc-v2.emojivoto.svc.cluster.local:80
+    weight: 0.5
+",
+        )
+        .unwrap();
+        assert_eq!(
+            profile.retry_budget,
+            Some(pb::RetryBudget {
+                retry_ratio: 0.5,
+                min_retries_per_second: 5,
+                ttl: Some(Duration::from_secs(90).into()),
+            })
+        );
+        let weights = profile
+            .dst_overrides
+            .iter()
+            .map(|dst| (dst.authority.as_str(), dst.weight))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            weights,
+            [
+                ("web-svc-v1.emojivoto.svc.cluster.local:80", 500),
+                ("web-svc-v2.emojivoto.svc.cluster.local:80", 500),
+            ]
+        );
+    }
+
+    #[test]
+    fn defaults_the_retry_budget() {
+        let profile = profile("metadata:\n  name: web-svc.emojivoto.svc.cluster.local\n").unwrap();
+        assert!(profile.routes.is_empty());
+        assert_eq!(
+            profile.retry_budget,
+            Some(pb::RetryBudget {
+                retry_ratio: 0.2,
+                min_retries_per_second: 10,
+                ttl: Some(Duration::from_secs(10).into()),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let route = |route: &str| {
+            format!(
+                "metadata:\n  name: web\nspec:\n  routes:\n  - name: route\n{}",
+                route
+            )
+        };
+        for yaml in &[
+            route("    condition: {}\n"),
+            route("    condition:\n      method: GET\n    timeout: \"10\"\n"),
+            route(
+                "    condition:\n      method: GET\n    responseClasses:\n    - condition:\n        status:\n          min: 500\n          max: 400\n",
+            ),
+            "metadata:\n  name: web\nspec:\n  retryBudget:\n    retryRatio: -1\n    minRetriesPerSecond: 1\n    ttl: 10s\n".to_string(),
+            "metadata:\n  name: web\nspec:\n  dstOverrides:\n  - authority: web:80\n    weight: 1x\n".to_string(),
+        ] {
+            assert!(profile(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn parses_quantities_in_thousandths() {
+        let milli = |s: &str| Quantity::String(s.to_string()).milli();
+        assert_eq!(Quantity::Number(0.5).milli().unwrap(), 500);
+        assert_eq!(Quantity::Number(3.0).milli().unwrap(), 3000);
+        assert_eq!(milli("500m").unwrap(), 500);
+        assert_eq!(milli("1").unwrap(), 1000);
+        assert_eq!(milli("1.5").unwrap(), 1500);
+        assert_eq!(milli("1k").unwrap(), 1_000_000);
+        assert_eq!(milli("1Ki").unwrap(), 1_024_000);
+        assert!(milli("-1").is_err());
+        assert!(milli("1x").is_err());
+        assert!(milli("m").is_err());
+        assert!(milli("10G").is_err());
+        assert!(Quantity::Number(-1.0).milli().is_err());
+        assert!(Quantity::Number(std::f64::NAN).milli().is_err());
+    }
+
+    #[test]
+    fn parses_go_durations() {
+        let cases = [
+            ("0", Duration::from_secs(0)),
+            ("300ms", Duration::from_millis(300)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("1m30s", Duration::from_secs(90)),
+            ("2h", Duration::from_secs(7200)),
+            ("100us", Duration::from_micros(100)),
+            ("100µs", Duration::from_micros(100)),
+            ("10ns", Duration::from_nanos(10)),
+        ];
+        for (s, duration) in cases.iter() {
+            assert_eq!(parse_go_duration(s).unwrap(), *duration, "{}", s);
+        }
+        for s in &["", "10", "s", "1x", "-1s", "1s2", "1..5s"] {
+            assert!(parse_go_duration(s).is_err(), "{}", s);
+        }
+    }
+}
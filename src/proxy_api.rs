@@ -0,0 +1,122 @@
+//! Proxy API types that the `linkerd2-proxy-api` crate does not yet provide,
+//! generated from the protos vendored under `proto/linkerd`.
+
+use crate::{Cidr, Dst};
+use std::net::{IpAddr, SocketAddr};
+
+pub(crate) mod grpc_route {
+    tonic::include_proto!("io.linkerd.proxy.grpc_route");
+}
+
+pub(crate) mod http_route {
+    tonic::include_proto!("io.linkerd.proxy.http_route");
+}
+
+pub(crate) mod http_types {
+    tonic::include_proto!("io.linkerd.proxy.http_types");
+}
+
+pub(crate) mod inbound {
+    tonic::include_proto!("io.linkerd.proxy.inbound");
+}
+
+pub(crate) mod meta {
+    tonic::include_proto!("io.linkerd.proxy.meta");
+}
+
+pub(crate) mod net {
+    tonic::include_proto!("io.linkerd.proxy.net");
+}
+
+pub(crate) mod outbound {
+    tonic::include_proto!("io.linkerd.proxy.outbound");
+}
+
+// === impl net::IpAddress ===
+
+impl From<IpAddr> for net::IpAddress {
+    fn from(addr: IpAddr) -> Self {
+        let ip = match addr {
+            IpAddr::V4(addr) => net::ip_address::Ip::Ipv4(addr.into()),
+            IpAddr::V6(addr) => {
+                let addr = u128::from(addr);
+                net::ip_address::Ip::Ipv6(net::IPv6 {
+                    first: (addr >> 64) as u64,
+                    last: addr as u64,
+                })
+            }
+        };
+        Self { ip: Some(ip) }
+    }
+}
+
+impl net::IpAddress {
+    pub(crate) fn to_ip_addr(&self) -> Option<IpAddr> {
+        match self.ip.as_ref()? {
+            net::ip_address::Ip::Ipv4(addr) => Some(IpAddr::from(addr.to_be_bytes())),
+            net::ip_address::Ip::Ipv6(addr) => {
+                let addr = (u128::from(addr.first) << 64) | u128::from(addr.last);
+                Some(IpAddr::from(addr.to_be_bytes()))
+            }
+        }
+    }
+}
+
+// === impl net::TcpAddress ===
+
+impl net::TcpAddress {
+    pub(crate) fn to_socket_addr(&self) -> Option<SocketAddr> {
+        let ip = self.ip.as_ref()?.to_ip_addr()?;
+        if self.port > u32::from(u16::max_value()) {
+            return None;
+        }
+        Some(SocketAddr::new(ip, self.port as u16))
+    }
+}
+
+// === impl net::IpNetwork ===
+
+impl From<Cidr> for net::IpNetwork {
+    fn from(cidr: Cidr) -> Self {
+        Self {
+            ip: Some(cidr.addr().into()),
+            prefix_len: cidr.prefix_len().into(),
+        }
+    }
+}
+
+// === impl meta::Metadata ===
+
+impl meta::Metadata {
+    /// Describes a resource declared in the mock's configuration.
+    pub(crate) fn resource(kind: &str, name: &str) -> Self {
+        Self {
+            kind: Some(meta::metadata::Kind::Resource(meta::Resource {
+                group: "policy.linkerd.io".to_string(),
+                kind: kind.to_string(),
+                name: name.to_string(),
+                ..meta::Resource::default()
+            })),
+        }
+    }
+
+    /// Describes a Kubernetes service.
+    pub(crate) fn service(dst: &Dst) -> Self {
+        Self {
+            kind: Some(meta::metadata::Kind::Resource(meta::Resource {
+                group: "core".to_string(),
+                kind: "Service".to_string(),
+                name: dst.name().to_string(),
+                port: dst.port().into(),
+                ..meta::Resource::default()
+            })),
+        }
+    }
+
+    /// Describes a default that is not declared in the mock's configuration.
+    pub(crate) fn for_default(name: &str) -> Self {
+        Self {
+            kind: Some(meta::metadata::Kind::Default(name.to_string())),
+        }
+    }
+}
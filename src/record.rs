@@ -0,0 +1,540 @@
+//! Records the streams a Destination controller serves, and replays them
+//! through a `DstService`.
+//!
+//! A recording is a sequence of length-delimited protobuf `Record`s, each
+//! holding a message received on a stream and when it was received.
+
+use crate::{
+    destination::socket_addr, Dst, DstSender, EndpointMeta, Endpoints, Error, ServiceProfile,
+};
+use futures::{prelude::*, stream::BoxStream};
+use linkerd2_proxy_api::destination::{
+    self as pb, destination_client::DestinationClient, GetDestination,
+};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncWriteExt;
+use tonic::transport::Endpoint;
+
+/// Records the `Get` and `GetProfile` streams that a Destination controller
+/// serves for some destinations.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    addr: String,
+    dsts: Vec<Dst>,
+    profiles: bool,
+    context_token: String,
+}
+
+/// Replays a recording through a `DstSender`.
+#[derive(Clone, Debug)]
+pub struct Replayer {
+    records: Vec<Record>,
+    speed: f64,
+}
+
+/// A message received on a recorded stream.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Record {
+    /// When the message was received, relative to the start of the recording.
+    #[prost(uint64, tag = "1")]
+    micros: u64,
+    #[prost(string, tag = "2")]
+    dst: String,
+    #[prost(oneof = "Event", tags = "3, 4, 5")]
+    event: Option<Event>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum Event {
+    #[prost(message, tag = "3")]
+    Update(pb::Update),
+    #[prost(message, tag = "4")]
+    Profile(pb::DestinationProfile),
+    #[prost(message, tag = "5")]
+    End(End),
+}
+
+/// The end of a stream, with the gRPC status it ended with.
+#[derive(Clone, PartialEq, prost::Message)]
+struct End {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+}
+
+// === impl Recorder ===
+
+impl Recorder {
+    /// Records `dsts` from the controller at `addr`, like
+    /// `http://127.0.0.1:8086`.
+    pub fn new(addr: impl Into<String>, dsts: Vec<Dst>) -> Self {
+        Self {
+            addr: addr.into(),
+            dsts,
+            profiles: true,
+            context_token: String::new(),
+        }
+    }
+
+    /// Sets whether `GetProfile` streams are recorded, as well as `Get`
+    /// streams.
+    pub fn profiles(self, profiles: bool) -> Self {
+        Self { profiles, ..self }
+    }
+
+    /// Sets the context token sent with each request, like the JSON
+    /// `{"ns":"default","nodeName":"node-1"}` that proxies send.
+    pub fn context_token(self, context_token: impl Into<String>) -> Self {
+        Self {
+            context_token: context_token.into(),
+            ..self
+        }
+    }
+
+    /// Writes every message received to the file at `path` until `shutdown`
+    /// resolves or all of the streams end.
+    ///
+    /// Streams that end are not reopened.
+    pub async fn record(
+        self,
+        path: &Path,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let channel = Endpoint::from_shared(self.addr.clone())?.connect().await?;
+        let client = DestinationClient::new(channel);
+
+        let mut streams = Vec::new();
+        for dst in self.dsts.iter() {
+            let req = GetDestination {
+                scheme: "k8s".to_string(),
+                path: dst.to_string(),
+                context_token: self.context_token.clone(),
+            };
+            let updates = client.clone().get(req.clone()).await?.into_inner();
+            streams.push(events(dst, updates, Event::Update));
+            if self.profiles {
+                let profiles = client.clone().get_profile(req).await?.into_inner();
+                streams.push(events(dst, profiles, Event::Profile));
+            }
+        }
+        tracing::info!(streams = streams.len(), "Recording");
+
+        let start = Instant::now();
+        let mut events = stream::select_all(streams);
+        futures::pin_mut!(shutdown);
+        let mut records = 0;
+        loop {
+            let (dst, event) = tokio::select! {
+                event = events.next() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            let record = Record {
+                micros: start.elapsed().as_micros() as u64,
+                dst,
+                event: Some(event),
+            };
+            tracing::debug!(?record);
+            let mut buf = Vec::with_capacity(record.encoded_len() + 10);
+            record.encode_length_delimited(&mut buf)?;
+            file.write_all(&buf).await?;
+            // Flush each record, so that the recording is usable if the
+            // recorder is killed.
+            file.flush().await?;
+            records += 1;
+        }
+        tracing::info!(records, "Recorded");
+        Ok(())
+    }
+}
+
+/// Labels each message received on a stream with its destination, ending
+/// with the stream's status.
+fn events<T: Send + 'static>(
+    dst: &Dst,
+    stream: tonic::Streaming<T>,
+    to_event: fn(T) -> Event,
+) -> BoxStream<'static, (String, Event)> {
+    let dst = dst.to_string();
+    stream::unfold(Some(stream), move |stream| {
+        let dst = dst.clone();
+        async move {
+            let mut stream = stream?;
+            let (event, stream) = match stream.message().await {
+                Ok(Some(msg)) => (to_event(msg), Some(stream)),
+                Ok(None) => (Event::End(End::from(tonic::Status::ok(""))), None),
+                Err(status) => (Event::End(End::from(status)), None),
+            };
+            Some(((dst, event), stream))
+        }
+    })
+    .boxed()
+}
+
+// === impl Replayer ===
+
+impl Replayer {
+    /// The slowest that a recording may be replayed, a thousand times slower
+    /// than it was recorded.
+    pub const MIN_SPEED: f64 = 1e-3;
+
+    /// The fastest that a recording may be replayed, a million times faster
+    /// than it was recorded.
+    pub const MAX_SPEED: f64 = 1e6;
+
+    /// Loads the recording at `path`.
+    pub async fn load(path: &Path) -> Result<Self, Error> {
+        let contents = tokio::fs::read(path).await?;
+        let mut buf = &contents[..];
+        let mut records = Vec::new();
+        while !buf.is_empty() {
+            records.push(Record::decode_length_delimited(&mut buf)?);
+        }
+        tracing::info!(records = records.len(), "Loaded recording");
+        Ok(Self {
+            records,
+            speed: 1.0,
+        })
+    }
+
+    /// Replays the recording `speed` times faster than it was recorded.
+    ///
+    /// Fails if `speed` is not between `MIN_SPEED` and `MAX_SPEED`.
+    pub fn speed(self, speed: f64) -> Result<Self, Error> {
+        if !(Self::MIN_SPEED..=Self::MAX_SPEED).contains(&speed) {
+            return Err(format!(
+                "invalid replay speed {}; it must be between {} and {}",
+                speed,
+                Self::MIN_SPEED,
+                Self::MAX_SPEED
+            )
+            .into());
+        }
+        Ok(Self { speed, ..self })
+    }
+
+    /// Sends the endpoints and overrides that were served at each point in
+    /// the recording, with the recorded timing.
+    ///
+    /// Profiles are replayed as they were recorded, and are served for the
+    /// destination and port they were recorded for.
+    ///
+    /// `Get` streams are not replayed verbatim. Each update is applied to the
+    /// endpoints that were served before it, and the result is served like
+    /// any other endpoints, so that:
+    ///
+    /// - the metric labels of each recorded set of endpoints are replaced by
+    ///   a `concrete` label naming the destination, and each endpoint is
+    ///   labeled with its `addr` and `h2` unless it was recorded with them;
+    /// - updates are diffed again, so a remove that leaves no endpoints is
+    ///   served as `NoEndpoints { exists: true }`, and adds of endpoints that
+    ///   are already served unchanged are not served at all;
+    /// - `NoEndpoints { exists: false }` stops serving the destination, so
+    ///   open streams end and later lookups fail until it is served again.
+    ///
+    /// Recorded streams ending is only logged.
+    pub async fn replay(self, sender: &mut DstSender) -> Result<(), Error> {
+        let start = tokio::time::Instant::now();
+        let mut endpoints = HashMap::<Dst, Endpoints>::new();
+        for Record { micros, dst, event } in self.records.into_iter() {
+            // The cast saturates rather than overflowing.
+            let at = Duration::from_micros((micros as f64 / self.speed) as u64);
+            let at = start
+                .checked_add(at)
+                .ok_or("recording is too long to replay at this speed")?;
+            delay_until(at).await;
+
+            let dst = match dst.parse::<Dst>() {
+                Ok(dst) => dst,
+                Err(e) => {
+                    tracing::warn!(%e, %dst, "Skipping record with an invalid destination");
+                    continue;
+                }
+            };
+            match event {
+                Some(Event::Update(update)) => {
+                    use pb::update::Update;
+                    let eps = endpoints.entry(dst.clone()).or_default();
+                    match update.update {
+                        Some(Update::Add(pb::WeightedAddrSet { addrs, .. })) => {
+                            for addr in addrs.iter() {
+                                match EndpointMeta::from_weighted_addr(addr) {
+                                    Some(meta) => {
+                                        eps.0.insert(meta.address, meta);
+                                    }
+                                    None => tracing::warn!(?addr, "Skipping invalid endpoint"),
+                                }
+                            }
+                        }
+                        Some(Update::Remove(pb::AddrSet { addrs })) => {
+                            for addr in addrs.iter().filter_map(socket_addr) {
+                                eps.0.remove(&addr);
+                            }
+                        }
+                        Some(Update::NoEndpoints(pb::NoEndpoints { exists: true })) => {
+                            eps.0.clear();
+                        }
+                        Some(Update::NoEndpoints(pb::NoEndpoints { exists: false })) => {
+                            endpoints.remove(&dst);
+                            sender.delete_dst(dst).await;
+                            continue;
+                        }
+                        None => continue,
+                    }
+                    let eps = eps.clone();
+                    sender.send_endpoints(dst, eps).await?;
+                }
+                Some(Event::Profile(profile)) => {
+                    let profile = ServiceProfile::from_pb(dst.name(), profile);
+                    sender.send_dst_profile(dst, profile).await?;
+                }
+                Some(Event::End(End { code, message })) => {
+                    tracing::info!(%dst, code, %message, "Recorded stream ended");
+                }
+                None => {}
+            }
+        }
+        tracing::info!("Replay finished");
+        Ok(())
+    }
+}
+
+/// Waits until `deadline`, however far away it is.
+///
+/// tokio's timer fails on deadlines more than about two years away, as slow
+/// replays of long recordings may have, so those are waited for in steps.
+async fn delay_until(deadline: tokio::time::Instant) {
+    const MAX_DELAY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+    loop {
+        let now = tokio::time::Instant::now();
+        if deadline <= now {
+            return;
+        }
+        tokio::time::delay_for((deadline - now).min(MAX_DELAY)).await;
+    }
+}
+
+// === impl End ===
+
+impl From<tonic::Status> for End {
+    fn from(status: tonic::Status) -> Self {
+        Self {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockController;
+    use std::{collections::BTreeSet, net::SocketAddr};
+
+    const WEB: &str = "web.ns.svc.cluster.local:8080";
+    const WEB_ADMIN: &str = "web.ns.svc.cluster.local:9990";
+
+    fn get(path: &str) -> GetDestination {
+        GetDestination {
+            scheme: "k8s".to_string(),
+            path: path.to_string(),
+            context_token: String::new(),
+        }
+    }
+
+    /// Waits until at least `n` records have been written to `path`.
+    async fn recorded(path: &Path, n: usize) {
+        loop {
+            // The last record may be partially written.
+            if let Ok(replayer) = Replayer::load(path).await {
+                if replayer.records.len() >= n {
+                    return;
+                }
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Summarizes an update as the addresses it adds or removes.
+    fn summarize(update: pb::Update) -> (&'static str, BTreeSet<SocketAddr>) {
+        use pb::update::Update;
+        match update.update {
+            Some(Update::Add(add)) => {
+                assert_eq!(
+                    add.metric_labels.get("concrete").map(String::as_str),
+                    Some(WEB)
+                );
+                let addrs = add.addrs.iter().filter_map(|a| a.addr.as_ref());
+                ("add", addrs.filter_map(socket_addr).collect())
+            }
+            Some(Update::Remove(remove)) => (
+                "remove",
+                remove.addrs.iter().filter_map(socket_addr).collect(),
+            ),
+            Some(Update::NoEndpoints(pb::NoEndpoints { exists: true })) => {
+                ("exists", BTreeSet::new())
+            }
+            Some(Update::NoEndpoints(pb::NoEndpoints { exists: false })) => {
+                ("missing", BTreeSet::new())
+            }
+            None => panic!("empty update"),
+        }
+    }
+
+    fn addrs(addrs: &[&str]) -> BTreeSet<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    /// Records endpoints changing on one controller, and replays them on
+    /// another.
+    #[tokio::test]
+    async fn replays_recorded_streams() -> Result<(), Error> {
+        let path =
+            std::env::temp_dir().join(format!("linkerd2-mock-dst-{}.rec", std::process::id()));
+        let dst = WEB.parse::<Dst>()?;
+        let profile = pb::DestinationProfile {
+            retry_budget: Some(pb::RetryBudget {
+                retry_ratio: 0.2,
+                min_retries_per_second: 10,
+                ttl: None,
+            }),
+            dst_overrides: vec![pb::WeightedDst {
+                authority: "web-v2.ns.svc.cluster.local:8080".to_string(),
+                weight: 1000,
+            }],
+            ..Default::default()
+        };
+
+        let mut recorded_controller = MockController::spawn().await?;
+        recorded_controller.ready().await;
+        let sender = recorded_controller.dst();
+        sender
+            .send_endpoints(dst.clone(), "10.0.0.1:8080".parse()?)
+            .await?;
+        sender
+            .send_profile(ServiceProfile::from_pb(dst.name(), profile.clone()))
+            .await?;
+
+        let recorder = Recorder::new(
+            format!("http://{}", recorded_controller.addr()),
+            vec![dst.clone()],
+        );
+        let record_path = path.clone();
+        let recording =
+            tokio::spawn(async move { recorder.record(&record_path, future::pending()).await });
+
+        // Wait for each update to be recorded before sending the next, so
+        // that none are skipped, starting with the initial update and
+        // profile.
+        let timeout = Duration::from_secs(10);
+        tokio::time::timeout(timeout, recorded(&path, 2)).await?;
+        let sender = recorded_controller.dst();
+        sender
+            .send_endpoints(dst.clone(), "10.0.0.1:8080,10.0.0.2:8080".parse()?)
+            .await?;
+        tokio::time::timeout(timeout, recorded(&path, 3)).await?;
+        sender
+            .send_endpoints(dst.clone(), "10.0.0.2:8080".parse()?)
+            .await?;
+        tokio::time::timeout(timeout, recorded(&path, 4)).await?;
+        // Shutting the controller down ends the streams, and the recording.
+        recorded_controller.shutdown().await?;
+        tokio::time::timeout(timeout, recording).await???;
+
+        let mut controller = MockController::spawn().await?;
+        controller.ready().await;
+        controller
+            .dst()
+            .send_endpoints(dst.clone(), Endpoints::default())
+            .await?;
+        let mut client =
+            DestinationClient::connect(format!("http://{}", controller.addr())).await?;
+        let mut updates = client.get(get(WEB)).await?.into_inner();
+        let update = updates.message().await?.ok_or("stream ended")?;
+        assert_eq!(summarize(update), ("exists", BTreeSet::new()));
+
+        let replayer = Replayer::load(&path).await?.speed(2.0)?;
+        let _ = std::fs::remove_file(&path);
+        replayer.replay(controller.dst()).await?;
+
+        let mut served = Vec::new();
+        while let Some(update) = updates.message().await? {
+            served.push(summarize(update));
+        }
+        assert_eq!(
+            served,
+            [
+                ("add", addrs(&["10.0.0.1:8080"])),
+                ("add", addrs(&["10.0.0.2:8080"])),
+                ("remove", addrs(&["10.0.0.1:8080"])),
+                // The controller shutting down is replayed, too.
+                ("missing", BTreeSet::new()),
+            ]
+        );
+
+        let mut profiles = client.get_profile(get(WEB)).await?.into_inner();
+        assert_eq!(profiles.message().await?, Some(profile));
+
+        controller.shutdown().await
+    }
+
+    /// Profiles recorded for different ports of a destination are each served
+    /// for their own port.
+    #[tokio::test]
+    async fn replays_profiles_for_each_port() -> Result<(), Error> {
+        let profile = |authority: &str| pb::DestinationProfile {
+            dst_overrides: vec![pb::WeightedDst {
+                authority: authority.to_string(),
+                weight: 1000,
+            }],
+            ..Default::default()
+        };
+        let web = profile("web-v2.ns.svc.cluster.local:8080");
+        let admin = profile("web-v2.ns.svc.cluster.local:9990");
+        let record = |dst: &str, profile: &pb::DestinationProfile| Record {
+            micros: 0,
+            dst: dst.to_string(),
+            event: Some(Event::Profile(profile.clone())),
+        };
+        let replayer = Replayer {
+            records: vec![record(WEB, &web), record(WEB_ADMIN, &admin)],
+            speed: 1.0,
+        };
+
+        let mut controller = MockController::spawn().await?;
+        controller.ready().await;
+        replayer.replay(controller.dst()).await?;
+
+        let mut client =
+            DestinationClient::connect(format!("http://{}", controller.addr())).await?;
+        for (dst, profile) in vec![(WEB, web), (WEB_ADMIN, admin)] {
+            let mut profiles = client.get_profile(get(dst)).await?.into_inner();
+            assert_eq!(profiles.message().await?, Some(profile), "{}", dst);
+        }
+
+        controller.shutdown().await
+    }
+
+    #[test]
+    fn rejects_invalid_speeds() {
+        let replayer = Replayer {
+            records: Vec::new(),
+            speed: 1.0,
+        };
+        for &speed in &[Replayer::MIN_SPEED, 0.5, Replayer::MAX_SPEED] {
+            assert!(replayer.clone().speed(speed).is_ok(), "{}", speed);
+        }
+        for &speed in &[0.0, -1.0, 1e-20, 1e20, std::f64::NAN, std::f64::INFINITY] {
+            assert!(replayer.clone().speed(speed).is_err(), "{}", speed);
+        }
+    }
+}
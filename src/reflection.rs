@@ -0,0 +1,295 @@
+use futures::prelude::*;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::mpsc;
+use tracing_futures::Instrument;
+
+mod pb {
+    tonic::include_proto!("grpc.reflection.v1alpha");
+}
+
+pub(crate) use self::pb::server_reflection_server::ServerReflectionServer;
+use self::pb::{
+    server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+    server_reflection_server::ServerReflection, ErrorResponse, ExtensionNumberResponse,
+    FileDescriptorResponse, ListServiceResponse, ServerReflectionRequest, ServerReflectionResponse,
+    ServiceResponse,
+};
+
+/// Descriptors for the Linkerd, health and reflection services, compiled by
+/// the build script.
+const DESCRIPTORS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptors.bin"));
+
+/// Serves the `grpc.reflection.v1alpha.ServerReflection` service for the
+/// services on a listener.
+#[derive(Clone, Debug)]
+pub(crate) struct ReflectionService {
+    index: Arc<Index>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    services: Vec<String>,
+    /// File descriptors by file name.
+    files: HashMap<String, FileDescriptorProto>,
+    /// The names of the files that declare each fully-qualified symbol.
+    symbols: HashMap<String, String>,
+}
+
+// === impl ReflectionService ===
+
+impl ReflectionService {
+    /// Lists `services`, and serves the descriptors of the services this
+    /// controller serves along with those in `descriptor_set`, an encoded
+    /// `FileDescriptorSet`.
+    pub(crate) fn new(
+        services: Vec<String>,
+        descriptor_set: Option<&[u8]>,
+    ) -> Result<Self, prost::DecodeError> {
+        let mut index = Index {
+            services,
+            ..Index::default()
+        };
+        index.add(FileDescriptorSet::decode(DESCRIPTORS)?);
+        if let Some(descriptor_set) = descriptor_set {
+            index.add(FileDescriptorSet::decode(descriptor_set)?);
+        }
+        Ok(Self {
+            index: Arc::new(index),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream =
+        mpsc::Receiver<Result<ServerReflectionResponse, tonic::Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        req: tonic::Request<tonic::Streaming<ServerReflectionRequest>>,
+    ) -> Result<tonic::Response<Self::ServerReflectionInfoStream>, tonic::Status> {
+        let mut requests = req.into_inner();
+        let index = self.index.clone();
+
+        let (mut tx, rx) = mpsc::channel(8);
+        tokio::spawn(
+            async move {
+                while let Some(req) = requests.next().await {
+                    let req = match req {
+                        Ok(req) => req,
+                        Err(status) => {
+                            tracing::debug!(%status, "Request stream failed");
+                            return;
+                        }
+                    };
+                    tracing::debug!(?req.message_request);
+                    if tx.send(Ok(index.respond(req))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+// === impl Index ===
+
+impl Index {
+    fn add(&mut self, descriptor_set: FileDescriptorSet) {
+        for file in descriptor_set.file.into_iter() {
+            let name = file.name().to_string();
+            let prefix = match file.package() {
+                "" => String::new(),
+                package => format!("{}.", package),
+            };
+
+            for message in &file.message_type {
+                self.add_message(&prefix, message, &name);
+            }
+            for enum_type in &file.enum_type {
+                self.add_symbol(format!("{}{}", prefix, enum_type.name()), &name);
+            }
+            for service in &file.service {
+                let service_name = format!("{}{}", prefix, service.name());
+                for method in &service.method {
+                    self.add_symbol(format!("{}.{}", service_name, method.name()), &name);
+                }
+                self.add_symbol(service_name, &name);
+            }
+
+            self.files.insert(name, file);
+        }
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &DescriptorProto, file: &str) {
+        let name = format!("{}{}", prefix, message.name());
+        let nested_prefix = format!("{}.", name);
+        for nested in &message.nested_type {
+            self.add_message(&nested_prefix, nested, file);
+        }
+        for enum_type in &message.enum_type {
+            self.add_symbol(format!("{}{}", nested_prefix, enum_type.name()), file);
+        }
+        self.add_symbol(name, file);
+    }
+
+    fn add_symbol(&mut self, symbol: String, file: &str) {
+        self.symbols.insert(symbol, file.to_string());
+    }
+
+    fn respond(&self, req: ServerReflectionRequest) -> ServerReflectionResponse {
+        let message_response = match req.message_request {
+            Some(MessageRequest::ListServices(_)) => {
+                MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+            Some(MessageRequest::FileByFilename(ref name)) => self.file_response(name),
+            Some(MessageRequest::FileContainingSymbol(ref symbol)) => {
+                match self.symbols.get(symbol) {
+                    Some(name) => self.file_response(name),
+                    None => error(tonic::Code::NotFound, "symbol not found"),
+                }
+            }
+            // None of the services' descriptors declare extensions.
+            Some(MessageRequest::FileContainingExtension(_)) => {
+                error(tonic::Code::NotFound, "extension not found")
+            }
+            Some(MessageRequest::AllExtensionNumbersOfType(ref base_type_name)) => {
+                if self.symbols.contains_key(base_type_name) {
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: base_type_name.clone(),
+                        extension_number: Vec::new(),
+                    })
+                } else {
+                    error(tonic::Code::NotFound, "type not found")
+                }
+            }
+            None => error(tonic::Code::InvalidArgument, "missing message request"),
+        };
+
+        ServerReflectionResponse {
+            valid_host: req.host.clone(),
+            original_request: Some(req),
+            message_response: Some(message_response),
+        }
+    }
+
+    /// Responds with the named file and all of the files it depends on.
+    fn file_response(&self, name: &str) -> MessageResponse {
+        if !self.files.contains_key(name) {
+            return error(tonic::Code::NotFound, "file not found");
+        }
+
+        let mut file_descriptor_proto = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![name];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            if let Some(file) = self.files.get(name) {
+                let mut buf = Vec::with_capacity(file.encoded_len());
+                file.encode(&mut buf)
+                    .expect("buffer must have sufficient capacity");
+                file_descriptor_proto.push(buf);
+                pending.extend(file.dependency.iter().map(String::as_str));
+            }
+        }
+
+        MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto,
+        })
+    }
+}
+
+fn error(code: tonic::Code, message: &str) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: message.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pb::server_reflection_client::ServerReflectionClient;
+    use super::*;
+    use crate::{Error, MockController};
+
+    async fn reflect(
+        controller: &MockController,
+        request: MessageRequest,
+    ) -> Result<MessageResponse, Error> {
+        let mut client =
+            ServerReflectionClient::connect(format!("http://{}", controller.addr())).await?;
+        let req = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(request),
+        };
+        let mut rsps = client
+            .server_reflection_info(stream::iter(vec![req]))
+            .await?
+            .into_inner();
+        let rsp = rsps.message().await?.ok_or("stream ended")?;
+        Ok(rsp.message_response.ok_or("missing response")?)
+    }
+
+    #[tokio::test]
+    async fn lists_and_describes_served_services() -> Result<(), Error> {
+        let controller = MockController::spawn().await?;
+        controller.ready().await;
+
+        let mut services =
+            match reflect(&controller, MessageRequest::ListServices(String::new())).await? {
+                MessageResponse::ListServicesResponse(rsp) => {
+                    rsp.service.into_iter().map(|s| s.name).collect::<Vec<_>>()
+                }
+                rsp => panic!("unexpected response: {:?}", rsp),
+            };
+        services.sort();
+        assert_eq!(
+            services,
+            [
+                "grpc.health.v1.Health",
+                "grpc.reflection.v1alpha.ServerReflection",
+                "io.linkerd.proxy.destination.Destination",
+                "io.linkerd.proxy.identity.Identity",
+                "io.linkerd.proxy.inbound.InboundServerPolicies",
+                "io.linkerd.proxy.outbound.OutboundPolicies",
+            ]
+        );
+
+        // The file declaring each service is served first, followed by its
+        // dependencies.
+        for service in services {
+            let symbol = MessageRequest::FileContainingSymbol(service.clone());
+            let files = match reflect(&controller, symbol).await? {
+                MessageResponse::FileDescriptorResponse(rsp) => rsp.file_descriptor_proto,
+                rsp => panic!("unexpected response for {}: {:?}", service, rsp),
+            };
+            let file = FileDescriptorProto::decode(files[0].as_slice())?;
+            assert!(
+                file.service
+                    .iter()
+                    .any(|s| format!("{}.{}", file.package(), s.name()) == service),
+                "{} is not declared in {}",
+                service,
+                file.name()
+            );
+        }
+
+        controller.shutdown().await
+    }
+}
@@ -0,0 +1,359 @@
+use crate::{Dst, DstSender, Error, Overrides, RolloutSpec};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+
+/// The total weight of a rollout's overrides, so that each percent of traffic
+/// is weighted 10.
+const TOTAL_WEIGHT: u32 = 1000;
+
+/// A staged shift of a destination's traffic from one backend to another.
+///
+/// Rollouts are parsed from specs like
+/// `rollout(from=foo-v1:80,to=foo-v2:80,step=10,interval=5s,pause=50)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rollout {
+    pub(crate) from: Dst,
+    pub(crate) to: Dst,
+    /// The percentage of traffic shifted at each step.
+    pub(crate) step: u32,
+    pub(crate) interval: Duration,
+    /// The percentages of traffic at which the rollout waits to be resumed.
+    pub(crate) pauses: Vec<u32>,
+}
+
+/// Shifts the traffic of destinations between backends in steps, serving
+/// each step as the destinations' overrides.
+#[derive(Debug)]
+pub struct RolloutRunner {
+    dsts: HashMap<Dst, Rollout>,
+    triggers: Option<mpsc::UnboundedReceiver<Trigger>>,
+}
+
+/// Resumes a `RolloutRunner`'s paused rollouts, or rolls them back.
+#[derive(Clone, Debug)]
+pub struct RolloutTrigger(mpsc::UnboundedSender<Trigger>);
+
+#[derive(Copy, Clone, Debug)]
+enum Trigger {
+    Resume,
+    Rollback,
+}
+
+#[derive(Debug)]
+struct RollingOut {
+    dst: Dst,
+    rollout: Rollout,
+    /// The percentage of traffic shifted to `rollout.to`.
+    percent: u32,
+    state: State,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// The next step is due at the given time.
+    Stepping(Instant),
+    Paused,
+    /// All traffic has been shifted, or the rollout was rolled back.
+    Done,
+}
+
+// === impl Rollout ===
+
+impl Rollout {
+    /// Shifts traffic from `from` to `to` in 10% steps every 5 seconds,
+    /// without pausing.
+    pub fn new(from: Dst, to: Dst) -> Self {
+        Self {
+            from,
+            to,
+            step: 10,
+            interval: Duration::from_secs(5),
+            pauses: Vec::new(),
+        }
+    }
+
+    /// Sets the percentage of traffic, between 1 and 100, shifted at each
+    /// step.
+    pub fn step(self, step: u32) -> Self {
+        Self { step, ..self }
+    }
+
+    pub fn interval(self, interval: Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Waits to be resumed once `percent` of traffic has been shifted.
+    pub fn pause_at(mut self, percent: u32) -> Self {
+        self.pauses.push(percent);
+        self
+    }
+}
+
+// === impl RolloutRunner ===
+
+impl RolloutRunner {
+    /// Rolls out each destination in `spec`, returning a trigger that
+    /// resumes or rolls back the rollouts.
+    pub fn new(spec: RolloutSpec) -> (RolloutTrigger, Self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let runner = Self {
+            dsts: spec.dsts,
+            triggers: Some(rx),
+        };
+        (RolloutTrigger(tx), runner)
+    }
+
+    /// Serves the overrides of each step through `sender` until every
+    /// rollout is done, or an update fails to be sent.
+    ///
+    /// Each destination starts with all of its traffic on its `from`
+    /// backend, replacing any overrides it was served.
+    pub async fn run(mut self, sender: &mut DstSender) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut rollouts = self
+            .dsts
+            .drain()
+            .map(|(dst, rollout)| RollingOut {
+                state: State::Stepping(start + rollout.interval),
+                dst,
+                rollout,
+                percent: 0,
+            })
+            .collect::<Vec<_>>();
+        for rolling in &rollouts {
+            sender
+                .send_overrides(rolling.dst.clone(), rolling.overrides())
+                .await?;
+        }
+
+        while rollouts.iter().any(|r| r.state != State::Done) {
+            let next_step = rollouts
+                .iter()
+                .filter_map(|r| match r.state {
+                    State::Stepping(at) => Some(at),
+                    _ => None,
+                })
+                .min();
+            let trigger = match next_step {
+                Some(at) => tokio::select! {
+                    _ = time::delay_until(at) => None,
+                    trigger = next_trigger(&mut self.triggers) => Some(trigger),
+                },
+                None => Some(next_trigger(&mut self.triggers).await),
+            };
+
+            let now = Instant::now();
+            for rolling in &mut rollouts {
+                if rolling.update(trigger, now) {
+                    sender
+                        .send_overrides(rolling.dst.clone(), rolling.overrides())
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waits for the next trigger, or forever once all triggers are dropped.
+async fn next_trigger(triggers: &mut Option<mpsc::UnboundedReceiver<Trigger>>) -> Trigger {
+    if let Some(rx) = triggers {
+        if let Some(trigger) = rx.recv().await {
+            return trigger;
+        }
+    }
+    *triggers = None;
+    futures::future::pending().await
+}
+
+// === impl RolloutTrigger ===
+
+impl RolloutTrigger {
+    /// Resumes the rollouts that are paused.
+    pub fn resume(&self) {
+        let _ = self.0.send(Trigger::Resume);
+    }
+
+    /// Shifts all traffic of the rollouts in progress back to their `from`
+    /// backends, ending them.
+    pub fn rollback(&self) {
+        let _ = self.0.send(Trigger::Rollback);
+    }
+}
+
+// === impl RollingOut ===
+
+impl RollingOut {
+    /// Applies `trigger`, or the step that is due by `now`, returning whether
+    /// the overrides changed.
+    fn update(&mut self, trigger: Option<Trigger>, now: Instant) -> bool {
+        match (trigger, &self.state) {
+            (_, State::Done) => false,
+            (Some(Trigger::Rollback), _) => {
+                tracing::info!(dst = %self.dst, percent = self.percent, "Rolling back");
+                self.percent = 0;
+                self.state = State::Done;
+                true
+            }
+            (Some(Trigger::Resume), State::Paused) => {
+                tracing::info!(dst = %self.dst, percent = self.percent, "Resuming");
+                self.state = State::Stepping(now + self.rollout.interval);
+                false
+            }
+            (_, State::Stepping(at)) if *at <= now => {
+                self.step(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Shifts the next step of traffic, stopping early at a pause.
+    fn step(&mut self, now: Instant) {
+        let next = (self.percent + self.rollout.step).min(100);
+        let pause = self
+            .rollout
+            .pauses
+            .iter()
+            .copied()
+            .filter(|&pause| pause > self.percent && pause <= next)
+            .min();
+        match pause {
+            Some(pause) => {
+                self.percent = pause;
+                self.state = State::Paused;
+                tracing::info!(dst = %self.dst, percent = self.percent, "Paused");
+            }
+            None if next == 100 => {
+                self.percent = next;
+                self.state = State::Done;
+                tracing::info!(dst = %self.dst, "Rolled out");
+            }
+            None => {
+                self.percent = next;
+                self.state = State::Stepping(now + self.rollout.interval);
+                tracing::debug!(dst = %self.dst, percent = self.percent, "Stepped");
+            }
+        }
+    }
+
+    fn overrides(&self) -> Overrides {
+        let to = self.percent * TOTAL_WEIGHT / 100;
+        let dsts = vec![
+            (self.rollout.from.clone(), TOTAL_WEIGHT - to),
+            (self.rollout.to.clone(), to),
+        ]
+        .into_iter()
+        .filter(|&(_, weight)| weight > 0)
+        .collect();
+        Overrides::new(dsts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    fn rolling(rollout: Rollout, now: Instant) -> RollingOut {
+        RollingOut {
+            dst: "web.ns.svc.cluster.local:80".parse().unwrap(),
+            rollout: rollout.interval(INTERVAL),
+            percent: 0,
+            state: State::Stepping(now + INTERVAL),
+        }
+    }
+
+    fn rollout() -> Rollout {
+        Rollout::new(
+            "web-v1.ns.svc.cluster.local:80".parse().unwrap(),
+            "web-v2.ns.svc.cluster.local:80".parse().unwrap(),
+        )
+    }
+
+    fn overrides(v1: u32, v2: u32) -> Overrides {
+        let dsts = vec![
+            ("web-v1.ns.svc.cluster.local:80".parse().unwrap(), v1),
+            ("web-v2.ns.svc.cluster.local:80".parse().unwrap(), v2),
+        ]
+        .into_iter()
+        .filter(|&(_, weight)| weight > 0)
+        .collect();
+        Overrides::new(dsts)
+    }
+
+    /// Steps `rolling` once its next step is due, returning the percentage
+    /// of traffic that was shifted.
+    fn step(rolling: &mut RollingOut, now: &mut Instant) -> u32 {
+        *now += INTERVAL;
+        assert!(rolling.update(None, *now));
+        rolling.percent
+    }
+
+    #[test]
+    fn shifts_traffic_in_steps() {
+        let mut now = Instant::now();
+        let mut rolling = rolling(rollout().step(30), now);
+        assert_eq!(rolling.overrides(), overrides(1000, 0));
+
+        // Nothing changes before the first step is due.
+        assert!(!rolling.update(None, now + INTERVAL / 2));
+        assert_eq!(step(&mut rolling, &mut now), 30);
+        assert_eq!(rolling.overrides(), overrides(700, 300));
+        assert_eq!(step(&mut rolling, &mut now), 60);
+        assert_eq!(step(&mut rolling, &mut now), 90);
+        // The last step is cut short.
+        assert_eq!(step(&mut rolling, &mut now), 100);
+        assert_eq!(rolling.state, State::Done);
+        assert_eq!(rolling.overrides(), overrides(0, 1000));
+        assert!(!rolling.update(None, now + INTERVAL));
+    }
+
+    #[test]
+    fn pauses_until_resumed() {
+        let mut now = Instant::now();
+        let mut rolling = rolling(rollout().step(30).pause_at(50), now);
+        assert_eq!(step(&mut rolling, &mut now), 30);
+        assert_eq!(step(&mut rolling, &mut now), 50);
+        assert_eq!(rolling.state, State::Paused);
+        assert_eq!(rolling.overrides(), overrides(500, 500));
+        assert!(!rolling.update(None, now + INTERVAL * 10));
+
+        // Resuming waits another interval before the next step.
+        assert!(!rolling.update(Some(Trigger::Resume), now));
+        assert_eq!(rolling.state, State::Stepping(now + INTERVAL));
+        assert_eq!(step(&mut rolling, &mut now), 80);
+        assert_eq!(step(&mut rolling, &mut now), 100);
+    }
+
+    #[test]
+    fn rolls_back_to_the_original_backend() {
+        let mut now = Instant::now();
+        let mut rolling = rolling(rollout().step(40), now);
+        assert_eq!(step(&mut rolling, &mut now), 40);
+        assert!(rolling.update(Some(Trigger::Rollback), now));
+        assert_eq!(rolling.state, State::Done);
+        assert_eq!(rolling.overrides(), overrides(1000, 0));
+
+        // Finished rollouts are not resumed or rolled back.
+        assert!(!rolling.update(Some(Trigger::Resume), now));
+        assert!(!rolling.update(Some(Trigger::Rollback), now));
+    }
+
+    #[tokio::test]
+    async fn finishes_once_all_traffic_is_shifted() {
+        let (mut sender, _svc) = crate::DstService::empty();
+        let spec = "web.ns.svc.cluster.local:80=rollout(from=web-v1.ns.svc.cluster.local:80,to=web-v2.ns.svc.cluster.local:80,step=50,interval=1ms)"
+            .parse::<RolloutSpec>()
+            .unwrap();
+        let (_trigger, runner) = RolloutRunner::new(spec);
+        time::timeout(Duration::from_secs(5), runner.run(&mut sender))
+            .await
+            .expect("rollout did not finish")
+            .unwrap();
+    }
+}
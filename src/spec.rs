@@ -1,6 +1,13 @@
-use crate::{Dst, EndpointMeta, Endpoints, Overrides};
+use crate::{
+    CertifyFaults, Churn, Dst, DstPattern, EndpointMeta, Endpoints, EndpointsGenerator,
+    EndpointsTemplate, FinalUpdate, Listen, Overrides, Rollout, StatusRange, Target, Teardown,
+    Weight,
+};
+use serde::{Deserialize, Deserializer};
 use std::collections::{BTreeMap, HashMap};
-use std::{default::Default, error::Error, str::FromStr};
+use std::{
+    convert::TryFrom, default::Default, error::Error, net::IpAddr, str::FromStr, time::Duration,
+};
 use tracing_error::{prelude::*, TracedError};
 
 #[derive(Debug, Default)]
@@ -8,11 +15,41 @@ pub struct EndpointsSpec {
     pub(super) dsts: HashMap<Dst, Endpoints>,
 }
 
+/// Endpoints templates for the destinations that each pattern matches, in
+/// the order that patterns are tried.
+#[derive(Debug, Default)]
+pub struct PatternsSpec {
+    pub(super) patterns: Vec<(DstPattern, EndpointsTemplate)>,
+}
+
+#[derive(Debug, Default)]
+pub struct ChurnSpec {
+    pub(super) dsts: HashMap<Dst, Churn>,
+}
+
+#[derive(Debug, Default)]
+pub struct RolloutSpec {
+    pub(super) dsts: HashMap<Dst, Rollout>,
+}
+
 #[derive(Debug, Default)]
 pub struct OverridesSpec {
     pub(super) dsts: HashMap<Dst, Overrides>,
 }
 
+#[derive(Debug, Default)]
+pub struct IdentityFaultsSpec {
+    pub(super) identities: HashMap<String, CertifyFaults>,
+}
+
+/// An IP network, such as `10.0.0.0/16`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
 #[derive(Debug)]
 pub struct ParseError {
     reason: &'static str,
@@ -26,6 +63,12 @@ macro_rules! parse_error {
 
 // === impl EndpointsSpec ===
 
+impl Extend<(Dst, Endpoints)> for EndpointsSpec {
+    fn extend<I: IntoIterator<Item = (Dst, Endpoints)>>(&mut self, iter: I) {
+        self.dsts.extend(iter)
+    }
+}
+
 impl FromStr for EndpointsSpec {
     type Err = TracedError<ParseError>;
 
@@ -37,16 +80,17 @@ impl FromStr for EndpointsSpec {
 
         #[tracing::instrument(level = "info")]
         fn parse_entry(entry: &str) -> Result<(Dst, Endpoints), TracedError<ParseError>> {
-            let mut parts = entry.split('=');
-            match (parts.next(), parts.next(), parts.next()) {
-                (_, _, Some(_)) => parse_error!("too many '='s"),
-                (None, _, _) | (_, None, None) => parse_error!("no destination or endpoints"),
-                (Some(dst), Some(endpoints), None) => {
+            // Only split on the first '=', since generators' arguments
+            // contain '='s, too.
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(dst), Some(endpoints)) => {
                     let dst = dst.parse()?;
                     let endpoints = endpoints.parse()?;
                     tracing::trace!(?dst, ?endpoints, "parsed");
                     Ok((dst, endpoints))
                 }
+                _ => parse_error!("no destination or endpoints"),
             }
         }
 
@@ -55,6 +99,62 @@ impl FromStr for EndpointsSpec {
     }
 }
 
+// === impl Cidr ===
+
+impl Cidr {
+    /// The network's address, with all host bits unset.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Cidr::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let (addr, prefix_len) = match (parts.next(), parts.next()) {
+            (Some(addr), Some(prefix_len)) => (addr, prefix_len),
+            _ => parse_error!("network has no prefix length"),
+        };
+        let addr = match addr.parse::<IpAddr>() {
+            Ok(addr) => addr,
+            Err(_) => parse_error!("invalid network address"),
+        };
+        let prefix_len = match prefix_len.parse::<u8>() {
+            Ok(len) if addr.is_ipv4() && len <= 32 => len,
+            Ok(len) if addr.is_ipv6() && len <= 128 => len,
+            _ => parse_error!("invalid prefix length"),
+        };
+
+        // Unset the host bits, so that e.g. `10.0.0.1/8` is `10.0.0.0/8`.
+        let addr = match addr {
+            IpAddr::V4(addr) => {
+                let mask = (!0u32).checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                IpAddr::from((u32::from(addr) & mask).to_be_bytes())
+            }
+            IpAddr::V6(addr) => {
+                let mask = (!0u128).checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                IpAddr::from((u128::from(addr) & mask).to_be_bytes())
+            }
+        };
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = TracedError<ParseError>;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 // === impl ParseError ===
 
 impl std::fmt::Display for ParseError {
@@ -65,6 +165,27 @@ impl std::fmt::Display for ParseError {
 
 impl Error for ParseError {}
 
+// === impl Listen ===
+
+impl FromStr for Listen {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Listen::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("unix:") {
+            match &s["unix:".len()..] {
+                "" => parse_error!("empty socket path"),
+                path => return Ok(Listen::Unix(path.into())),
+            }
+        }
+
+        match s.parse() {
+            Ok(addr) => Ok(Listen::Tcp(addr)),
+            Err(_) => parse_error!("invalid socket address"),
+        }
+    }
+}
+
 // === impl Dst ===
 
 impl FromStr for Dst {
@@ -83,6 +204,74 @@ impl FromStr for Dst {
     }
 }
 
+impl TryFrom<String> for Dst {
+    type Error = TracedError<ParseError>;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// === impl Target ===
+
+impl FromStr for Target {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Target::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Target::Addr(addr));
+        }
+        s.parse().map(Target::Authority)
+    }
+}
+
+// === impl StatusRange ===
+
+impl FromStr for StatusRange {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "StatusRange::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_status(s: &str) -> Result<u16, TracedError<ParseError>> {
+            match s.parse() {
+                Ok(status) if (100..600).contains(&status) => Ok(status),
+                _ => parse_error!("invalid status code"),
+            }
+        }
+
+        // A class of statuses, like `5xx`.
+        if s.len() == 3 && s.ends_with("xx") {
+            let start = parse_status(&format!("{}00", &s[..1]))?;
+            return Ok(StatusRange::new(start, start + 99));
+        }
+
+        let mut parts = s.splitn(2, '-');
+        match (parts.next(), parts.next()) {
+            (Some(status), None) => {
+                let status = parse_status(status)?;
+                Ok(StatusRange::new(status, status))
+            }
+            (Some(start), Some(end)) => {
+                let (start, end) = (parse_status(start)?, parse_status(end)?);
+                if start > end {
+                    parse_error!("status range ends before it starts");
+                }
+                Ok(StatusRange::new(start, end))
+            }
+            _ => parse_error!("invalid status range"),
+        }
+    }
+}
+
+impl TryFrom<String> for StatusRange {
+    type Error = TracedError<ParseError>;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 // === impl Endpoints ===
 
 impl FromStr for Endpoints {
@@ -90,6 +279,11 @@ impl FromStr for Endpoints {
 
     #[tracing::instrument(name = "Endpoints::from_str", level = "error")]
     fn from_str(endpoints: &str) -> Result<Self, Self::Err> {
+        if endpoints.starts_with("gen(") {
+            let generator = endpoints.parse::<EndpointsGenerator>()?;
+            return Ok(generator.generate());
+        }
+
         let endpoints = endpoints
             .split(',')
             .map(|addr| {
@@ -124,8 +318,411 @@ impl FromStr for Endpoints {
     }
 }
 
+// === impl PatternsSpec ===
+
+impl PatternsSpec {
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+impl FromStr for PatternsSpec {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "PatternsSpec::from_str", level = "error")]
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.is_empty() {
+            return Ok(Self::default());
+        }
+
+        #[tracing::instrument(level = "info")]
+        fn parse_entry(
+            entry: &str,
+        ) -> Result<(DstPattern, EndpointsTemplate), TracedError<ParseError>> {
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(pattern), Some(template)) => {
+                    let pattern = pattern.parse()?;
+                    let template = template.parse()?;
+                    tracing::trace!(?pattern, ?template, "parsed");
+                    Ok((pattern, template))
+                }
+                _ => parse_error!("no pattern or endpoints template"),
+            }
+        }
+
+        let patterns = spec.split(';').map(parse_entry).collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+}
+
+// === impl DstPattern ===
+
+impl FromStr for DstPattern {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "DstPattern::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_port(s: &str) -> Result<u16, TracedError<ParseError>> {
+            match s.parse() {
+                Ok(port) => Ok(port),
+                Err(_) => parse_error!("invalid port"),
+            }
+        }
+
+        // The port is split from the end, since regexes may contain colons.
+        let mut parts = s.rsplitn(2, ':');
+        let (ports, name) = match (parts.next(), parts.next()) {
+            (Some(ports), Some(name)) => (ports, name),
+            _ => parse_error!("invalid destination pattern"),
+        };
+        let pattern = match call_args(name, "regex") {
+            Some(regex) => match DstPattern::regex(regex) {
+                Ok(pattern) => pattern,
+                Err(_) => parse_error!("invalid regex"),
+            },
+            None => DstPattern::glob(name),
+        };
+        let mut ports = ports.splitn(2, '-');
+        match (ports.next(), ports.next()) {
+            (Some("*"), None) => Ok(pattern),
+            (Some(port), None) => {
+                let port = parse_port(port)?;
+                Ok(pattern.ports(port..=port))
+            }
+            (Some(min), Some(max)) => {
+                let (min, max) = (parse_port(min)?, parse_port(max)?);
+                if min > max {
+                    parse_error!("port range is empty");
+                }
+                Ok(pattern.ports(min..=max))
+            }
+            _ => parse_error!("invalid port"),
+        }
+    }
+}
+
+// === impl EndpointsTemplate ===
+
+impl FromStr for EndpointsTemplate {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "EndpointsTemplate::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Templates are checked by rendering them for an arbitrary port.
+        s.replace("{port}", "1").parse::<Endpoints>()?;
+        Ok(Self(s.to_string()))
+    }
+}
+
+// === impl EndpointsGenerator ===
+
+impl FromStr for EndpointsGenerator {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "EndpointsGenerator::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = match call_args(s, "gen") {
+            Some(args) => args,
+            None => parse_error!("generators must be of the form 'gen(...)'"),
+        };
+
+        let (mut cidr, mut port, mut count) = (None, None, None);
+        let mut generator_weight = None;
+        let mut h2 = None;
+        let mut seed = None;
+        for arg in split_args(args) {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("cidr"), Some(value)) => cidr = Some(value.parse::<Cidr>()?),
+                (Some("port"), Some(value)) => match value.parse::<u16>() {
+                    Ok(value) => port = Some(value),
+                    Err(_) => parse_error!("invalid port"),
+                },
+                (Some("count"), Some(value)) => match value.parse::<usize>() {
+                    Ok(value) => count = Some(value),
+                    Err(_) => parse_error!("invalid endpoint count"),
+                },
+                (Some("weight"), Some(value)) => generator_weight = Some(parse_weight(value)?),
+                (Some("h2"), Some(value)) => match value.parse::<f64>() {
+                    Ok(value) if (0.0..=1.0).contains(&value) => h2 = Some(value),
+                    _ => parse_error!("h2 must be a probability between 0 and 1"),
+                },
+                (Some("seed"), Some(value)) => match value.parse::<u64>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => parse_error!("invalid seed"),
+                },
+                _ => parse_error!("invalid generator argument"),
+            }
+        }
+
+        let (cidr, port, count) = match (cidr, port, count) {
+            (Some(cidr), Some(port), Some(count)) => (cidr, port, count),
+            _ => parse_error!("generators require a cidr, port and count"),
+        };
+        if count > crate::generate::capacity(&cidr) {
+            parse_error!("network is too small for the endpoint count");
+        }
+
+        let mut generator = EndpointsGenerator::new(cidr, port, count);
+        if let Some(weight) = generator_weight {
+            generator = generator.weight(weight);
+        }
+        if let Some(h2) = h2 {
+            generator = generator.h2(h2);
+        }
+        if let Some(seed) = seed {
+            generator = generator.seed(seed);
+        }
+        Ok(generator)
+    }
+}
+
+// === impl ChurnSpec ===
+
+impl ChurnSpec {
+    pub fn is_empty(&self) -> bool {
+        self.dsts.is_empty()
+    }
+}
+
+impl FromStr for ChurnSpec {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "ChurnSpec::from_str", level = "error")]
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.is_empty() {
+            return Ok(Self::default());
+        }
+
+        #[tracing::instrument(level = "info")]
+        fn parse_entry(entry: &str) -> Result<(Dst, Churn), TracedError<ParseError>> {
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(dst), Some(churn)) => {
+                    let dst = dst.parse()?;
+                    let churn = churn.parse()?;
+                    tracing::trace!(?dst, ?churn, "parsed");
+                    Ok((dst, churn))
+                }
+                _ => parse_error!("no destination or churn"),
+            }
+        }
+
+        let dsts = spec.split(';').map(parse_entry).collect::<Result<_, _>>()?;
+        Ok(Self { dsts })
+    }
+}
+
+// === impl Churn ===
+
+impl FromStr for Churn {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Churn::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = match call_args(s, "churn") {
+            Some(args) => args,
+            None => parse_error!("churn must be of the form 'churn(...)'"),
+        };
+
+        fn parse_rate(s: &str) -> Result<f64, TracedError<ParseError>> {
+            match s.parse::<f64>() {
+                Ok(rate) if rate >= 0.0 && rate.is_finite() => Ok(rate),
+                _ => parse_error!("invalid rate"),
+            }
+        }
+
+        let (mut cidr, mut port) = (None, None);
+        let (mut add, mut remove, mut reweight) = (0.0, 0.0, 0.0);
+        let mut churn_weight = None;
+        let mut h2 = None;
+        let mut seed = None;
+        for arg in split_args(args) {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("cidr"), Some(value)) => cidr = Some(value.parse::<Cidr>()?),
+                (Some("port"), Some(value)) => match value.parse::<u16>() {
+                    Ok(value) => port = Some(value),
+                    Err(_) => parse_error!("invalid port"),
+                },
+                (Some("add"), Some(value)) => add = parse_rate(value)?,
+                (Some("remove"), Some(value)) => remove = parse_rate(value)?,
+                (Some("reweight"), Some(value)) => reweight = parse_rate(value)?,
+                (Some("weight"), Some(value)) => churn_weight = Some(parse_weight(value)?),
+                (Some("h2"), Some(value)) => match value.parse::<f64>() {
+                    Ok(value) if (0.0..=1.0).contains(&value) => h2 = Some(value),
+                    _ => parse_error!("h2 must be a probability between 0 and 1"),
+                },
+                (Some("seed"), Some(value)) => match value.parse::<u64>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => parse_error!("invalid seed"),
+                },
+                _ => parse_error!("invalid churn argument"),
+            }
+        }
+
+        let (cidr, port) = match (cidr, port) {
+            (Some(cidr), Some(port)) => (cidr, port),
+            _ => parse_error!("churn requires a cidr and port"),
+        };
+
+        let mut churn = Churn::new(cidr, port)
+            .add(add)
+            .remove(remove)
+            .reweight(reweight);
+        if let Some(weight) = churn_weight {
+            churn = churn.weight(weight);
+        }
+        if let Some(h2) = h2 {
+            churn = churn.h2(h2);
+        }
+        if let Some(seed) = seed {
+            churn = churn.seed(seed);
+        }
+        Ok(churn)
+    }
+}
+
+// === impl RolloutSpec ===
+
+impl RolloutSpec {
+    pub fn is_empty(&self) -> bool {
+        self.dsts.is_empty()
+    }
+}
+
+impl FromStr for RolloutSpec {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "RolloutSpec::from_str", level = "error")]
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.is_empty() {
+            return Ok(Self::default());
+        }
+
+        #[tracing::instrument(level = "info")]
+        fn parse_entry(entry: &str) -> Result<(Dst, Rollout), TracedError<ParseError>> {
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(dst), Some(rollout)) => {
+                    let dst = dst.parse()?;
+                    let rollout = rollout.parse()?;
+                    tracing::trace!(?dst, ?rollout, "parsed");
+                    Ok((dst, rollout))
+                }
+                _ => parse_error!("no destination or rollout"),
+            }
+        }
+
+        let dsts = spec.split(';').map(parse_entry).collect::<Result<_, _>>()?;
+        Ok(Self { dsts })
+    }
+}
+
+// === impl Rollout ===
+
+impl FromStr for Rollout {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Rollout::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = match call_args(s, "rollout") {
+            Some(args) => args,
+            None => parse_error!("rollout must be of the form 'rollout(...)'"),
+        };
+
+        fn parse_percent(s: &str) -> Result<u32, TracedError<ParseError>> {
+            match s.trim_end_matches('%').parse::<u32>() {
+                Ok(percent) if percent > 0 && percent <= 100 => Ok(percent),
+                _ => parse_error!("percentages must be between 1 and 100"),
+            }
+        }
+
+        let (mut from, mut to) = (None, None);
+        let mut step = None;
+        let mut interval = None;
+        let mut pauses = Vec::new();
+        for arg in split_args(args) {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("from"), Some(value)) => from = Some(value.parse::<Dst>()?),
+                (Some("to"), Some(value)) => to = Some(value.parse::<Dst>()?),
+                (Some("step"), Some(value)) => step = Some(parse_percent(value)?),
+                (Some("interval"), Some(value)) => interval = Some(parse_duration(value)?),
+                (Some("pause"), Some(value)) => pauses.push(parse_percent(value)?),
+                _ => parse_error!("invalid rollout argument"),
+            }
+        }
+
+        let mut rollout = match (from, to) {
+            (Some(from), Some(to)) => Rollout::new(from, to),
+            _ => parse_error!("rollout requires a from and to destination"),
+        };
+        if let Some(step) = step {
+            rollout = rollout.step(step);
+        }
+        if let Some(interval) = interval {
+            rollout = rollout.interval(interval);
+        }
+        for pause in pauses {
+            rollout = rollout.pause_at(pause);
+        }
+        Ok(rollout)
+    }
+}
+
+/// Parses a weight, which is either a number or `uniform(MIN,MAX)`.
+fn parse_weight(s: &str) -> Result<Weight, TracedError<ParseError>> {
+    if let Some(args) = call_args(s, "uniform") {
+        let mut bounds = args.splitn(2, ',').map(|b| b.trim().parse::<u32>());
+        return match (bounds.next(), bounds.next()) {
+            (Some(Ok(min)), Some(Ok(max))) if min <= max => Ok(Weight::Uniform(min, max)),
+            _ => parse_error!("invalid uniform weight bounds"),
+        };
+    }
+    match s.parse() {
+        Ok(weight) => Ok(Weight::Fixed(weight)),
+        Err(_) => parse_error!("invalid weight"),
+    }
+}
+
+/// Returns the arguments of a call like `name(args)`.
+fn call_args<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    if s.starts_with(name) && s[name.len()..].starts_with('(') && s.ends_with(')') {
+        Some(&s[name.len() + 1..s.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Splits arguments on the commas that are not nested in parentheses.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                split.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&args[start..]);
+    split
+}
+
 // === impl OverridesSpec ===
 
+impl Extend<(Dst, Overrides)> for OverridesSpec {
+    fn extend<I: IntoIterator<Item = (Dst, Overrides)>>(&mut self, iter: I) {
+        self.dsts.extend(iter)
+    }
+}
+
 impl FromStr for OverridesSpec {
     type Err = TracedError<ParseError>;
 
@@ -187,3 +784,409 @@ impl FromStr for Overrides {
         Ok(Overrides::new(dsts))
     }
 }
+
+// === impl IdentityFaultsSpec ===
+
+impl FromStr for IdentityFaultsSpec {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "IdentityFaultsSpec::from_str", level = "error")]
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.is_empty() {
+            return Ok(Self::default());
+        }
+
+        #[tracing::instrument(level = "info")]
+        fn parse_entry(entry: &str) -> Result<(String, CertifyFaults), TracedError<ParseError>> {
+            let mut parts = entry.split('=');
+            match (parts.next(), parts.next(), parts.next()) {
+                (_, _, Some(_)) => parse_error!("too many '='s"),
+                (None, _, _) | (_, None, None) => parse_error!("no identity or faults"),
+                (Some(""), _, _) => parse_error!("empty identity"),
+                (Some(identity), Some(faults), None) => {
+                    let faults = faults.parse()?;
+                    tracing::trace!(?identity, ?faults, "parsed");
+                    Ok((identity.to_string(), faults))
+                }
+            }
+        }
+
+        let identities = spec.split(';').map(parse_entry).collect::<Result<_, _>>()?;
+        Ok(Self { identities })
+    }
+}
+
+// === impl CertifyFaults ===
+
+impl FromStr for CertifyFaults {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "CertifyFaults::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = CertifyFaults::default();
+        for fault in s.split(',') {
+            let span = tracing::error_span!("parse_fault", ?fault);
+            let _g = span.enter();
+
+            // Faults are either a bare name or a name followed by a single
+            // argument in parentheses, e.g. `fail(3)` or `latency(500ms)`.
+            let (name, arg) = match fault.find('(') {
+                Some(idx) if fault.ends_with(')') => {
+                    (&fault[..idx], Some(&fault[idx + 1..fault.len() - 1]))
+                }
+                Some(_) => parse_error!("unclosed '('"),
+                None => (fault, None),
+            };
+            match (name, arg) {
+                ("fail", Some(n)) => match n.parse() {
+                    Ok(n) => faults.fail_first = n,
+                    Err(_) => parse_error!("invalid failure count"),
+                },
+                ("latency", Some(latency)) => faults.latency = Some(parse_duration(latency)?),
+                ("unavailable", None) => faults.unavailable = true,
+                ("wrong-name", Some(name)) if !name.is_empty() => {
+                    faults.wrong_name = Some(name.to_string())
+                }
+                ("truncate-chain", None) => faults.truncate_chain = true,
+                ("alternate", Some(name)) if !name.is_empty() => {
+                    faults.alternate = Some(name.to_string())
+                }
+                _ => parse_error!("invalid fault"),
+            }
+        }
+        Ok(faults)
+    }
+}
+
+// === impl FinalUpdate ===
+
+impl FromStr for FinalUpdate {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "FinalUpdate::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no-endpoints" => Ok(FinalUpdate::NoEndpoints),
+            "close" => Ok(FinalUpdate::Close),
+            _ if s.starts_with("status(") && s.ends_with(')') => {
+                let code = parse_code(&s["status(".len()..s.len() - 1])?;
+                Ok(FinalUpdate::Status(
+                    code,
+                    "controller shutting down".to_string(),
+                ))
+            }
+            _ => parse_error!("invalid final update"),
+        }
+    }
+}
+
+// === impl Teardown ===
+
+impl FromStr for Teardown {
+    type Err = TracedError<ParseError>;
+
+    #[tracing::instrument(name = "Teardown::from_str", level = "error")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reset" => Ok(Teardown::Reset),
+            _ if s.starts_with("status(") && s.ends_with(')') => {
+                let code = parse_code(&s["status(".len()..s.len() - 1])?;
+                Ok(Teardown::Status(code, "controller restarting".to_string()))
+            }
+            _ => parse_error!("invalid teardown"),
+        }
+    }
+}
+
+/// Parses a gRPC status code from either its number or its snake-case name.
+pub(crate) fn parse_code(s: &str) -> Result<tonic::Code, TracedError<ParseError>> {
+    use tonic::Code;
+
+    if let Ok(code) = s.parse::<i32>() {
+        return match Code::from_i32(code) {
+            Code::Unknown if code != Code::Unknown as i32 => parse_error!("unknown status code"),
+            code => Ok(code),
+        };
+    }
+
+    let code = match s {
+        "ok" => Code::Ok,
+        "cancelled" => Code::Cancelled,
+        "unknown" => Code::Unknown,
+        "invalid_argument" => Code::InvalidArgument,
+        "deadline_exceeded" => Code::DeadlineExceeded,
+        "not_found" => Code::NotFound,
+        "already_exists" => Code::AlreadyExists,
+        "permission_denied" => Code::PermissionDenied,
+        "resource_exhausted" => Code::ResourceExhausted,
+        "failed_precondition" => Code::FailedPrecondition,
+        "aborted" => Code::Aborted,
+        "out_of_range" => Code::OutOfRange,
+        "unimplemented" => Code::Unimplemented,
+        "internal" => Code::Internal,
+        "unavailable" => Code::Unavailable,
+        "data_loss" => Code::DataLoss,
+        "unauthenticated" => Code::Unauthenticated,
+        _ => parse_error!("unknown status code"),
+    };
+    Ok(code)
+}
+
+/// Parses a duration with a `ms`, `s`, or `m` unit suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, TracedError<ParseError>> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => parse_error!("duration has no unit"),
+    };
+    let value = match value.parse::<u64>() {
+        Ok(value) => value,
+        Err(_) => parse_error!("invalid duration"),
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => match value.checked_mul(60) {
+            Some(secs) => Ok(Duration::from_secs(secs)),
+            None => parse_error!("duration is too long"),
+        },
+        _ => parse_error!("invalid duration unit"),
+    }
+}
+
+/// Deserializes a list of gRPC status codes in the format accepted by
+/// `parse_code`.
+pub(crate) fn deserialize_codes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<tonic::Code>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| parse_code(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Deserializes an optional duration in the format accepted by
+/// `parse_duration`.
+pub(crate) fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_duration(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_networks() {
+        let cidr = "10.1.2.3/16".parse::<Cidr>().unwrap();
+        assert_eq!(cidr.addr(), "10.1.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(cidr.prefix_len(), 16);
+
+        let cidr = "fd00::1/64".parse::<Cidr>().unwrap();
+        assert_eq!(cidr.addr(), "fd00::".parse::<IpAddr>().unwrap());
+        assert_eq!(cidr.prefix_len(), 64);
+
+        let cidr = "10.1.2.3/0".parse::<Cidr>().unwrap();
+        assert_eq!(cidr.addr(), "0.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            "10.1.2.3/32".parse::<Cidr>().unwrap().addr(),
+            "10.1.2.3".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_networks() {
+        for cidr in &["10.0.0.0", "10.0.0/8", "10.0.0.0/33", "fd00::/129", "foo/8"] {
+            assert!(cidr.parse::<Cidr>().is_err(), "{}", cidr);
+        }
+    }
+
+    #[test]
+    fn parses_generators() {
+        let generator =
+            "gen(cidr=10.0.0.0/16,port=8080,count=5000,weight=uniform(1, 100),h2=0.5,seed=1)"
+                .parse::<EndpointsGenerator>()
+                .unwrap();
+        assert_eq!(
+            generator,
+            EndpointsGenerator::new("10.0.0.0/16".parse().unwrap(), 8080, 5000)
+                .weight(Weight::Uniform(1, 100))
+                .h2(0.5)
+                .seed(1)
+        );
+
+        let generator = "gen(count=10,port=80,cidr=10.0.0.0/24,weight=5)"
+            .parse::<EndpointsGenerator>()
+            .unwrap();
+        assert_eq!(
+            generator,
+            EndpointsGenerator::new("10.0.0.0/24".parse().unwrap(), 80, 10)
+                .weight(Weight::Fixed(5))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_generators() {
+        for generator in &[
+            "generate(cidr=10.0.0.0/16,port=80,count=1)",
+            "gen(cidr=10.0.0.0/16,port=80)",
+            "gen(cidr=10.0.0.0/16,port=80,count=1,h2=2)",
+            "gen(cidr=10.0.0.0/16,port=80,count=1,weight=uniform(10,1))",
+            "gen(cidr=10.0.0.0/16,port=80,count=1,color=red)",
+            // The network only has 255 addresses to assign.
+            "gen(cidr=10.0.0.0/24,port=80,count=256)",
+        ] {
+            assert!(
+                generator.parse::<EndpointsGenerator>().is_err(),
+                "{}",
+                generator
+            );
+        }
+    }
+
+    #[test]
+    fn generates_endpoints_from_specs() {
+        let spec = "foo.ns.svc.cluster.local:8080=gen(cidr=10.0.0.0/16,port=8080,count=100,seed=3)"
+            .parse::<EndpointsSpec>()
+            .unwrap();
+        let dst = "foo.ns.svc.cluster.local:8080".parse::<Dst>().unwrap();
+        assert_eq!(spec.dsts[&dst].0.len(), 100);
+    }
+
+    #[test]
+    fn parses_churn() {
+        let churn = "churn(cidr=10.0.0.0/16,port=8080,add=10,remove=2.5,reweight=50,weight=uniform(1,100),h2=0.5,seed=1)"
+            .parse::<Churn>()
+            .unwrap();
+        assert_eq!(
+            churn,
+            Churn::new("10.0.0.0/16".parse().unwrap(), 8080)
+                .add(10.0)
+                .remove(2.5)
+                .reweight(50.0)
+                .weight(Weight::Uniform(1, 100))
+                .h2(0.5)
+                .seed(1)
+        );
+        assert_eq!(
+            "churn(port=80,cidr=10.0.0.0/8)".parse::<Churn>().unwrap(),
+            Churn::new("10.0.0.0/8".parse().unwrap(), 80)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_churn() {
+        for churn in &[
+            "gen(cidr=10.0.0.0/16,port=80)",
+            "churn(cidr=10.0.0.0/16)",
+            "churn(cidr=10.0.0.0/16,port=80,add=-1)",
+            "churn(cidr=10.0.0.0/16,port=80,add=inf)",
+            "churn(cidr=10.0.0.0/16,port=80,h2=1.5)",
+            "churn(cidr=10.0.0.0/16,port=80,count=1)",
+        ] {
+            assert!(churn.parse::<Churn>().is_err(), "{}", churn);
+        }
+    }
+
+    #[test]
+    fn parses_churn_specs() {
+        let spec = "foo.ns.svc.cluster.local:8080=churn(cidr=10.0.0.0/16,port=8080,add=1);bar.ns.svc.cluster.local:80=churn(cidr=10.1.0.0/16,port=80,remove=1)"
+            .parse::<ChurnSpec>()
+            .unwrap();
+        assert_eq!(spec.dsts.len(), 2);
+        assert!("".parse::<ChurnSpec>().unwrap().is_empty());
+        assert!("foo.ns.svc.cluster.local:8080"
+            .parse::<ChurnSpec>()
+            .is_err());
+    }
+
+    #[test]
+    fn parses_rollouts() {
+        let rollout = "rollout(from=web-v1.ns.svc.cluster.local:80,to=web-v2.ns.svc.cluster.local:80,step=25%,interval=2s,pause=50,pause=75%)"
+            .parse::<Rollout>()
+            .unwrap();
+        assert_eq!(
+            rollout,
+            Rollout::new(
+                "web-v1.ns.svc.cluster.local:80".parse().unwrap(),
+                "web-v2.ns.svc.cluster.local:80".parse().unwrap(),
+            )
+            .step(25)
+            .interval(Duration::from_secs(2))
+            .pause_at(50)
+            .pause_at(75)
+        );
+        assert_eq!(
+            "rollout(to=b:80,from=a:80)".parse::<Rollout>().unwrap(),
+            Rollout::new("a:80".parse().unwrap(), "b:80".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rollouts() {
+        for rollout in &[
+            "churn(from=a:80,to=b:80)",
+            "rollout(from=a:80)",
+            "rollout(from=a,to=b:80)",
+            "rollout(from=a:80,to=b:80,step=0)",
+            "rollout(from=a:80,to=b:80,step=101)",
+            "rollout(from=a:80,to=b:80,pause=0%)",
+            "rollout(from=a:80,to=b:80,interval=soon)",
+            "rollout(from=a:80,to=b:80,weight=1)",
+        ] {
+            assert!(rollout.parse::<Rollout>().is_err(), "{}", rollout);
+        }
+    }
+
+    #[test]
+    fn parses_destination_patterns() {
+        let matches = |pattern: &str, dst: &str| {
+            pattern
+                .parse::<DstPattern>()
+                .unwrap()
+                .matches(&dst.parse().unwrap())
+        };
+        assert!(matches(
+            "*.ns.svc.cluster.local:8080",
+            "web.ns.svc.cluster.local:8080"
+        ));
+        assert!(!matches(
+            "*.ns.svc.cluster.local:8080",
+            "web.ns.svc.cluster.local:80"
+        ));
+        assert!(matches("web:*", "web:1"));
+        assert!(matches("web:8000-8100", "web:8050"));
+        assert!(!matches("web:8000-8100", "web:8101"));
+        assert!(matches(r"regex(web-[0-9]+\.ns):80", "web-3.ns:80"));
+        assert!(!matches(r"regex(web-[0-9]+\.ns):80", "web-x.ns:80"));
+        // Only the last colon separates the ports.
+        assert!(matches("regex(a(?::b)?):80", "a:80"));
+    }
+
+    #[test]
+    fn rejects_invalid_destination_patterns() {
+        for pattern in &[
+            "web",
+            "web:http",
+            "web:8100-8000",
+            "web:8000-",
+            "web:70000",
+            "regex(web-():80",
+        ] {
+            assert!(pattern.parse::<DstPattern>().is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn parses_patterns_specs() {
+        let spec = "*.ns.svc.cluster.local:8080=10.0.0.1:{port};web:*=gen(cidr=10.0.0.0/24,port={port},count=2)"
+            .parse::<PatternsSpec>()
+            .unwrap();
+        assert_eq!(spec.patterns.len(), 2);
+        assert!("".parse::<PatternsSpec>().unwrap().is_empty());
+        assert!("web:*=10.0.0.1".parse::<PatternsSpec>().is_err());
+        assert!("web:*=bogus:{port}".parse::<PatternsSpec>().is_err());
+    }
+}
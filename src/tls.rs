@@ -0,0 +1,98 @@
+use crate::Error;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// TLS settings for a controller listener.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    client_trust_anchor: Option<PathBuf>,
+}
+
+/// The identity of a client, taken from the first DNS name in the
+/// subjectAltName of its verified certificate.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientIdentity(String);
+
+// === impl TlsConfig ===
+
+impl TlsConfig {
+    /// Configures the listener to terminate TLS with the PEM-encoded
+    /// certificate chain and private key at the given paths.
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        Self {
+            cert,
+            key,
+            client_trust_anchor: None,
+        }
+    }
+
+    /// Requires clients to present a certificate issued by the PEM-encoded
+    /// trust anchor at the given path.
+    pub fn client_trust_anchor(self, client_trust_anchor: Option<PathBuf>) -> Self {
+        Self {
+            client_trust_anchor,
+            ..self
+        }
+    }
+
+    pub(crate) fn server_config(&self) -> Result<ServerTlsConfig, Error> {
+        let cert = read(&self.cert)?;
+        let key = read(&self.key)?;
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(ref trust_anchor) = self.client_trust_anchor {
+            let trust_anchor = read(trust_anchor)?;
+            config = config.client_ca_root(Certificate::from_pem(trust_anchor));
+        }
+        Ok(config)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, io::Error> {
+    std::fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))
+}
+
+// === impl ClientIdentity ===
+
+impl ClientIdentity {
+    /// Returns the identity of the client that sent `req`, if the connection
+    /// was authenticated with a client certificate.
+    pub fn from_request<T>(req: &tonic::Request<T>) -> Option<Self> {
+        let certs = req.peer_certs()?;
+        let leaf = certs.first()?;
+        let name = dns_names(leaf.get_ref())?.into_iter().next()?;
+        Some(ClientIdentity(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Returns the DNS names in the subjectAltName extension of a DER-encoded
+/// X.509 certificate.
+fn dns_names(cert: &[u8]) -> Option<Vec<String>> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let san = cert.subject_alternative_name().ok()??;
+    let names = san
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .collect();
+    Some(names)
+}
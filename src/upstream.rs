@@ -0,0 +1,570 @@
+use crate::{
+    destination::{socket_addr, weighted_dsts},
+    spec::deserialize_duration,
+    Dst, Error,
+};
+use futures::{
+    prelude::*,
+    stream::{self, BoxStream},
+};
+use linkerd2_proxy_api::destination::{
+    self as pb, destination_client::DestinationClient, GetDestination,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tonic::transport::{Channel, Endpoint};
+
+/// An upstream Destination controller that lookups for destinations which are
+/// not served locally are forwarded to.
+///
+/// Responses are rewritten by the upstream's `Rewrites` before they are
+/// served.
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    client: DestinationClient<Channel>,
+    rewrites: Arc<Rewrites>,
+}
+
+/// How responses from an upstream controller are changed before they are
+/// served.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Rewrites {
+    /// The destinations whose responses are rewritten. If empty, all are.
+    #[serde(default)]
+    pub dsts: Vec<Dst>,
+    /// Endpoints with all of the metric labels in any of these sets are
+    /// dropped. Each set must have at least one label.
+    #[serde(default)]
+    pub drop_endpoints: Vec<BTreeMap<String, String>>,
+    /// Whether endpoints' TLS identities are removed.
+    #[serde(default)]
+    pub strip_tls_identities: bool,
+    /// How long each update and profile is delayed by. Messages that arrive
+    /// together are delayed one after another.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub latency: Option<Duration>,
+    #[serde(default)]
+    pub profile: ProfileRewrites,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct ProfileRewrites {
+    /// Whether all routes are removed.
+    #[serde(default)]
+    pub strip_routes: bool,
+    /// Overrides whether each route is retryable.
+    #[serde(default)]
+    pub retryable: Option<bool>,
+    /// Overrides each route's timeout.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub route_timeout: Option<Duration>,
+    /// Replaces the destination overrides, mapping each destination to its
+    /// weight.
+    #[serde(default)]
+    pub dst_overrides: Option<HashMap<Dst, u32>>,
+}
+
+type GrpcResult<T> = Result<T, tonic::Status>;
+
+// === impl Upstream ===
+
+impl Upstream {
+    /// Connects to the controller at `addr`, like `http://127.0.0.1:8086`,
+    /// forwarding its responses unchanged.
+    pub async fn connect(addr: impl Into<String>) -> Result<Self, Error> {
+        let channel = Endpoint::from_shared(addr.into())?.connect().await?;
+        Ok(Self {
+            client: DestinationClient::new(channel),
+            rewrites: Arc::new(Rewrites::default()),
+        })
+    }
+
+    pub fn with_rewrites(self, rewrites: Rewrites) -> Self {
+        Self {
+            rewrites: Arc::new(rewrites),
+            ..self
+        }
+    }
+
+    pub(crate) async fn get(
+        &self,
+        dst: &Dst,
+        req: GetDestination,
+    ) -> GrpcResult<BoxStream<'static, GrpcResult<pb::Update>>> {
+        let updates = self.client.clone().get(req).await?.into_inner();
+        let rewrites = match self.rewrites_for(dst) {
+            Some(rewrites) => rewrites,
+            None => return Ok(updates.boxed()),
+        };
+        // Removes are only forwarded for the addresses that were added.
+        let mut forwarded = HashSet::new();
+        Ok(rewrite(updates, rewrites, move |rw, update| {
+            rw.update(update, &mut forwarded)
+        }))
+    }
+
+    pub(crate) async fn get_profile(
+        &self,
+        dst: &Dst,
+        req: GetDestination,
+    ) -> GrpcResult<BoxStream<'static, GrpcResult<pb::DestinationProfile>>> {
+        let profiles = self.client.clone().get_profile(req).await?.into_inner();
+        let rewrites = match self.rewrites_for(dst) {
+            Some(rewrites) => rewrites,
+            None => return Ok(profiles.boxed()),
+        };
+        Ok(rewrite(profiles, rewrites, |rw, profile| {
+            vec![rw.profile(profile)]
+        }))
+    }
+
+    /// Returns the rewrites for `dst`, if any apply to it.
+    fn rewrites_for(&self, dst: &Dst) -> Option<Arc<Rewrites>> {
+        if self.rewrites.applies_to(dst) {
+            Some(self.rewrites.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// Rewrites each message on `stream` into the messages `f` returns for it,
+/// and delays each by the configured latency.
+fn rewrite<T: Send + 'static>(
+    stream: tonic::Streaming<T>,
+    rewrites: Arc<Rewrites>,
+    mut f: impl FnMut(&Rewrites, T) -> Vec<T> + Send + 'static,
+) -> BoxStream<'static, GrpcResult<T>> {
+    let latency = rewrites.latency;
+    stream
+        .flat_map(move |msg| {
+            let msgs = match msg {
+                Ok(msg) => f(&rewrites, msg).into_iter().map(Ok).collect(),
+                Err(status) => vec![Err(status)],
+            };
+            stream::iter(msgs)
+        })
+        .then(move |msg| async move {
+            if let Some(latency) = latency {
+                tokio::time::delay_for(latency).await;
+            }
+            msg
+        })
+        .boxed()
+}
+
+// === impl Rewrites ===
+
+impl Rewrites {
+    /// Loads rewrites from a JSON or YAML file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        let rewrites: Self = match path.extension().and_then(OsStr::to_str) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => serde_yaml::from_str(&contents)?,
+        };
+        // An empty set of labels would match, and drop, every endpoint.
+        if rewrites.drop_endpoints.iter().any(BTreeMap::is_empty) {
+            return Err("drop_endpoints label sets must not be empty".into());
+        }
+        Ok(rewrites)
+    }
+
+    /// Whether responses for `dst` are changed.
+    fn applies_to(&self, dst: &Dst) -> bool {
+        *self != Self::default() && (self.dsts.is_empty() || self.dsts.contains(dst))
+    }
+
+    /// Rewrites an update into the updates to serve for it, tracking the
+    /// addresses that have been forwarded in `forwarded`.
+    ///
+    /// Forwarded endpoints that an add changes so that they are now dropped
+    /// are removed. An add whose endpoints are all dropped is served as
+    /// `NoEndpoints` if no endpoints are left forwarded, so that the
+    /// destination still resolves.
+    fn update(
+        &self,
+        mut update: pb::Update,
+        forwarded: &mut HashSet<SocketAddr>,
+    ) -> Vec<pb::Update> {
+        match update.update {
+            Some(pb::update::Update::Add(ref mut set)) => {
+                let (dropped, addrs) = set
+                    .addrs
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|addr| self.drops(addr));
+                set.addrs = addrs;
+                let removed = dropped
+                    .into_iter()
+                    .filter_map(|addr| addr.addr)
+                    .filter(|addr| match socket_addr(addr) {
+                        Some(addr) => forwarded.remove(&addr),
+                        None => false,
+                    })
+                    .collect::<Vec<_>>();
+                if self.strip_tls_identities {
+                    for addr in set.addrs.iter_mut() {
+                        addr.tls_identity = None;
+                    }
+                }
+                forwarded.extend(
+                    set.addrs
+                        .iter()
+                        .filter_map(|addr| addr.addr.as_ref().and_then(socket_addr)),
+                );
+                if set.addrs.is_empty() && forwarded.is_empty() {
+                    return vec![pb::Update {
+                        update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints {
+                            exists: true,
+                        })),
+                    }];
+                }
+                let mut updates = Vec::with_capacity(2);
+                if !removed.is_empty() {
+                    updates.push(pb::Update {
+                        update: Some(pb::update::Update::Remove(pb::AddrSet { addrs: removed })),
+                    });
+                }
+                if !set.addrs.is_empty() {
+                    updates.push(update);
+                }
+                return updates;
+            }
+            Some(pb::update::Update::Remove(ref mut set)) => {
+                set.addrs.retain(|addr| match socket_addr(addr) {
+                    Some(addr) => forwarded.remove(&addr),
+                    None => true,
+                });
+                if set.addrs.is_empty() {
+                    return vec![];
+                }
+            }
+            Some(pb::update::Update::NoEndpoints(_)) | None => forwarded.clear(),
+        }
+        vec![update]
+    }
+
+    fn drops(&self, addr: &pb::WeightedAddr) -> bool {
+        self.drop_endpoints
+            .iter()
+            .filter(|labels| !labels.is_empty())
+            .any(|labels| {
+                labels
+                    .iter()
+                    .all(|(k, v)| addr.metric_labels.get(k) == Some(v))
+            })
+    }
+
+    fn profile(&self, mut profile: pb::DestinationProfile) -> pb::DestinationProfile {
+        let rw = &self.profile;
+        if rw.strip_routes {
+            profile.routes.clear();
+        }
+        for route in profile.routes.iter_mut() {
+            if let Some(retryable) = rw.retryable {
+                route.is_retryable = retryable;
+            }
+            if let Some(timeout) = rw.route_timeout {
+                route.timeout = Some(timeout.into());
+            }
+        }
+        if let Some(ref overrides) = rw.dst_overrides {
+            profile.dst_overrides = weighted_dsts(overrides);
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dst(dst: &str) -> Dst {
+        dst.parse().unwrap()
+    }
+
+    fn weighted_addr(addr: &str, labels: &[(&str, &str)]) -> pb::WeightedAddr {
+        let addr = addr.parse::<SocketAddr>().unwrap();
+        pb::WeightedAddr {
+            addr: Some((&addr).into()),
+            weight: 1,
+            metric_labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            tls_identity: Some(pb::TlsIdentity {
+                strategy: Some(pb::tls_identity::Strategy::DnsLikeIdentity(
+                    pb::tls_identity::DnsLikeIdentity {
+                        name: "web.ns.serviceaccount.identity.linkerd.cluster.local".into(),
+                    },
+                )),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn add(addrs: Vec<pb::WeightedAddr>) -> pb::Update {
+        pb::Update {
+            update: Some(pb::update::Update::Add(pb::WeightedAddrSet {
+                addrs,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn remove(addrs: &[&str]) -> pb::Update {
+        let addrs = addrs
+            .iter()
+            .map(|addr| (&addr.parse::<SocketAddr>().unwrap()).into())
+            .collect();
+        pb::Update {
+            update: Some(pb::update::Update::Remove(pb::AddrSet { addrs })),
+        }
+    }
+
+    fn no_endpoints(exists: bool) -> pb::Update {
+        pb::Update {
+            update: Some(pb::update::Update::NoEndpoints(pb::NoEndpoints { exists })),
+        }
+    }
+
+    /// Summarizes an update as the addresses it adds or removes.
+    fn summarize(update: &pb::Update) -> (&'static str, Vec<SocketAddr>) {
+        match update.update {
+            Some(pb::update::Update::Add(ref set)) => (
+                "add",
+                set.addrs
+                    .iter()
+                    .filter_map(|addr| addr.addr.as_ref().and_then(socket_addr))
+                    .collect(),
+            ),
+            Some(pb::update::Update::Remove(ref set)) => {
+                ("remove", set.addrs.iter().filter_map(socket_addr).collect())
+            }
+            Some(pb::update::Update::NoEndpoints(pb::NoEndpoints { exists: true })) => {
+                ("exists", vec![])
+            }
+            _ => ("missing", vec![]),
+        }
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    fn dropping(labels: &[(&str, &str)]) -> Rewrites {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Rewrites {
+            drop_endpoints: vec![labels],
+            ..Rewrites::default()
+        }
+    }
+
+    #[test]
+    fn drops_endpoints_with_all_labels_and_strips_identities() {
+        let rewrites = Rewrites {
+            strip_tls_identities: true,
+            ..dropping(&[("zone", "east"), ("pod", "web-1")])
+        };
+        let mut forwarded = HashSet::new();
+        let mut updates = rewrites.update(
+            add(vec![
+                weighted_addr("10.0.0.1:8080", &[("zone", "east"), ("pod", "web-1")]),
+                weighted_addr("10.0.0.2:8080", &[("zone", "east"), ("pod", "web-2")]),
+                weighted_addr("10.0.0.3:8080", &[]),
+            ]),
+            &mut forwarded,
+        );
+        assert_eq!(updates.len(), 1, "add must be forwarded");
+        let update = updates.remove(0);
+        assert_eq!(
+            summarize(&update),
+            ("add", addrs(&["10.0.0.2:8080", "10.0.0.3:8080"]))
+        );
+        match update.update {
+            Some(pb::update::Update::Add(ref set)) => {
+                assert!(set.addrs.iter().all(|addr| addr.tls_identity.is_none()));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            forwarded,
+            addrs(&["10.0.0.2:8080", "10.0.0.3:8080"])
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn adds_that_are_entirely_dropped_still_resolve() {
+        let rewrites = dropping(&[("zone", "east")]);
+        let mut forwarded = HashSet::new();
+        let east = || vec![weighted_addr("10.0.0.1:8080", &[("zone", "east")])];
+
+        // Nothing has been forwarded, so the destination exists but has no
+        // endpoints.
+        let updates = rewrites.update(add(east()), &mut forwarded);
+        assert_eq!(updates, vec![no_endpoints(true)]);
+
+        // Once endpoints have been forwarded, they are still served.
+        let west = weighted_addr("10.0.0.2:8080", &[("zone", "west")]);
+        assert_eq!(rewrites.update(add(vec![west]), &mut forwarded).len(), 1);
+        assert_eq!(rewrites.update(add(east()), &mut forwarded), vec![]);
+    }
+
+    #[test]
+    fn only_removes_forwarded_addresses() {
+        let rewrites = dropping(&[("zone", "east")]);
+        let mut forwarded = HashSet::new();
+        rewrites.update(
+            add(vec![
+                weighted_addr("10.0.0.1:8080", &[("zone", "east")]),
+                weighted_addr("10.0.0.2:8080", &[("zone", "west")]),
+            ]),
+            &mut forwarded,
+        );
+
+        // Dropped addresses were never added, so they are not removed.
+        let updates = rewrites.update(remove(&["10.0.0.1:8080", "10.0.0.2:8080"]), &mut forwarded);
+        assert_eq!(
+            updates.iter().map(summarize).collect::<Vec<_>>(),
+            vec![("remove", addrs(&["10.0.0.2:8080"]))]
+        );
+        assert!(forwarded.is_empty());
+        assert_eq!(
+            rewrites.update(remove(&["10.0.0.1:8080"]), &mut forwarded),
+            vec![]
+        );
+
+        // `NoEndpoints` forgets everything that was forwarded.
+        rewrites.update(
+            add(vec![weighted_addr("10.0.0.3:8080", &[])]),
+            &mut forwarded,
+        );
+        let updates = rewrites.update(no_endpoints(false), &mut forwarded);
+        assert_eq!(updates, vec![no_endpoints(false)]);
+        assert!(forwarded.is_empty());
+    }
+
+    #[test]
+    fn removes_forwarded_addresses_that_are_now_dropped() {
+        let rewrites = dropping(&[("zone", "east")]);
+        let mut forwarded = HashSet::new();
+        rewrites.update(
+            add(vec![
+                weighted_addr("10.0.0.1:8080", &[("zone", "west")]),
+                weighted_addr("10.0.0.2:8080", &[("zone", "west")]),
+            ]),
+            &mut forwarded,
+        );
+
+        // An endpoint moves into a dropped zone while another is added.
+        let updates = rewrites.update(
+            add(vec![
+                weighted_addr("10.0.0.1:8080", &[("zone", "east")]),
+                weighted_addr("10.0.0.3:8080", &[("zone", "west")]),
+            ]),
+            &mut forwarded,
+        );
+        assert_eq!(
+            updates.iter().map(summarize).collect::<Vec<_>>(),
+            vec![
+                ("remove", addrs(&["10.0.0.1:8080"])),
+                ("add", addrs(&["10.0.0.3:8080"])),
+            ]
+        );
+
+        // Only a remove is served when nothing else is added.
+        let updates = rewrites.update(
+            add(vec![weighted_addr("10.0.0.2:8080", &[("zone", "east")])]),
+            &mut forwarded,
+        );
+        assert_eq!(
+            updates.iter().map(summarize).collect::<Vec<_>>(),
+            vec![("remove", addrs(&["10.0.0.2:8080"]))]
+        );
+        assert_eq!(
+            forwarded,
+            addrs(&["10.0.0.3:8080"])
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+
+        // Once the last is dropped, the destination still resolves.
+        let updates = rewrites.update(
+            add(vec![weighted_addr("10.0.0.3:8080", &[("zone", "east")])]),
+            &mut forwarded,
+        );
+        assert_eq!(updates, vec![no_endpoints(true)]);
+        assert!(forwarded.is_empty());
+    }
+
+    #[test]
+    fn rewrites_profiles() {
+        let overrides = vec![(dst("web-v2.ns.svc.cluster.local:8080"), 1000)];
+        let rewrites = Rewrites {
+            profile: ProfileRewrites {
+                retryable: Some(true),
+                route_timeout: Some(Duration::from_secs(1)),
+                dst_overrides: Some(overrides.iter().cloned().collect()),
+                ..ProfileRewrites::default()
+            },
+            ..Rewrites::default()
+        };
+        let profile = pb::DestinationProfile {
+            routes: vec![pb::Route::default(), pb::Route::default()],
+            ..pb::DestinationProfile::default()
+        };
+
+        let rewritten = rewrites.profile(profile.clone());
+        assert_eq!(rewritten.routes.len(), 2);
+        for route in rewritten.routes.iter() {
+            assert!(route.is_retryable);
+            assert_eq!(route.timeout, Some(Duration::from_secs(1).into()));
+        }
+        assert_eq!(
+            rewritten.dst_overrides,
+            weighted_dsts(&overrides.into_iter().collect())
+        );
+
+        let stripped = Rewrites {
+            profile: ProfileRewrites {
+                strip_routes: true,
+                ..ProfileRewrites::default()
+            },
+            ..Rewrites::default()
+        };
+        assert!(stripped.profile(profile).routes.is_empty());
+    }
+
+    #[test]
+    fn rewrites_apply_to_their_destinations() {
+        let web = dst("web.ns.svc.cluster.local:8080");
+        let api = dst("api.ns.svc.cluster.local:8080");
+        assert!(!Rewrites::default().applies_to(&web));
+
+        let all = Rewrites {
+            strip_tls_identities: true,
+            ..Rewrites::default()
+        };
+        assert!(all.applies_to(&web));
+        assert!(all.applies_to(&api));
+
+        let scoped = Rewrites {
+            dsts: vec![web.clone()],
+            ..all
+        };
+        assert!(scoped.applies_to(&web));
+        assert!(!scoped.applies_to(&api));
+    }
+}
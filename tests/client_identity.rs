@@ -0,0 +1,62 @@
+mod support;
+
+use self::support::{get, tls_fixture, Error};
+use linkerd2_mock_dst::{DstService, IdentityService, Metrics, MockController, TlsConfig};
+use linkerd2_proxy_api::destination::destination_client::DestinationClient;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+const CLIENT_ID: &str = "web.default.serviceaccount.identity.linkerd.cluster.local";
+
+#[tokio::test]
+async fn records_the_client_identity_of_streams() -> Result<(), Error> {
+    let metrics = Metrics::default().with_client_ids(true);
+    let (dst, dst_svc) = DstService::empty();
+    let dst_svc = dst_svc.with_metrics(metrics.clone());
+    let tls = TlsConfig::new(tls_fixture("server.pem"), tls_fixture("server-key.pem"))
+        .client_trust_anchor(Some(tls_fixture("ca.pem")));
+    let mut controller = MockController::spawn_with_tls(
+        (dst, dst_svc.clone()),
+        IdentityService::default(),
+        Some(tls),
+    )
+    .await?;
+    controller.ready().await;
+    controller
+        .dst()
+        .send_endpoints(
+            "foo.ns.svc.cluster.local:8080".parse()?,
+            "127.0.0.1:1234".parse()?,
+        )
+        .await?;
+
+    let client_tls = ClientTlsConfig::new()
+        .domain_name("localhost")
+        .ca_certificate(Certificate::from_pem(std::fs::read(tls_fixture("ca.pem"))?))
+        .identity(Identity::from_pem(
+            std::fs::read(tls_fixture("client.pem"))?,
+            std::fs::read(tls_fixture("client-key.pem"))?,
+        ));
+    let channel = Endpoint::from_shared(format!("https://{}", controller.addr()))?
+        .tls_config(client_tls)?
+        .connect()
+        .await?;
+    let mut updates = DestinationClient::new(channel)
+        .get(get("foo.ns.svc.cluster.local:8080"))
+        .await?
+        .into_inner();
+    assert!(updates.message().await?.is_some());
+
+    let rendered = metrics.render(&dst_svc).await;
+    assert!(
+        rendered.contains(&format!(
+            "mock_dst_streams_active{{rpc=\"get\",dst=\"foo.ns.svc.cluster.local:8080\",client_id=\"{}\"}} 1",
+            CLIENT_ID
+        )),
+        "{}",
+        rendered
+    );
+
+    drop(updates);
+    controller.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+mod support;
+
+use self::support::{added, connect, get, socket_addr, Error};
+use linkerd2_mock_dst::{DstSender, MockController};
+use linkerd2_proxy_api::destination::{destination_client::DestinationClient, update};
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+use tonic::{transport::Channel, Code};
+
+const SHARED: &str = "web.ns.svc.cluster.local:8080";
+
+/// Updates destinations of its own, deleting every other one, and the shared
+/// destination.
+async fn update(mut sender: DstSender, source: u8) -> Result<(), Error> {
+    for port in 1..=50 {
+        let dst = format!("source-{}.ns.svc.cluster.local:{}", source, port);
+        sender
+            .send_endpoints(dst.parse()?, format!("10.1.{}.1:{}", source, port).parse()?)
+            .await?;
+        if port % 2 == 0 {
+            sender.delete_dst(dst.parse()?).await;
+        }
+        sender
+            .send_endpoints(SHARED.parse()?, format!("10.0.0.{}:8080", source).parse()?)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Returns whether `dst` is served, checking that it is served `addr` if so.
+async fn is_served(
+    client: &mut DestinationClient<Channel>,
+    dst: &str,
+    addr: &str,
+) -> Result<bool, Error> {
+    let mut updates = client.get(get(dst)).await?.into_inner();
+    match updates.message().await {
+        Ok(Some(update)) => match update.update {
+            Some(update::Update::Add(add)) => {
+                assert_eq!(added(&add), [addr.parse::<SocketAddr>()?], "{}", dst);
+                Ok(true)
+            }
+            update => panic!("unexpected update for {}: {:?}", dst, update),
+        },
+        Err(status) if status.code() == Code::InvalidArgument => Ok(false),
+        res => panic!("unexpected response for {}: {:?}", dst, res),
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn sources_update_destinations_at_once() -> Result<(), Error> {
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+    controller
+        .dst()
+        .send_endpoints(SHARED.parse()?, "10.0.0.1:8080".parse()?)
+        .await?;
+
+    let mut client = connect(controller.addr()).await?;
+    let mut updates = client.get(get(SHARED)).await?.into_inner();
+
+    let (a, b) = futures::join!(
+        tokio::spawn(update(controller.dst().clone(), 2)),
+        tokio::spawn(update(controller.dst().clone(), 3)),
+    );
+    a??;
+    b??;
+
+    // Neither source's deletes affect the other's destinations.
+    for source in 2..=3 {
+        for port in 1..=50 {
+            let dst = format!("source-{}.ns.svc.cluster.local:{}", source, port);
+            let addr = format!("10.1.{}.1:{}", source, port);
+            assert_eq!(is_served(&mut client, &dst, &addr).await?, port % 2 == 1);
+        }
+    }
+
+    // The stream that was opened before the sources started is still served
+    // the latest update, whichever sender sent it.
+    controller
+        .dst()
+        .send_endpoints(SHARED.parse()?, "10.0.0.4:8080".parse()?)
+        .await?;
+    let expected = Some("10.0.0.4:8080".parse::<SocketAddr>()?)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let mut served = HashSet::new();
+    while served != expected {
+        let update = tokio::time::timeout(Duration::from_secs(10), updates.message())
+            .await??
+            .ok_or("stream ended")?;
+        match update.update {
+            Some(update::Update::Add(add)) => served.extend(added(&add)),
+            Some(update::Update::Remove(remove)) => {
+                for addr in remove.addrs.iter() {
+                    served.remove(&socket_addr(addr));
+                }
+            }
+            update => panic!("unexpected update: {:?}", update),
+        }
+        assert!(
+            served.iter().all(|a| a.port() == 8080 && a.ip().is_ipv4()),
+            "{:?}",
+            served
+        );
+    }
+
+    controller.shutdown().await
+}
@@ -0,0 +1,289 @@
+mod support;
+
+use self::support::{added, connect, get, socket_addr, tls_fixture, Error};
+use futures::prelude::*;
+use hyper::{
+    header,
+    server::conn::Http,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use linkerd2_mock_dst::{K8sApi, K8sWatcher, MockController};
+use linkerd2_proxy_api::destination::update;
+use rustls::{internal::pemfile, AllowAnyAuthenticatedClient, RootCertStore, ServerConfig};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::oneshot};
+use tokio_rustls::TlsAcceptor;
+
+/// A stand-in API server with one service, whose endpoint slice changes
+/// while its watch is too old to be resumed.
+#[derive(Default)]
+struct ApiServer {
+    slice_lists: AtomicUsize,
+    /// The bearer token that requests must be authenticated with, if any.
+    token: Option<&'static str>,
+}
+
+impl ApiServer {
+    fn respond(&self, req: Request<Body>) -> Response<Body> {
+        if let Some(token) = self.token {
+            let expected = format!("Bearer {}", token);
+            let authorization = req.headers().get(header::AUTHORIZATION);
+            if authorization.and_then(|a| a.to_str().ok()) != Some(expected.as_str()) {
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        }
+        let watch = req
+            .uri()
+            .query()
+            .map_or(false, |q| q.contains("watch=true"));
+        match (req.uri().path(), watch) {
+            ("/api/v1/namespaces/emojivoto/services", false) => list(vec![json!({
+                "metadata": {"name": "web-svc", "namespace": "emojivoto"},
+                "spec": {"ports": [{"name": "http", "port": 80, "targetPort": 8080}]},
+            })]),
+            ("/apis/discovery.k8s.io/v1/namespaces/emojivoto/endpointslices", false) => {
+                let ip = match self.slice_lists.fetch_add(1, Ordering::SeqCst) {
+                    0 => "10.0.0.1",
+                    _ => "10.0.0.2",
+                };
+                list(vec![json!({
+                    "metadata": {
+                        "name": "web-svc-abcde",
+                        "namespace": "emojivoto",
+                        "labels": {"kubernetes.io/service-name": "web-svc"},
+                    },
+                    "endpoints": [
+                        {"addresses": [ip], "conditions": {"ready": true}},
+                        {"addresses": ["10.0.0.9"], "conditions": {"ready": false}},
+                    ],
+                    "ports": [{"name": "http", "port": 8080}],
+                })])
+            }
+            ("/apis/linkerd.io/v1alpha2/namespaces/emojivoto/serviceprofiles", false) => {
+                list(vec![])
+            }
+            ("/apis/discovery.k8s.io/v1/namespaces/emojivoto/endpointslices", true)
+                if self.slice_lists.load(Ordering::SeqCst) == 1 =>
+            {
+                let gone = json!({
+                    "type": "ERROR",
+                    "object": {
+                        "kind": "Status",
+                        "code": 410,
+                        "reason": "Expired",
+                        "message": "too old resource version: 1 (2)",
+                    },
+                });
+                Response::new(Body::from(format!("{}\n", gone)))
+            }
+            // Other watches see no changes, and stay open.
+            (_, true) => Response::new(Body::wrap_stream(stream::pending::<
+                Result<Vec<u8>, Infallible>,
+            >())),
+            (_, false) => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap(),
+        }
+    }
+
+    fn spawn(self) -> SocketAddr {
+        let api = Arc::new(self);
+        let make_svc = make_service_fn(move |_| {
+            let api = api.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let rsp = api.respond(req);
+                    async move { Ok::<_, Infallible>(rsp) }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    /// Serves over TLS, with `localhost`'s certificate, to clients with
+    /// certificates issued by the test trust anchor.
+    async fn spawn_tls(self) -> Result<SocketAddr, Error> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add_pem_file(&mut &include_bytes!("tls/ca.pem")[..])
+            .map_err(|()| "invalid trust anchor")?;
+        let mut config = ServerConfig::new(AllowAnyAuthenticatedClient::new(roots));
+        let certs = pemfile::certs(&mut &include_bytes!("tls/server.pem")[..])
+            .map_err(|()| "invalid certificate")?;
+        let key = pemfile::pkcs8_private_keys(&mut &include_bytes!("tls/server-key.pem")[..])
+            .map_err(|()| "invalid key")?
+            .remove(0);
+        config.set_single_cert(certs, key)?;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let mut listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let api = Arc::new(self);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let (acceptor, api) = (acceptor.clone(), api.clone());
+                tokio::spawn(async move {
+                    let tls = match acceptor.accept(tcp).await {
+                        Ok(tls) => tls,
+                        Err(_) => return,
+                    };
+                    let svc = service_fn(move |req| {
+                        let rsp = api.respond(req);
+                        async move { Ok::<_, Infallible>(rsp) }
+                    });
+                    let _ = Http::new().serve_connection(tls, svc).await;
+                });
+            }
+        });
+        Ok(addr)
+    }
+}
+
+/// Writes a kubeconfig for an API server served by `ApiServer::spawn_tls`,
+/// whose user authenticates with the test client certificate and `token`.
+fn kubeconfig(addr: SocketAddr, token: &str) -> Result<PathBuf, Error> {
+    let config = json!({
+        "current-context": "kind",
+        "clusters": [{
+            "name": "kind",
+            "cluster": {
+                "server": format!("https://{}", addr),
+                "certificate-authority": tls_fixture("ca.pem"),
+                // The server is reached by IP address.
+                "tls-server-name": "localhost",
+            },
+        }],
+        "contexts": [{
+            "name": "kind",
+            "context": {"cluster": "kind", "user": "web", "namespace": "emojivoto"},
+        }],
+        "users": [{
+            "name": "web",
+            "user": {
+                "token": token,
+                "client-certificate": tls_fixture("client.pem"),
+                "client-key": tls_fixture("client-key.pem"),
+            },
+        }],
+    });
+    let path = std::env::temp_dir().join(format!(
+        "linkerd2-mock-dst-kubeconfig-{}.yaml",
+        std::process::id()
+    ));
+    // JSON is YAML.
+    std::fs::write(&path, config.to_string())?;
+    Ok(path)
+}
+
+fn list(items: Vec<serde_json::Value>) -> Response<Body> {
+    let list = json!({"metadata": {"resourceVersion": "1"}, "items": items});
+    Response::new(Body::from(list.to_string()))
+}
+
+#[tokio::test]
+async fn lists_resources_again_when_their_watch_expires() -> Result<(), Error> {
+    let api = ApiServer::default().spawn();
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+    let addr = controller.addr();
+
+    {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let watcher = K8sWatcher::new(
+            K8sApi::new(format!("http://{}", api))?.namespace(Some("emojivoto".to_string())),
+        )
+        .with_ready(ready_tx);
+        let watch = watcher.watch(controller.dst());
+
+        let check = async move {
+            ready_rx.await?;
+            let mut updates = connect(addr)
+                .await?
+                .get(get("web-svc.emojivoto.svc.cluster.local:80"))
+                .await?
+                .into_inner();
+
+            // The service port targets the slice's named port, and the endpoint
+            // that is not ready is not served.
+            let mut served = Vec::new();
+            while served != ["10.0.0.2:8080".parse::<SocketAddr>()?] {
+                let update = updates.message().await?.ok_or("stream ended")?;
+                match update.update {
+                    Some(update::Update::Add(add)) => served.extend(added(&add)),
+                    Some(update::Update::Remove(remove)) => {
+                        let removed = remove.addrs.iter().map(socket_addr).collect::<Vec<_>>();
+                        served.retain(|a| !removed.contains(a));
+                    }
+                    Some(update::Update::NoEndpoints(_)) => served.clear(),
+                    None => {}
+                }
+                assert!(
+                    served
+                        .iter()
+                        .all(|a| a.port() == 8080 && a.ip() != IpAddr::from([10, 0, 0, 9])),
+                    "{:?}",
+                    served
+                );
+            }
+            Ok::<_, Error>(())
+        };
+
+        futures::pin_mut!(watch);
+        futures::pin_mut!(check);
+        let done = future::select(watch, check);
+        match tokio::time::timeout(Duration::from_secs(10), done).await? {
+            future::Either::Left((watched, _)) => panic!("watch ended: {:?}", watched),
+            future::Either::Right((checked, _)) => checked?,
+        }
+    }
+
+    controller.shutdown().await
+}
+
+#[tokio::test]
+async fn watches_api_servers_over_tls_with_a_kubeconfig() -> Result<(), Error> {
+    let api = ApiServer {
+        token: Some("s3cret"),
+        ..ApiServer::default()
+    }
+    .spawn_tls()
+    .await?;
+    let kubeconfig = kubeconfig(api, "s3cret")?;
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+
+    {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let watcher = K8sWatcher::new(K8sApi::from_kubeconfig(&kubeconfig)?).with_ready(ready_tx);
+        let watch = watcher.watch(controller.dst());
+
+        // Every resource is listed in the context's namespace.
+        futures::pin_mut!(watch);
+        let done = future::select(watch, ready_rx);
+        match tokio::time::timeout(Duration::from_secs(10), done).await? {
+            future::Either::Left((watched, _)) => panic!("watch ended: {:?}", watched),
+            future::Either::Right((ready, _)) => ready?,
+        }
+    }
+
+    std::fs::remove_file(&kubeconfig)?;
+    controller.shutdown().await
+}
@@ -0,0 +1,70 @@
+mod support;
+
+use self::support::{added, connect, get, Error};
+use linkerd2_mock_dst::{DstService, IdentityService, MockController, PatternsSpec};
+use linkerd2_proxy_api::destination::{destination_client::DestinationClient, update};
+use std::net::SocketAddr;
+use tonic::transport::Channel;
+
+/// Returns the addresses that are first served for `dst`.
+async fn resolve(client: &mut DestinationClient<Channel>, dst: &str) -> Result<Vec<String>, Error> {
+    let mut updates = client.get(get(dst)).await?.into_inner();
+    let update = updates.message().await?.ok_or("stream ended")?;
+    let mut addrs = match update.update {
+        Some(update::Update::Add(add)) => added(&add)
+            .iter()
+            .map(SocketAddr::to_string)
+            .collect::<Vec<_>>(),
+        update => panic!("unexpected update: {:?}", update),
+    };
+    addrs.sort();
+    Ok(addrs)
+}
+
+#[tokio::test]
+async fn serves_templated_endpoints_for_matching_destinations() -> Result<(), Error> {
+    let patterns = "*.ns.svc.cluster.local:8000-8100=10.0.0.1:{port},10.0.0.2:{port};\
+                    regex(web-[0-9]+\\.ns\\.svc\\.cluster\\.local):*=10.0.1.1:{port}"
+        .parse::<PatternsSpec>()?;
+    let (dst, dst_svc) = DstService::empty();
+    let mut controller = MockController::spawn_with(
+        (dst, dst_svc.with_patterns(patterns)),
+        IdentityService::default(),
+    )
+    .await?;
+    controller.ready().await;
+    controller
+        .dst()
+        .send_endpoints(
+            "api.ns.svc.cluster.local:8080".parse()?,
+            "10.0.2.1:8080".parse()?,
+        )
+        .await?;
+
+    let mut client = connect(controller.addr()).await?;
+    // Each matching destination is served on its own port.
+    assert_eq!(
+        resolve(&mut client, "web.ns.svc.cluster.local:8000").await?,
+        ["10.0.0.1:8000", "10.0.0.2:8000"]
+    );
+    assert_eq!(
+        resolve(&mut client, "emoji.ns.svc.cluster.local:8100").await?,
+        ["10.0.0.1:8100", "10.0.0.2:8100"]
+    );
+    // Patterns are tried in order.
+    assert_eq!(
+        resolve(&mut client, "web-1.ns.svc.cluster.local:8050").await?,
+        ["10.0.0.1:8050", "10.0.0.2:8050"]
+    );
+    assert_eq!(
+        resolve(&mut client, "web-1.ns.svc.cluster.local:9000").await?,
+        ["10.0.1.1:9000"]
+    );
+    // Destinations that are configured exactly take precedence.
+    assert_eq!(
+        resolve(&mut client, "api.ns.svc.cluster.local:8080").await?,
+        ["10.0.2.1:8080"]
+    );
+
+    controller.shutdown().await
+}
@@ -0,0 +1,121 @@
+mod support;
+
+use self::support::{added, connect, get, Error};
+use linkerd2_mock_dst::{MockController, Overrides, Teardown};
+use linkerd2_proxy_api::destination::update;
+use std::{net::SocketAddr, time::Duration};
+use tonic::Code;
+
+const WEB: &str = "web.ns.svc.cluster.local:8080";
+const API: &str = "api.ns.svc.cluster.local:8080";
+const OUTAGE: Duration = Duration::from_millis(500);
+
+/// Returns the addresses first served for `dst` on a new connection.
+async fn resolve(addr: SocketAddr, dst: &str) -> Result<Vec<SocketAddr>, Error> {
+    let mut updates = connect(addr).await?.get(get(dst)).await?.into_inner();
+    match updates.message().await?.and_then(|u| u.update) {
+        Some(update::Update::Add(add)) => Ok(added(&add)),
+        update => panic!("unexpected update: {:?}", update),
+    }
+}
+
+async fn spawn() -> Result<MockController, Error> {
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+    for (dst, addr) in &[(WEB, "10.0.0.1:8080"), (API, "10.0.1.1:8080")] {
+        controller
+            .dst()
+            .send_endpoints(dst.parse()?, addr.parse()?)
+            .await?;
+    }
+    let overrides = Overrides::new(Some((WEB.parse()?, 1000)).into_iter().collect());
+    controller
+        .dst()
+        .send_overrides(WEB.parse()?, overrides)
+        .await?;
+    Ok(controller)
+}
+
+#[tokio::test]
+async fn restarts_end_every_stream_with_a_status() -> Result<(), Error> {
+    let controller = spawn().await?;
+    let addr = controller.addr();
+
+    // Streams on separate connections are all torn down.
+    let mut web = connect(addr).await?;
+    let mut api = connect(addr).await?;
+    let mut streams = vec![
+        web.get(get(WEB)).await?.into_inner(),
+        api.get(get(API)).await?.into_inner(),
+    ];
+    let mut profiles = web.get_profile(get(WEB)).await?.into_inner();
+    for updates in streams.iter_mut() {
+        updates.message().await?.ok_or("endpoints must be served")?;
+    }
+    profiles.message().await?.ok_or("profile must be served")?;
+
+    controller.restart(
+        Teardown::Status(Code::Unavailable, "restarting".to_string()),
+        OUTAGE,
+    );
+    for updates in streams.iter_mut() {
+        let status = updates.message().await.expect_err("Get must fail");
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "restarting");
+    }
+    let status = profiles.message().await.expect_err("GetProfile must fail");
+    assert_eq!(status.code(), Code::Unavailable);
+
+    // New streams are rejected until the outage ends, and then are served
+    // the endpoints that were served before the restart.
+    match web.get(get(WEB)).await {
+        Err(status) => assert_eq!(status.code(), Code::Unavailable),
+        Ok(_) => panic!("Get must be rejected during the outage"),
+    }
+    tokio::time::delay_for(OUTAGE * 2).await;
+    assert_eq!(
+        resolve(addr, WEB).await?,
+        ["10.0.0.1:8080".parse::<SocketAddr>()?]
+    );
+    let mut updates = web.get(get(API)).await?.into_inner();
+    updates.message().await?.ok_or("endpoints must be served")?;
+
+    controller.shutdown().await
+}
+
+#[tokio::test]
+async fn restarts_reset_every_connection() -> Result<(), Error> {
+    let controller = spawn().await?;
+    let addr = controller.addr();
+
+    let mut web = connect(addr).await?;
+    let mut api = connect(addr).await?;
+    let mut streams = vec![
+        web.get(get(WEB)).await?.into_inner(),
+        api.get(get(API)).await?.into_inner(),
+    ];
+    for updates in streams.iter_mut() {
+        updates.message().await?.ok_or("endpoints must be served")?;
+    }
+
+    controller.restart(Teardown::Reset, OUTAGE);
+    for updates in streams.iter_mut() {
+        updates
+            .message()
+            .await
+            .expect_err("stream must fail when its connection is reset");
+    }
+
+    // Connections accepted during the outage are reset too.
+    assert!(
+        resolve(addr, WEB).await.is_err(),
+        "lookups must fail during the outage"
+    );
+    tokio::time::delay_for(OUTAGE * 2).await;
+    assert_eq!(
+        resolve(addr, API).await?,
+        ["10.0.1.1:8080".parse::<SocketAddr>()?]
+    );
+
+    controller.shutdown().await
+}
@@ -0,0 +1,52 @@
+mod support;
+
+use self::support::{connect, get, Error};
+use linkerd2_mock_dst::{MockController, Overrides};
+use linkerd2_proxy_api::destination::update;
+use std::time::Duration;
+
+const WEB: &str = "web.ns.svc.cluster.local:8080";
+
+#[tokio::test]
+async fn shutdown_ends_open_streams_cleanly() -> Result<(), Error> {
+    let mut controller = MockController::spawn().await?;
+    controller.ready().await;
+    controller
+        .dst()
+        .send_endpoints(WEB.parse()?, "10.0.0.1:8080".parse()?)
+        .await?;
+    let overrides = Overrides::new(Some((WEB.parse()?, 1000)).into_iter().collect());
+    controller
+        .dst()
+        .send_overrides(WEB.parse()?, overrides)
+        .await?;
+    let addr = controller.addr();
+
+    let mut client = connect(addr).await?;
+    let mut updates = client.get(get(WEB)).await?.into_inner();
+    let mut profiles = client.get_profile(get(WEB)).await?.into_inner();
+    match updates.message().await?.and_then(|u| u.update) {
+        Some(update::Update::Add(_)) => {}
+        update => panic!("unexpected update: {:?}", update),
+    }
+    profiles.message().await?.ok_or("profile must be served")?;
+
+    let shutdown = tokio::spawn(controller.shutdown());
+
+    // `Get` streams are told that the destination no longer exists, and then
+    // every stream ends with an OK status rather than an error.
+    match updates.message().await?.and_then(|u| u.update) {
+        Some(update::Update::NoEndpoints(no_endpoints)) => assert!(!no_endpoints.exists),
+        update => panic!("unexpected update: {:?}", update),
+    }
+    assert!(updates.message().await?.is_none(), "Get must end");
+    assert!(profiles.message().await?.is_none(), "GetProfile must end");
+
+    // The client's connection is closed within the shutdown timeout.
+    tokio::time::timeout(Duration::from_secs(5), shutdown).await???;
+    assert!(
+        connect(addr).await.is_err(),
+        "new connections must be refused"
+    );
+    Ok(())
+}
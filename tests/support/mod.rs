@@ -0,0 +1,53 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use linkerd2_proxy_api::{
+    destination::{destination_client::DestinationClient, GetDestination, WeightedAddrSet},
+    net::{ip_address::Ip, TcpAddress},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+use tonic::transport::Channel;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Returns a lookup of `dst`, as a proxy sends it.
+pub fn get(dst: &str) -> GetDestination {
+    GetDestination {
+        scheme: "k8s".to_string(),
+        path: dst.to_string(),
+        context_token: String::new(),
+    }
+}
+
+/// Connects a client to the controller listening in plaintext on `addr`.
+pub async fn connect(addr: SocketAddr) -> Result<DestinationClient<Channel>, Error> {
+    let client = DestinationClient::connect(format!("http://{}", addr)).await?;
+    Ok(client)
+}
+
+/// Returns the addresses of the endpoints in an add.
+pub fn added(add: &WeightedAddrSet) -> Vec<SocketAddr> {
+    add.addrs
+        .iter()
+        .filter_map(|a| a.addr.as_ref())
+        .map(socket_addr)
+        .collect()
+}
+
+pub fn socket_addr(addr: &TcpAddress) -> SocketAddr {
+    match addr.ip.as_ref().and_then(|ip| ip.ip.as_ref()) {
+        Some(Ip::Ipv4(ip)) => SocketAddr::new(IpAddr::from(ip.to_be_bytes()), addr.port as u16),
+        ip => panic!("unexpected address: {:?}", ip),
+    }
+}
+
+/// Returns the path of a certificate or key in `tests/tls`.
+pub fn tls_fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("tls")
+        .join(name)
+}